@@ -1,17 +1,34 @@
 use crate::index_arena::{Handle, Many};
 use crate::ir::Var;
-use crate::lexer::SourceSpan;
+use crate::lexer::{NumberSuffix, Span};
 use crate::types::TypeRef;
 
 #[derive(Debug, Clone)]
 pub(crate) enum Type {
     Name(String),
+    /// Generic type given its type arguments, eg. `Pair<i32, u32>`
+    Generic(String, Vec<Type>),
     Tuple(Vec<Type>),
     Pointer(Box<Type>),
     Array(usize, Box<Type>),
+    /// Array with the length given by the named constant, eg. `[N]i32`
+    ConstArray(String, Box<Type>),
     Slice(Box<Type>),
     Unit,
     Function(Vec<Type>, Box<Type>),
+    Range(Box<Type>),
+}
+
+/// Annotation of a function or a struct, eg. `#[deprecated("use g")]`
+#[derive(Debug, Clone)]
+pub(crate) struct Attribute {
+    pub name: String,
+    pub args: Vec<String>,
+}
+
+/// Returns the attribute with the name, if there is one
+pub(crate) fn attribute<'a>(attributes: &'a [Attribute], name: &str) -> Option<&'a Attribute> {
+    attributes.iter().find(|it| it.name == name)
 }
 
 #[derive(Debug)]
@@ -26,12 +43,52 @@ pub(crate) struct Field {
     pub r#type: Type,
 }
 
+#[derive(Debug)]
+pub(crate) struct Variant {
+    pub name: String,
+    pub types: Vec<Type>,
+}
+
+#[derive(Debug)]
+pub(crate) enum Pattern {
+    Wildcard,
+    /// Variant of an enum, binding its payload to the names
+    Variant {
+        r#type: String,
+        name: String,
+        bindings: Vec<String>,
+    },
+}
+
+/// Pattern taking apart a tuple or a struct, `_` binds no name
+#[derive(Debug)]
+pub(crate) enum Destructuring {
+    Tuple(Vec<String>),
+    /// Fields of the struct along with the names bound to them
+    Struct {
+        r#type: String,
+        fields: Vec<(String, String)>,
+    },
+}
+
+#[derive(Debug)]
+pub(crate) struct MatchArm {
+    pub pattern: Pattern,
+    pub body: Vec<Item>,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub(crate) enum Operator {
     Add,
     Sub,
     Mul,
     Div,
+    Rem,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
     Equal,
     NotEqual,
     Less,
@@ -40,6 +97,7 @@ pub(crate) enum Operator {
     GreaterEqual,
     And,
     Or,
+    Not,
     Negate,
     Ref,
     Deref,
@@ -49,8 +107,10 @@ pub(crate) enum Operator {
 #[derive(Debug)]
 pub(crate) enum Expr {
     Identifier(String),
-    Integer(i64),
-    Float(f64),
+    /// Name within the namespace of a type, eg. variant of an enum
+    Path(String, String),
+    Integer(i64, Option<NumberSuffix>),
+    Float(f64, Option<NumberSuffix>),
     Bool(bool),
     Prefix(Operator, Handle<Expr>),
     Infix(Operator, Handle<Expr>, Handle<Expr>),
@@ -58,11 +118,42 @@ pub(crate) enum Expr {
     Array(Handle<Expr, Many>),
     Tuple(Handle<Expr, Many>),
     Call(Handle<Expr>, Handle<Expr, Many>),
-    Range(Handle<Expr>, Option<Handle<Expr>>),
+    /// Items from the start up to the end, `range n` starts at zero
+    Range {
+        start: Option<Handle<Expr>>,
+        end: Handle<Expr>,
+        step: Option<Handle<Expr>>,
+        inclusive: bool,
+    },
     Index(Handle<Expr>, Handle<Expr>),
+    /// Elements of an array or a slice from the start up to the end, eg. `items[1..n]`
+    Slice {
+        base: Handle<Expr>,
+        start: Option<Handle<Expr>>,
+        end: Option<Handle<Expr>>,
+    },
     Cast(Handle<Expr>, Type),
     Var(Var),
     StructLiteral(Option<String>, Vec<(String, Handle<Expr>)>),
+    If {
+        condition: Handle<Expr>,
+        arm_true: Vec<Item>,
+        arm_false: Option<Vec<Item>>,
+    },
+    Block(Vec<Item>),
+    Loop {
+        label: Option<String>,
+        body: Vec<Item>,
+    },
+    Match {
+        scrutinee: Handle<Expr>,
+        arms: Vec<MatchArm>,
+    },
+    Closure {
+        params: Vec<Argument>,
+        ty: Option<Type>,
+        body: Handle<Expr>,
+    },
 }
 
 impl Expr {
@@ -75,12 +166,24 @@ impl Expr {
 }
 
 #[derive(Debug)]
-pub(crate) enum Item {
+pub(crate) struct Item {
+    pub kind: ItemKind,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub(crate) enum ItemKind {
     Let {
         name: String,
         r#type: Option<Type>,
         expr: Option<Handle<Expr>>,
     },
+    /// Let binding taking apart the value, eg. `let (a, b) = pair;`
+    LetPattern {
+        pattern: Destructuring,
+        r#type: Option<Type>,
+        expr: Handle<Expr>,
+    },
     Assignment {
         lhs: Handle<Expr>,
         operator: Option<Operator>,
@@ -89,8 +192,14 @@ pub(crate) enum Item {
     Expr {
         expr: Handle<Expr>,
     },
+    /// Expression ending a block without `;`, its value is the value of the block
+    Tail {
+        expr: Handle<Expr>,
+    },
     Function {
         name: String,
+        attributes: Vec<Attribute>,
+        type_params: Vec<String>,
         is_extern: bool,
         params: Vec<Argument>,
         ty: Type,
@@ -98,24 +207,66 @@ pub(crate) enum Item {
     },
     Struct {
         name: String,
+        attributes: Vec<Attribute>,
+        type_params: Vec<String>,
         fields: Vec<Field>,
     },
+    Enum {
+        name: String,
+        variants: Vec<Variant>,
+    },
+    /// Value computed while type checking, uses of the constant are replaced with it
+    Const {
+        name: String,
+        r#type: Type,
+        expr: Handle<Expr>,
+    },
+    /// Global variable shared by all function calls
+    Static {
+        name: String,
+        mutable: bool,
+        r#type: Type,
+        expr: Handle<Expr>,
+    },
+    /// Functions of the file at the path, relative to the importing file, named by its stem
+    Import {
+        path: String,
+    },
+    /// Methods of the named type
+    Impl {
+        name: String,
+        functions: Vec<Item>,
+    },
     If {
         condition: Handle<Expr>,
         arm_true: Vec<Item>,
         arm_false: Option<Vec<Item>>,
     },
     ForIn {
+        label: Option<String>,
         name: String,
         expr: Handle<Expr>,
         body: Vec<Item>,
     },
     Loop {
+        label: Option<String>,
         body: Vec<Item>,
     },
-    Break,
+    While {
+        label: Option<String>,
+        condition: Handle<Expr>,
+        body: Vec<Item>,
+    },
+    Break {
+        label: Option<String>,
+        value: Option<Handle<Expr>>,
+    },
+    Continue {
+        label: Option<String>,
+    },
     Yield(Box<Handle<Expr>>),
-    Return(Box<Handle<Expr>>),
+    /// Value is left out when returning from a function giving no value or from a generator
+    Return(Option<Box<Handle<Expr>>>),
     Block(Vec<Item>),
     Assert(Box<Handle<Expr>>),
 }
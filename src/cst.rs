@@ -0,0 +1,320 @@
+use std::fmt;
+
+use crate::lexer::{SourceSpan, Token, TokenType};
+
+/// Kind of a node in the concrete syntax tree
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NodeKind {
+    SourceFile,
+    Import,
+    Const,
+    Static,
+    Function,
+    /// Annotation of the function or the struct it is within, eg. `#[deprecated("use g")]`
+    Attribute,
+    TypeParams,
+    ParamList,
+    Param,
+    Struct,
+    Field,
+    Impl,
+    Enum,
+    Variant,
+    Block,
+    Let,
+    Assignment,
+    ExprStmt,
+    If,
+    Match,
+    MatchArm,
+    WildcardPattern,
+    VariantPattern,
+    /// Names bound to the fields of a tuple, eg. `(a, _)`
+    TuplePattern,
+    /// Names bound to the fields of a struct, eg. `Point { x, y: py }`
+    StructPattern,
+    FieldPattern,
+    ForIn,
+    Loop,
+    While,
+    Break,
+    Continue,
+    Yield,
+    Return,
+    Assert,
+    NameType,
+    PointerType,
+    ArrayType,
+    FnType,
+    TupleType,
+    /// Range with items of the type, eg. `range<i32>`
+    RangeType,
+    Literal,
+    NameRef,
+    PathExpr,
+    PrefixExpr,
+    BinaryExpr,
+    ParenExpr,
+    TupleExpr,
+    ArrayExpr,
+    RangeExpr,
+    FieldExpr,
+    CallExpr,
+    ArgList,
+    IndexExpr,
+    /// Bounds of a slice within an index expression, eg. `1..n` in `items[1..n]`
+    SliceBounds,
+    CastExpr,
+    StructLiteral,
+    FieldInit,
+    /// Function literal capturing variables from its environment, eg. `|x: i32| x * k`
+    Closure,
+    /// Tokens skipped while recovering from a syntax error, along with the incomplete nodes
+    Error,
+}
+
+impl NodeKind {
+    /// Checks whether node of this kind is an expression
+    pub fn is_expr(self) -> bool {
+        match self {
+            NodeKind::Literal
+            | NodeKind::NameRef
+            | NodeKind::PathExpr
+            | NodeKind::Closure
+            | NodeKind::PrefixExpr
+            | NodeKind::BinaryExpr
+            | NodeKind::ParenExpr
+            | NodeKind::TupleExpr
+            | NodeKind::ArrayExpr
+            | NodeKind::RangeExpr
+            | NodeKind::FieldExpr
+            | NodeKind::CallExpr
+            | NodeKind::IndexExpr
+            | NodeKind::CastExpr
+            | NodeKind::StructLiteral
+            | NodeKind::If
+            | NodeKind::Match
+            | NodeKind::Loop
+            | NodeKind::Block => true,
+            _ => false,
+        }
+    }
+
+    /// Checks whether node of this kind is a type
+    pub fn is_type(self) -> bool {
+        match self {
+            NodeKind::NameType
+            | NodeKind::PointerType
+            | NodeKind::ArrayType
+            | NodeKind::FnType
+            | NodeKind::TupleType
+            | NodeKind::RangeType => true,
+            _ => false,
+        }
+    }
+}
+
+/// Child of a node, either a nested node or a token
+#[derive(Clone, Debug)]
+pub enum Element<'a> {
+    Node(Node<'a>),
+    Token(Token<'a>),
+}
+
+/// Node of the concrete syntax tree
+///
+/// Tree keeps every token of the source along with the whitespace and comments preceding it,
+/// so printing the root node gives back the original source.
+#[derive(Clone)]
+pub struct Node<'a> {
+    /// Kind of the node
+    kind: NodeKind,
+    /// Nodes and tokens the node consists of, in source order
+    children: Vec<Element<'a>>,
+}
+
+impl<'a> Node<'a> {
+    /// Returns kind of the node
+    pub fn kind(&self) -> NodeKind {
+        self.kind
+    }
+
+    /// Returns all children of the node
+    pub fn children(&self) -> &[Element<'a>] {
+        &self.children
+    }
+
+    /// Returns all child nodes
+    pub fn nodes(&self) -> impl Iterator<Item = &Node<'a>> {
+        self.children.iter().filter_map(|it| match it {
+            Element::Node(node) => Some(node),
+            Element::Token(_) => None,
+        })
+    }
+
+    /// Returns all child tokens, without tokens of nested nodes
+    pub fn tokens(&self) -> impl Iterator<Item = &Token<'a>> {
+        self.children.iter().filter_map(|it| match it {
+            Element::Node(_) => None,
+            Element::Token(token) => Some(token),
+        })
+    }
+
+    /// Returns the first child node of given kind
+    pub fn node(&self, kind: NodeKind) -> Option<&Node<'a>> {
+        self.nodes().find(|it| it.kind == kind)
+    }
+
+    /// Returns the first child token of given type
+    pub fn token(&self, token_type: TokenType) -> Option<&Token<'a>> {
+        self.tokens().find(|it| it.get_type() == token_type)
+    }
+
+    /// Returns all child nodes being expressions
+    pub fn exprs(&self) -> impl Iterator<Item = &Node<'a>> {
+        self.nodes().filter(|it| it.kind.is_expr())
+    }
+
+    /// Returns the first child node being a type
+    pub fn ty(&self) -> Option<&Node<'a>> {
+        self.nodes().find(|it| it.kind.is_type())
+    }
+
+    /// Returns the first token of the node, including tokens of nested nodes
+    pub fn first_token(&self) -> Option<&Token<'a>> {
+        self.children.iter().find_map(|it| match it {
+            Element::Node(node) => node.first_token(),
+            Element::Token(token) => Some(token),
+        })
+    }
+
+    /// Returns the last token of the node, including tokens of nested nodes
+    pub fn last_token(&self) -> Option<&Token<'a>> {
+        self.children.iter().rev().find_map(|it| match it {
+            Element::Node(node) => node.last_token(),
+            Element::Token(token) => Some(token),
+        })
+    }
+
+    /// Returns span covering all tokens of the node, without leading trivia
+    pub fn span(&self) -> Option<SourceSpan<'a>> {
+        let first = self.first_token()?.get_span();
+        let last = self.last_token()?.get_span();
+        Some(first.to(&last))
+    }
+
+    fn write_tokens(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for child in &self.children {
+            match child {
+                Element::Node(node) => node.write_tokens(f)?,
+                Element::Token(token) => {
+                    for trivia in token.trivia() {
+                        write!(f, "{}", trivia.get_span().as_slice())?;
+                    }
+                    write!(f, "{}", token.get_span().as_slice())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_tree(&self, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+        writeln!(f, "{:indent$}{:?}", "", self.kind, indent = depth * 2)?;
+        for child in &self.children {
+            match child {
+                Element::Node(node) => node.write_tree(f, depth + 1)?,
+                Element::Token(token) => {
+                    writeln!(f, "{:indent$}{:?}", "", token, indent = (depth + 1) * 2)?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Prints the source the node was built from
+impl<'a> fmt::Display for Node<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_tokens(f)
+    }
+}
+
+/// Prints structure of the tree, one node or token per line
+impl<'a> fmt::Debug for Node<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write_tree(f, 0)
+    }
+}
+
+/// Position in the builder at which a node can be started retroactively
+#[derive(Copy, Clone)]
+pub(crate) struct Checkpoint(usize);
+
+/// Builder assembling the tree from a sequence of tokens
+pub(crate) struct TreeBuilder<'a> {
+    /// Children of all nodes that are not finished yet
+    elements: Vec<Element<'a>>,
+    /// Kinds of nodes that are not finished yet along with the index of their first child
+    open: Vec<(NodeKind, usize)>,
+}
+
+impl<'a> TreeBuilder<'a> {
+    pub(crate) fn new() -> Self {
+        Self {
+            elements: vec![],
+            open: vec![],
+        }
+    }
+
+    /// Starts a new node, all subsequent elements will become its children
+    pub(crate) fn start_node(&mut self, kind: NodeKind) {
+        self.open.push((kind, self.elements.len()));
+    }
+
+    /// Returns the current position for wrapping elements added since then into a node
+    pub(crate) fn checkpoint(&self) -> Checkpoint {
+        Checkpoint(self.elements.len())
+    }
+
+    /// Starts a new node which contains all elements added since the checkpoint
+    pub(crate) fn start_node_at(&mut self, checkpoint: Checkpoint, kind: NodeKind) {
+        assert!(
+            self.open.last().is_none_or(|&(_, start)| start <= checkpoint.0),
+            "checkpoint outside of the current node"
+        );
+        self.open.push((kind, checkpoint.0));
+    }
+
+    /// Adds token to the current node
+    pub(crate) fn token(&mut self, token: Token<'a>) {
+        self.elements.push(Element::Token(token));
+    }
+
+    /// Returns number of nodes that are not finished yet
+    pub(crate) fn depth(&self) -> usize {
+        self.open.len()
+    }
+
+    /// Finishes the nodes started after the given depth was reached
+    pub(crate) fn finish_nodes_until(&mut self, depth: usize) {
+        while self.open.len() > depth {
+            self.finish_node();
+        }
+    }
+
+    /// Finishes the most recently started node
+    pub(crate) fn finish_node(&mut self) {
+        let (kind, start) = self.open.pop().expect("no node to finish");
+        let children = self.elements.split_off(start);
+        self.elements.push(Element::Node(Node { kind, children }));
+    }
+
+    /// Returns the root node, finishing all nodes that are still open
+    pub(crate) fn finish(mut self) -> Node<'a> {
+        self.finish_nodes_until(0);
+        match self.elements.pop() {
+            Some(Element::Node(node)) if self.elements.is_empty() => node,
+            _ => panic!("tree should have a single root"),
+        }
+    }
+}
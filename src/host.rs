@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+
+use crate::ir::{Bits, Const};
+use crate::types::{Type, TypeRef};
+
+/// Implementation of a host function, called with values of its arguments
+type Body = dyn Fn(&[Const]) -> Const;
+
+/// Function implemented by the host, scripts declare it with `extern fn` to call it
+pub(crate) struct HostFunction {
+    args: Vec<TypeRef<'static>>,
+    ret: TypeRef<'static>,
+    body: Box<Body>,
+}
+
+impl HostFunction {
+    /// Returns types of the arguments and the type of the result
+    pub(crate) fn signature(&self) -> (&[TypeRef<'static>], TypeRef<'static>) {
+        (&self.args, self.ret)
+    }
+
+    pub(crate) fn call(&self, args: &[Const]) -> Const {
+        (self.body)(args)
+    }
+}
+
+/// Host functions by their name
+#[derive(Default)]
+pub(crate) struct HostFunctions {
+    functions: HashMap<String, HostFunction>,
+}
+
+impl HostFunctions {
+    /// Registers the function, replacing the one registered before under the same name
+    pub(crate) fn register(
+        &mut self,
+        name: &str,
+        args: &[TypeRef<'static>],
+        ret: TypeRef<'static>,
+        body: impl Fn(&[Const]) -> Const + 'static,
+    ) {
+        let function = HostFunction {
+            args: args.to_vec(),
+            ret,
+            body: Box::new(body),
+        };
+        self.functions.insert(name.to_owned(), function);
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&HostFunction> {
+        self.functions.get(name)
+    }
+}
+
+/// Returns the functions every script can declare
+pub(crate) fn builtins() -> HostFunctions {
+    let mut host = HostFunctions::default();
+    host.register("abs", &[&Type::I32], &Type::I32, |args| match args {
+        [Const::Integer(x)] => Const::Integer(Bits::from(x.as_i32().wrapping_abs())),
+        _ => Const::Undefined,
+    });
+    host.register("sqrt", &[&Type::F32], &Type::F32, |args| match args {
+        [Const::F32(x)] => Const::F32(x.sqrt()),
+        _ => Const::Undefined,
+    });
+    host.register("pow", &[&Type::I32, &Type::U32], &Type::I32, |args| match args {
+        [Const::Integer(base), Const::Integer(exp)] => {
+            Const::Integer(Bits::from(base.as_i32().wrapping_pow(exp.as_u32())))
+        }
+        _ => Const::Undefined,
+    });
+    host
+}
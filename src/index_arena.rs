@@ -3,6 +3,8 @@ use std::convert::TryInto;
 use std::fmt::Formatter;
 use std::marker::PhantomData;
 
+use crate::lexer::Span;
+
 #[derive(Copy, Clone)]
 pub struct Single;
 
@@ -12,6 +14,8 @@ pub struct Many(u32);
 #[derive(Debug)]
 pub struct IndexArena<T> {
     values: Vec<T>,
+    /// Source location of each value, indexed the same way as values
+    spans: Vec<Option<Span>>,
 }
 
 impl<T> Default for IndexArena<T> {
@@ -24,14 +28,22 @@ impl<T> IndexArena<T> {
     fn new() -> Self {
         Self {
             values: vec![],
+            spans: vec![],
         }
     }
     pub(crate) fn alloc(&mut self, value: T) -> Handle<T, Single> {
         let idx = self.values.len().try_into().expect("too many items??");
         self.values.push(value);
+        self.spans.push(None);
         Handle(idx, PhantomData, Single)
     }
 
+    pub(crate) fn alloc_with_span(&mut self, value: T, span: Span) -> Handle<T, Single> {
+        let handle = self.alloc(value);
+        self.spans[handle.0 as usize] = Some(span);
+        handle
+    }
+
     pub(crate) fn alloc_many<I>(&mut self, values: I) -> Handle<T, Many>
         where
             I: IntoIterator<Item=T>,
@@ -41,6 +53,22 @@ impl<T> IndexArena<T> {
         let idx = self.values.len().try_into().expect("too many items??");
         let count = iter.len().try_into().expect("wtf");
         self.values.extend(iter);
+        self.spans.resize(self.values.len(), None);
+        Handle(idx, PhantomData, Many(count))
+    }
+
+    pub(crate) fn alloc_many_with_spans<I>(&mut self, values: I) -> Handle<T, Many>
+        where
+            I: IntoIterator<Item=(T, Span)>,
+            I::IntoIter: ExactSizeIterator<Item=(T, Span)>
+    {
+        let iter = values.into_iter();
+        let idx = self.values.len().try_into().expect("too many items??");
+        let count = iter.len().try_into().expect("wtf");
+        for (value, span) in iter {
+            self.values.push(value);
+            self.spans.push(Some(span));
+        }
         Handle(idx, PhantomData, Many(count))
     }
 
@@ -48,13 +76,18 @@ impl<T> IndexArena<T> {
     pub(crate) fn resolve(&self, handle: Handle<T, Single>) -> &T {
         self.values.get(handle.0 as usize).unwrap()
     }
+
+    /// Returns location in the source of the value, if it was allocated with one
+    pub(crate) fn span(&self, handle: Handle<T, Single>) -> Option<Span> {
+        self.spans.get(handle.0 as usize).copied().flatten()
+    }
 }
 
 pub struct Handle<T, Meta = Single>(u32, PhantomData<T>, Meta);
 
 impl<T, Meta: Copy + Clone> Clone for Handle<T, Meta> {
     fn clone(&self) -> Self {
-        Self(self.0, self.1, self.2)
+        *self
     }
 }
 
@@ -106,7 +139,7 @@ impl<T> IntoIterator for Handle<T, Many> {
 
 impl<T> Handle<T, Many> {
     pub(crate) fn len(&self) -> usize {
-        self.0 as _
+        self.2.0 as _
     }
     pub(crate) fn is_empty(&self) -> bool {
         self.len() == 0
@@ -116,6 +149,7 @@ impl<T> Handle<T, Many> {
 #[cfg(test)]
 mod tests {
     use crate::index_arena::IndexArena;
+    use crate::lexer::Span;
 
     #[test]
     fn foo() {
@@ -133,6 +167,23 @@ mod tests {
         assert_eq!(arena.resolve(c1), &21);
         assert_eq!(arena.resolve(c2), &37);
     }
+
+    #[test]
+    fn spans() {
+        let span = |start| Span { start, length: 1, line: 1, column: start + 1 };
+        let mut arena = IndexArena::new();
+        let a = arena.alloc(1);
+        let b = arena.alloc_with_span(2, span(3));
+        let c = arena.alloc_many_with_spans([(3, span(5)), (4, span(7))]);
+        let d = arena.alloc_many([5]);
+        let mut it = c.into_iter();
+
+        assert_eq!(arena.span(a), None);
+        assert_eq!(arena.span(b), Some(span(3)));
+        assert_eq!(arena.span(it.next().unwrap()), Some(span(5)));
+        assert_eq!(arena.span(it.next().unwrap()), Some(span(7)));
+        assert_eq!(arena.span(d.into_iter().next().unwrap()), None);
+    }
 }
 
 
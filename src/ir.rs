@@ -4,7 +4,14 @@ use std::io::Write;
 use std::{fmt, io};
 use std::fmt::{Debug, Formatter, write};
 
-use crate::type_checking::{ExprRef, ExprToType, Expression, Item};
+use indexmap::IndexMap;
+
+use crate::host::HostFunctions;
+use crate::lexer::Span;
+use crate::type_checking::{
+    bind_placeholders, substitute, type_params_count, ExprRef, ExprToType, Expression, Intrinsic, Item,
+    ItemKind,
+};
 use crate::types::{Type, TypeRef};
 use crate::{ast, Arena};
 
@@ -87,13 +94,24 @@ impl_bits_for!(i64);
 
 impl Bits {
     fn value(&self) -> i64 {
-        let mask = ((1u128 << (self.width + 1)) - 1) as u64;
-        let sign = 1u64 << (self.width - 1);
-        if self.value & mask & sign != 0 {
-            let sign_ext = u64::MAX ^ mask;
-            ((self.value & mask) | sign_ext) as _
-        } else {
-            (self.value & mask) as _
+        let unused = 64 - self.width;
+        match self.sign {
+            Signedness::Unsigned => ((self.value << unused) >> unused) as _,
+            _ => ((self.value << unused) as i64) >> unused,
+        }
+    }
+
+    /// Makes value of the same type, wrapped around to fit its width
+    fn wrapped(&self, value: i64) -> Self {
+        let unused = 64 - self.width;
+        let value = match self.sign {
+            Signedness::Unsigned => ((value as u64) << unused) >> unused,
+            _ => ((value << unused) >> unused) as u64,
+        };
+        Self {
+            value,
+            width: self.width,
+            sign: self.sign,
         }
     }
 
@@ -106,40 +124,72 @@ impl Bits {
 
     fn add(&self, other: &Self) -> Self {
         self.check_compatible(other);
-        Self {
-            value: (self.value() + other.value()) as _,
-            width: self.width,
-            sign: self.sign,
-        }
+        self.wrapped(self.value() + other.value())
     }
 
     fn sub(&self, other: &Self) -> Self {
+        self.check_compatible(other);
+        self.wrapped(self.value().saturating_sub(other.value()))
+    }
+
+    fn mul(&self, other: &Self) -> Self {
+        self.check_compatible(other);
+        self.wrapped(self.value() * other.value())
+    }
+
+    fn div(&self, other: &Self) -> Self {
+        self.check_compatible(other);
+        self.wrapped(self.value() / other.value())
+    }
+
+    fn rem(&self, other: &Self) -> Self {
+        self.check_compatible(other);
+        self.wrapped(self.value() % other.value())
+    }
+
+    fn bit_and(&self, other: &Self) -> Self {
         self.check_compatible(other);
         Self {
-            value: self.value().saturating_sub(other.value()) as _,
+            value: self.value & other.value,
             width: self.width,
             sign: self.sign,
         }
     }
 
-    fn mul(&self, other: &Self) -> Self {
+    fn bit_or(&self, other: &Self) -> Self {
         self.check_compatible(other);
         Self {
-            value: (self.value() * other.value()) as _,
+            value: self.value | other.value,
             width: self.width,
             sign: self.sign,
         }
     }
 
-    fn div(&self, other: &Self) -> Self {
+    fn bit_xor(&self, other: &Self) -> Self {
         self.check_compatible(other);
         Self {
-            value: (self.value() / other.value()) as _,
+            value: self.value ^ other.value,
             width: self.width,
             sign: self.sign,
         }
     }
 
+    /// Shifts left by the amount taken modulo the width, as `wrapping_shl` of Rust does
+    fn shl(&self, other: &Self) -> Self {
+        let shift = other.value() as u32 % self.width;
+        self.wrapped(self.value().wrapping_shl(shift))
+    }
+
+    /// Shifts right by the amount taken modulo the width, filling with the sign bit only when
+    /// value is signed
+    fn shr(&self, other: &Self) -> Self {
+        let shift = other.value() as u32 % self.width;
+        match self.sign {
+            Signedness::Unsigned => self.wrapped((self.value() as u64 >> shift) as i64),
+            _ => self.wrapped(self.value() >> shift),
+        }
+    }
+
     fn cmp(&self, other: &Self) -> Ordering {
         self.check_compatible(other);
         self.value().cmp(&other.value())
@@ -147,22 +197,28 @@ impl Bits {
 
     fn negate(&self) -> Self {
         assert_ne!(self.sign, Signedness::Unsigned);
-        Self {
-            value: (-self.value()) as _,
-            width: self.width,
-            sign: self.sign,
+        self.wrapped(-self.value())
+    }
+
+    /// Reinterprets the value as the integer type, literals without a suffix get it only once
+    /// they are type checked
+    pub(crate) fn typed(self, ty: &Type) -> Self {
+        match ty {
+            Type::U32 => self.as_u32().into(),
+            Type::I32 => self.as_i32().into(),
+            _ => self,
         }
     }
 
-    fn as_usize(&self) -> usize {
+    pub(crate) fn as_usize(&self) -> usize {
         self.value.try_into().unwrap()
     }
 
-    fn as_i32(&self) -> i32 {
+    pub(crate) fn as_i32(&self) -> i32 {
         self.value as i32
     }
 
-    fn as_u32(&self) -> u32 {
+    pub(crate) fn as_u32(&self) -> u32 {
         self.value as u32
     }
 }
@@ -177,6 +233,26 @@ mod tests {
         assert_eq!(a.value(), -10);
         assert_eq!(a.negate().value(), 10);
     }
+
+    #[test]
+    fn test_bits_shifts() {
+        let a = Bits::from(-16i32);
+        let b = Bits::from(0x8000_0000u32);
+        let two = Bits::from(2i32);
+        assert_eq!(a.shr(&two).value(), -4);
+        assert_eq!(a.shl(&two).value(), -64);
+        assert_eq!(b.shr(&two).as_u32(), 0x2000_0000);
+    }
+
+    #[test]
+    fn test_bits_shifts_wrap_around_width() {
+        let one = Bits::from(1u32);
+        let forty = Bits::from(40u32);
+        assert_eq!(one.shl(&forty), Bits::from(256u32));
+        assert_eq!(Bits::from(0x8000_0000u32).shl(&one), Bits::from(0u32));
+        assert_eq!(Bits::from(0x8000_0000u32).shr(&forty), Bits::from(0x80_0000u32));
+        assert_eq!(Bits::from(-1i32).shr(&Bits::from(33i32)), Bits::from(-1i32));
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -186,24 +262,175 @@ pub(crate) enum Const {
     Bool(bool),
     Pointer(usize),
     Struct,
+    /// Tag of a value of an enum, its payload is kept like fields of a struct
+    Variant(usize),
+    /// Index of a suspended generator in the frame which called it
+    Generator(usize),
+    /// Index of the function, values captured by a closure are kept like fields of a struct
+    Function(usize),
     Undefined,
 }
 
+impl Const {
+    /// Checks whether the value has fields kept along with it
+    fn has_fields(&self) -> bool {
+        matches!(self, Const::Struct | Const::Variant(_) | Const::Function(_))
+    }
+}
+
+/// Value given back by a function, along with the fields of a struct, a tuple or a variant
+#[derive(Clone, PartialEq)]
+pub(crate) struct Value {
+    pub(crate) value: Const,
+    pub(crate) fields: Vec<Value>,
+}
+
+impl From<Const> for Value {
+    fn from(value: Const) -> Self {
+        Value {
+            value,
+            fields: vec![],
+        }
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.value)?;
+        if !self.fields.is_empty() {
+            f.debug_list().entries(&self.fields).finish()?;
+        }
+        Ok(())
+    }
+}
+
 impl Expression<'_> {
-    pub(crate) fn as_const(&self) -> Option<Const> {
+    /// Returns the value of the literal, tuples and structs are given with their fields
+    pub(crate) fn as_value(&self, type_by_expr: &ExprToType) -> Option<Value> {
         match self {
-            Expression::Integer(bits) => Some(Const::Integer(*bits)),
+            Expression::Tuple(fields) | Expression::StructLiteral(fields) => Some(Value {
+                value: Const::Struct,
+                fields: fields.iter().map(|it| it.as_value(type_by_expr)).collect::<Option<_>>()?,
+            }),
+            Expression::Variant(tag, payload) => Some(Value {
+                value: Const::Variant(*tag),
+                fields: payload.iter().map(|it| it.as_value(type_by_expr)).collect::<Option<_>>()?,
+            }),
+            _ => self.as_const(type_by_expr).map(Value::from),
+        }
+    }
+
+    pub(crate) fn as_const(&self, type_by_expr: &ExprToType) -> Option<Const> {
+        match self {
+            Expression::Integer(bits) => Some(Const::Integer(bits.typed(type_by_expr.of(self)))),
             Expression::Float(x) => Some(Const::F32(*x as _)),
-            Expression::Prefix(ast::Operator::Negate, inner) => match inner.as_const()? {
+            Expression::Prefix(ast::Operator::Negate, inner) => match inner.as_const(type_by_expr)? {
                 Const::Integer(val) => Some(Const::Integer(val.negate())),
                 _ => None,
             },
             Expression::Bool(x) => Some(Const::Bool(*x)),
+            Expression::Infix(op, lhs, rhs) => {
+                arithmetic_operation(*op, lhs.as_const(type_by_expr)?, rhs.as_const(type_by_expr)?)
+            }
             _ => None,
         }
     }
 }
 
+/// Computes result of the binary operator, unless it is not defined for the operands
+fn arithmetic_operation(op: ast::Operator, a: Const, b: Const) -> Option<Const> {
+    Some(match (op, a, b) {
+        (ast::Operator::And, Const::Bool(a), Const::Bool(b)) => {
+            Const::Bool(a && b)
+        }
+        (ast::Operator::Or, Const::Bool(a), Const::Bool(b)) => {
+            Const::Bool(a || b)
+        }
+        (ast::Operator::Add, Const::Integer(a), Const::Integer(b)) => {
+            Const::Integer(a.add(&b))
+        }
+        (ast::Operator::Mul, Const::Integer(a), Const::Integer(b)) => {
+            Const::Integer(a.mul(&b))
+        }
+        (ast::Operator::Sub, Const::Integer(a), Const::Integer(b)) => {
+            Const::Integer(a.sub(&b))
+        }
+        (ast::Operator::Div, Const::Integer(a), Const::Integer(b)) => {
+            Const::Integer(a.div(&b))
+        }
+        (ast::Operator::Rem, Const::Integer(a), Const::Integer(b)) => {
+            Const::Integer(a.rem(&b))
+        }
+        (ast::Operator::BitAnd, Const::Integer(a), Const::Integer(b)) => {
+            Const::Integer(a.bit_and(&b))
+        }
+        (ast::Operator::BitOr, Const::Integer(a), Const::Integer(b)) => {
+            Const::Integer(a.bit_or(&b))
+        }
+        (ast::Operator::BitXor, Const::Integer(a), Const::Integer(b)) => {
+            Const::Integer(a.bit_xor(&b))
+        }
+        (ast::Operator::Shl, Const::Integer(a), Const::Integer(b)) => {
+            Const::Integer(a.shl(&b))
+        }
+        (ast::Operator::Shr, Const::Integer(a), Const::Integer(b)) => {
+            Const::Integer(a.shr(&b))
+        }
+        (ast::Operator::BitAnd, Const::Bool(a), Const::Bool(b)) => {
+            Const::Bool(a & b)
+        }
+        (ast::Operator::BitOr, Const::Bool(a), Const::Bool(b)) => {
+            Const::Bool(a | b)
+        }
+        (ast::Operator::BitXor, Const::Bool(a), Const::Bool(b)) => {
+            Const::Bool(a ^ b)
+        }
+        (ast::Operator::Less, Const::Integer(a), Const::Integer(b)) => {
+            Const::Bool(a.cmp(&b).is_lt())
+        }
+        (ast::Operator::Greater, Const::Integer(a), Const::Integer(b)) => {
+            Const::Bool(a.cmp(&b).is_gt())
+        }
+        (ast::Operator::Equal, Const::Integer(a), Const::Integer(b)) => {
+            Const::Bool(a.cmp(&b).is_eq())
+        }
+        (ast::Operator::NotEqual, Const::Integer(a), Const::Integer(b)) => {
+            Const::Bool(a.cmp(&b).is_ne())
+        }
+        (ast::Operator::LessEqual, Const::Integer(a), Const::Integer(b)) => {
+            Const::Bool(a.cmp(&b).is_le())
+        }
+        (ast::Operator::GreaterEqual, Const::Integer(a), Const::Integer(b)) => {
+            Const::Bool(a.cmp(&b).is_ge())
+        }
+
+        (ast::Operator::Add, Const::F32(a), Const::F32(b)) => Const::F32(a + b),
+        (ast::Operator::Mul, Const::F32(a), Const::F32(b)) => Const::F32(a * b),
+        (ast::Operator::Sub, Const::F32(a), Const::F32(b)) => Const::F32(a - b),
+        (ast::Operator::Div, Const::F32(a), Const::F32(b)) => Const::F32(a / b),
+        (ast::Operator::Rem, Const::F32(a), Const::F32(b)) => Const::F32(a % b),
+        (ast::Operator::Less, Const::F32(a), Const::F32(b)) => {
+            Const::Bool(a < b)
+        }
+        (ast::Operator::Greater, Const::F32(a), Const::F32(b)) => {
+            Const::Bool(a > b)
+        }
+        (ast::Operator::Equal, Const::F32(a), Const::F32(b)) => {
+            Const::Bool(a == b)
+        }
+        (ast::Operator::NotEqual, Const::F32(a), Const::F32(b)) => {
+            Const::Bool(a != b)
+        }
+        (ast::Operator::LessEqual, Const::F32(a), Const::F32(b)) => {
+            Const::Bool(a <= b)
+        }
+        (ast::Operator::GreaterEqual, Const::F32(a), Const::F32(b)) => {
+            Const::Bool(a >= b)
+        }
+        _ => return None,
+    })
+}
+
 #[derive(Debug)]
 enum CastType {
     F32ToI32,
@@ -220,10 +447,34 @@ enum Instr {
     BinaryOperation(Var, ast::Operator, Var, Var),
     SetElement(Var, usize, Var),
     GetElement(Var, Var, Var),
+    /// Writes the element at the index held by the variable, elements of a slice are written in memory
+    StoreElement(Var, Var, Var),
     SetField(Var, usize, Var),
     GetField(Var, Var, usize),
+    /// Reads index of the variant of a value of an enum
+    GetTag(Var, Var),
     Call(Var, String, Vec<Var>),
+    /// Calls function value of the variable, passing values it captured before the arguments
+    CallIndirect(Var, Var, Vec<Var>),
+    /// Makes function value of the named function, values it captures are set as fields before
+    Closure(Var, String),
+    /// Reads value of the named static
+    LoadStatic(Var, String),
+    /// Writes value of the named static
+    StoreStatic(String, Var),
     Cast(Var, Var, CastType),
+    /// Runs generator until it yields a value or finishes, sets the flag when it yielded
+    Resume(Var, Var, Var),
+    /// Frees the generator once the loop iterating it is left
+    Release(Var),
+    /// Copies elements of the array into memory, makes the target a slice of all of them. Slice
+    /// then does not see later changes to the array
+    Store(Var, Var, usize),
+    /// Makes slice of the elements of the slice from the start up to the end, or up to its end
+    Slice(Var, Var, Var, Option<Var>),
+    /// Takes the next item of the range and moves the range past it, sets the flag when there was
+    /// an item left
+    Advance(Var, Var, Var),
 }
 
 impl fmt::Debug for Instr {
@@ -241,6 +492,12 @@ impl fmt::Debug for Instr {
                     ast::Operator::Sub => "-",
                     ast::Operator::Mul => "*",
                     ast::Operator::Div => "/",
+                    ast::Operator::Rem => "%",
+                    ast::Operator::BitAnd => "&",
+                    ast::Operator::BitOr => "|",
+                    ast::Operator::BitXor => "^",
+                    ast::Operator::Shl => "<<",
+                    ast::Operator::Shr => ">>",
                     ast::Operator::Equal => "==",
                     ast::Operator::NotEqual => "!=",
                     ast::Operator::Less => "<",
@@ -261,6 +518,7 @@ impl fmt::Debug for Instr {
                     ast::Operator::Negate => "-",
                     ast::Operator::Deref => "*",
                     ast::Operator::Ref => "&",
+                    ast::Operator::Not => "not ",
                     _ => unimplemented!("{:?}", op),
                 },
                 a
@@ -271,18 +529,48 @@ impl fmt::Debug for Instr {
             Instr::GetElement(var, arr, index) => {
                 write!(f, "{:?} = {:?}[{:?}]", var, arr, index)
             }
+            Instr::StoreElement(arr, index, val) => {
+                write!(f, "{:?}[{:?}] = {:?}", arr, index, val)
+            }
             Instr::Cast(left, right, mode) => {
                 write!(f, "{:?} = cast({:?}, {:?})", left, right, mode)
             }
             Instr::Call(target, ident, args) => {
                 write!(f, "{:?} = {}({:?})", target, ident, args)
             }
+            Instr::CallIndirect(target, callee, args) => {
+                write!(f, "{:?} = {:?}({:?})", target, callee, args)
+            }
+            Instr::Closure(target, ident) => {
+                write!(f, "{:?} = closure {}", target, ident)
+            }
+            Instr::LoadStatic(target, ident) => write!(f, "{:?} = {}", target, ident),
+            Instr::StoreStatic(ident, value) => write!(f, "{} = {:?}", ident, value),
             Instr::SetField(target, field, value) => {
                 write!(f, "{:?}.{} = {:?}", target, field, value)
             }
             Instr::GetField(var, base, value) => {
                 write!(f, "{:?} = {:?}.{}", var, base, value)
             }
+            Instr::GetTag(var, value) => {
+                write!(f, "{:?} = tag({:?})", var, value)
+            }
+            Instr::Resume(value, done, generator) => {
+                write!(f, "({:?}, {:?}) = resume({:?})", value, done, generator)
+            }
+            Instr::Release(generator) => write!(f, "release({:?})", generator),
+            Instr::Store(target, array, len) => {
+                write!(f, "{:?} = store({:?}, {})", target, array, len)
+            }
+            Instr::Slice(target, slice, start, Some(end)) => {
+                write!(f, "{:?} = {:?}[{:?}..{:?}]", target, slice, start, end)
+            }
+            Instr::Slice(target, slice, start, None) => {
+                write!(f, "{:?} = {:?}[{:?}..]", target, slice, start)
+            }
+            Instr::Advance(item, advanced, range) => {
+                write!(f, "({:?}, {:?}) = advance({:?})", item, advanced, range)
+            }
         }
     }
 }
@@ -297,6 +585,8 @@ enum Terminator {
     Return,
     Unreachable,
     Assert(Var, Block),
+    /// Suspends the generator with the value, it continues from the block once resumed
+    Yield(Var, Block),
 }
 
 #[derive(Debug)]
@@ -314,6 +604,8 @@ struct BlockBody {
 #[derive(Debug)]
 pub(crate) struct FunctionIr<'tcx> {
     name: String,
+    /// Attributes the function was annotated with, eg. `#[inline]` for the optimizer
+    attributes: Vec<ast::Attribute>,
     num_args: usize,
     /// defines[0..num_args] == function args
     ///
@@ -322,6 +614,20 @@ pub(crate) struct FunctionIr<'tcx> {
     /// defines[num_args+1..] == locals
     defines: Vec<VarDef<'tcx>>,
     blocks: Vec<BlockBody>,
+    /// Instances of generic functions called, as their mangled name, name and type arguments
+    instances: Vec<(String, String, Vec<TypeRef<'tcx>>)>,
+}
+
+impl<'tcx> FunctionIr<'tcx> {
+    /// Returns instances of generic functions which have to be built for this function to run
+    pub(crate) fn instances(&self) -> &[(String, String, Vec<TypeRef<'tcx>>)] {
+        &self.instances
+    }
+
+    /// Checks whether calling the function gives a generator instead of running it
+    fn is_generator(&self) -> bool {
+        matches!(self.defines[self.num_args].ty, Type::Generator(_))
+    }
 }
 
 pub(crate) fn validate_types(ir: &FunctionIr<'_>) {
@@ -336,6 +642,12 @@ pub(crate) fn validate_types(ir: &FunctionIr<'_>) {
 }
 
 pub(crate) fn dump_ir(ir: &FunctionIr<'_>, f: &mut impl Write) -> io::Result<()> {
+    for attribute in &ir.attributes {
+        match attribute.args.is_empty() {
+            true => writeln!(f, "#[{}]", attribute.name)?,
+            false => writeln!(f, "#[{}({})]", attribute.name, attribute.args.join(", "))?,
+        }
+    }
     write!(f, "fn {}(", ir.name)?;
     for (idx, it) in ir.defines.iter().enumerate().take(ir.num_args) {
         if idx != 0 {
@@ -368,6 +680,7 @@ pub(crate) fn dump_ir(ir: &FunctionIr<'_>, f: &mut impl Write) -> io::Result<()>
             Terminator::Return => writeln!(f, "    Return;")?,
             Terminator::Unreachable => writeln!(f, "    Unreachable;")?,
             Terminator::Assert(v, next) => writeln!(f, "    Assert(_{}, _bb{});", v.0, next.0)?,
+            Terminator::Yield(v, next) => writeln!(f, "    Yield(_{}, _bb{});", v.0, next.0)?,
         }
         writeln!(f, "  }}")?;
     }
@@ -377,18 +690,38 @@ pub(crate) fn dump_ir(ir: &FunctionIr<'_>, f: &mut impl Write) -> io::Result<()>
     Ok(())
 }
 
+/// Loop enclosing the items being lowered
+struct LoopScope {
+    label: Option<String>,
+    /// Block `continue` jumps to
+    next: Block,
+    /// Block `break` jumps to
+    exit: Block,
+}
+
 struct IrBuilder<'tcx> {
+    arena: &'tcx Arena<Type<'tcx>>,
+    /// Types standing for placeholders of the generic function being instantiated
+    type_args: Vec<TypeRef<'tcx>>,
+    /// Instances of generic functions called, by their mangled names
+    instances: Vec<(String, String, Vec<TypeRef<'tcx>>)>,
     args: usize,
     vars: Vec<VarDef<'tcx>>,
     blocks: Vec<BlockBody>,
+    /// Loops enclosing the items being lowered, innermost last
+    loops: Vec<LoopScope>,
 }
 
 impl<'tcx> IrBuilder<'tcx> {
-    fn new() -> Self {
+    fn new(arena: &'tcx Arena<Type<'tcx>>, type_args: &[TypeRef<'tcx>]) -> Self {
         Self {
+            arena,
+            type_args: type_args.to_vec(),
+            instances: vec![],
             args: 0,
             vars: vec![],
             blocks: Default::default(),
+            loops: vec![],
         }
     }
 
@@ -403,21 +736,28 @@ impl<'tcx> IrBuilder<'tcx> {
         self.make_var(ty, None)
     }
 
+    /// Gives the type within the instance being built
+    fn subst(&self, ty: TypeRef<'tcx>) -> TypeRef<'tcx> {
+        substitute(self.arena, ty, &self.type_args)
+    }
+
     fn make_var(&mut self, ty: TypeRef<'tcx>, name: Option<&str>) -> Var {
         let var = Var(self.vars.len());
         self.vars.push(VarDef {
-            ty,
+            ty: self.subst(ty),
             name: name.map(String::from),
         });
         var
     }
 
-    fn build(self, name: String) -> FunctionIr<'tcx> {
+    fn build(self, name: String, attributes: Vec<ast::Attribute>) -> FunctionIr<'tcx> {
         FunctionIr {
             name,
+            attributes,
             num_args: self.args,
             defines: self.vars,
             blocks: self.blocks,
+            instances: self.instances,
         }
     }
 
@@ -434,11 +774,26 @@ impl<'tcx> IrBuilder<'tcx> {
         self.blocks[block.0].terminator = term;
     }
 
+    /// Returns loop `break` or `continue` with the given label refers to
+    fn enclosing_loop(&self, label: &Option<String>) -> &LoopScope {
+        let scope = match label {
+            Some(label) => self.loops.iter().rev().find(|it| it.label.as_ref() == Some(label)),
+            None => self.loops.last(),
+        };
+        scope.expect("break or continue outside of a loop")
+    }
+
+    /// Checks whether control flow leaving the block was already decided
+    fn is_terminated(&self, block: Block) -> bool {
+        !matches!(self.blocks[block.0].terminator, Terminator::Unreachable)
+    }
+
     fn push(&mut self, block: Block, inst: Instr) {
         self.blocks[block.0].instrs.push(inst);
     }
 }
 
+#[allow(clippy::only_used_in_recursion)]
 fn visit_expr<'expr, 'tcx>(
     expr: ExprRef<'expr>,
     builder: &mut IrBuilder<'tcx>,
@@ -448,10 +803,40 @@ fn visit_expr<'expr, 'tcx>(
     type_by_expr: &mut ExprToType<'tcx>,
 ) -> Var {
     match expr {
-        Expression::Identifier(ident) => names[ident],
+        Expression::Identifier(ident) => match names.get(ident) {
+            Some(var) => *var,
+            // Function used as a value
+            None => {
+                let var = builder.make_var(type_by_expr.of(expr), None);
+                builder.push(block, Instr::Closure(var, ident.clone()));
+                var
+            }
+        },
+        Expression::Static(name) => {
+            let var = builder.make_var(type_by_expr.of(expr), None);
+            builder.push(block, Instr::LoadStatic(var, name.clone()));
+            var
+        }
+        Expression::Closure(name, captures) => {
+            let var = builder.make_var(type_by_expr.of(expr), None);
+            for (idx, capture) in captures.iter().enumerate() {
+                let x = visit_expr(capture, builder, names, block, exprs, type_by_expr);
+                builder.push(block, Instr::SetField(var, idx, x));
+            }
+            // Closure within a generic function refers to the instance for the same type arguments
+            let name = if builder.type_args.is_empty() {
+                name.clone()
+            } else {
+                let mangled = instance_name(name, &builder.type_args);
+                builder.instances.push((mangled.clone(), name.clone(), builder.type_args.clone()));
+                mangled
+            };
+            builder.push(block, Instr::Closure(var, name));
+            var
+        }
         Expression::Integer(val) => {
             let var = builder.make_var(type_by_expr.of(expr), None);
-            builder.push(block, Instr::Const(var, Const::Integer(*val as _)));
+            builder.push(block, Instr::Const(var, Const::Integer(val.typed(type_by_expr.of(expr)))));
             var
         }
         Expression::Float(val) => {
@@ -466,70 +851,131 @@ fn visit_expr<'expr, 'tcx>(
         }
         Expression::Prefix(op, rhs) => {
             let var = builder.make_var(type_by_expr.of(expr), None);
-            let operand = visit_expr(&rhs, builder, &names, block, exprs, type_by_expr);
+            let operand = visit_expr(rhs, builder, names, block, exprs, type_by_expr);
             builder.push(block, Instr::UnaryOperation(var, *op, operand));
             var
         }
         Expression::Infix(op, lhs, rhs) => {
             let var = builder.make_var(type_by_expr.of(expr), None);
-            let a = visit_expr(&lhs, builder, &names, block, exprs, type_by_expr);
-            let b = visit_expr(&rhs, builder, &names, block, exprs, type_by_expr);
+            let a = visit_expr(lhs, builder, names, block, exprs, type_by_expr);
+            let b = visit_expr(rhs, builder, names, block, exprs, type_by_expr);
             builder.push(block, Instr::BinaryOperation(var, *op, a, b));
             var
         }
         Expression::Array(elements) => {
             let var = builder.make_var(type_by_expr.of(expr), None);
             for (index, item) in elements.iter().enumerate() {
-                let expr = visit_expr(&item, builder, names, block, exprs, type_by_expr);
+                let expr = visit_expr(item, builder, names, block, exprs, type_by_expr);
                 builder.push(block, Instr::SetElement(var, index, expr));
             }
             var
         }
         Expression::Index(slice, index) => {
-            let ty = match type_by_expr.of(slice) {
-                Type::Array(_, ty) => ty,
-                Type::Slice(_) => unimplemented!(),
-                _ => unreachable!(),
-            };
-
-            let slice_var = match &slice {
-                Expression::Identifier(ident) => names[ident],
-                _ => unimplemented!(),
-            };
-
-            let element_var = builder.make_var(ty, None);
-            let index_var = visit_expr(&index, builder, names, block, exprs, type_by_expr);
+            let slice_var = visit_expr(slice, builder, names, block, exprs, type_by_expr);
+            let element_var = builder.make_var(type_by_expr.of(expr), None);
+            let index_var = visit_expr(index, builder, names, block, exprs, type_by_expr);
             builder.push(block, Instr::GetElement(element_var, slice_var, index_var));
             element_var
         }
+        Expression::Slice { base, start, end } => {
+            let mut base_var = visit_expr(base, builder, names, block, exprs, type_by_expr);
+            // Elements of an array are copied into memory, so that the slice can point to them
+            if let &Type::Array(len, _) = type_by_expr.of(base) {
+                let array = base_var;
+                base_var = builder.make_var(type_by_expr.of(expr), None);
+                builder.push(block, Instr::Store(base_var, array, len));
+            }
+            let start = visit_expr(start, builder, names, block, exprs, type_by_expr);
+            let end = end.map(|end| visit_expr(end, builder, names, block, exprs, type_by_expr));
+            let var = builder.make_var(type_by_expr.of(expr), None);
+            builder.push(block, Instr::Slice(var, base_var, start, end));
+            var
+        }
+        Expression::Call(Expression::Intrinsic(Intrinsic::len), args) => {
+            let var = builder.make_var(type_by_expr.of(expr), None);
+            match type_by_expr.of(args[0]) {
+                &Type::Array(len, _) => builder.push(block, Instr::Const(var, Const::Integer((len as u32).into()))),
+                _ => {
+                    let slice = visit_expr(args[0], builder, names, block, exprs, type_by_expr);
+                    builder.push(block, Instr::GetField(var, slice, 1));
+                }
+            }
+            var
+        }
         Expression::Call(func, args) => {
+            let indirect = match func {
+                Expression::Identifier(ident) => names.get(ident).copied(),
+                Expression::Intrinsic(_) => None,
+                func => Some(visit_expr(func, builder, names, block, exprs, type_by_expr)),
+            };
+            if let Some(callee) = indirect {
+                let mut params = Vec::new();
+                for arg in args {
+                    params.push(visit_expr(arg, builder, names, block, exprs, type_by_expr));
+                }
+                let ret = builder.make_var(type_by_expr.of(expr), Some("Return of"));
+                builder.push(block, Instr::CallIndirect(ret, callee, params));
+                return ret;
+            }
+
             let ident = match &func {
-                Expression::Identifier(ident) => ident.clone(),
+                Expression::Identifier(ident) => {
+                    let ty = builder.subst(type_by_expr.of(expr));
+                    match instance_of(ident, func, args, ty, type_by_expr, |ty| builder.subst(ty)) {
+                        Some((mangled, type_args)) => {
+                            builder.instances.push((mangled.clone(), ident.clone(), type_args));
+                            mangled
+                        }
+                        None => ident.clone(),
+                    }
+                }
                 Expression::Intrinsic(id) => format!("intrinsic.{}", id.to_str()),
                 other => todo!("{:?}", other),
             };
 
             let mut params = Vec::new();
             for arg in args {
-                params.push(visit_expr(&arg, builder, names, block, exprs, type_by_expr));
+                params.push(visit_expr(arg, builder, names, block, exprs, type_by_expr));
             }
 
             let ret = builder.make_var(type_by_expr.of(expr), Some("Return of"));
             builder.push(block, Instr::Call(ret, ident, params));
             ret
         }
-        Expression::Range(_, _) => Var::error(),
-        Expression::Var(var) => var.clone(),
+        Expression::Range {
+            start,
+            end,
+            step,
+            inclusive,
+        } => {
+            let var = builder.make_var(type_by_expr.of(expr), None);
+            let start = visit_expr(start, builder, names, block, exprs, type_by_expr);
+            let end = visit_expr(end, builder, names, block, exprs, type_by_expr);
+            let step = visit_expr(step, builder, names, block, exprs, type_by_expr);
+            let inclusive = {
+                let var = builder.make_var(&Type::Bool, None);
+                builder.push(block, Instr::Const(var, Const::Bool(*inclusive)));
+                var
+            };
+            // Direction is decided once, the start moves past the end when the range is done
+            let descending = builder.make_var(&Type::Bool, None);
+            builder.push(block, Instr::BinaryOperation(descending, ast::Operator::Greater, start, end));
+            for (idx, field) in [start, end, step, inclusive, descending].into_iter().enumerate() {
+                builder.push(block, Instr::SetField(var, idx, field));
+            }
+            var
+        }
+        Expression::Var(var) => *var,
         Expression::Error => Var::error(),
         Expression::Cast(source_expr) => {
             let var = builder.make_var(type_by_expr.of(source_expr), None);
-            let x = visit_expr(&source_expr, builder, names, block, exprs, type_by_expr);
+            let x = visit_expr(source_expr, builder, names, block, exprs, type_by_expr);
             builder.push(
                 block,
                 Instr::Cast(
                     var,
                     x,
-                    match (type_by_expr.of(source_expr), type_by_expr.of(expr)) {
+                    match (builder.subst(type_by_expr.of(source_expr)), builder.subst(type_by_expr.of(expr))) {
                         (Type::F32, Type::I32) => CastType::F32ToI32,
                         (Type::I32, Type::F32) => CastType::I32ToF32,
                         (Type::U32, Type::F32) => CastType::U32ToF32,
@@ -545,71 +991,115 @@ fn visit_expr<'expr, 'tcx>(
         Expression::Tuple(fields) | Expression::StructLiteral(fields) => {
             let var = builder.make_var(type_by_expr.of(expr), None);
             for (idx, field) in fields.iter().enumerate() {
-                let x = visit_expr(&&field, builder, names, block, exprs, type_by_expr);
+                let x = visit_expr(field, builder, names, block, exprs, type_by_expr);
                 builder.push(block, Instr::SetField(var, idx, x));
             }
             var
         }
-        Expression::Field(expr, idx) => {
-            let base = visit_expr(&&expr, builder, names, block, exprs, type_by_expr);
+        Expression::Field(base, idx) => {
+            let base = visit_expr(base, builder, names, block, exprs, type_by_expr);
             let element_var = builder.make_var(type_by_expr.of(expr), None);
             builder.push(block, Instr::GetField(element_var, base, *idx));
             element_var
         }
+        Expression::Variant(tag, payload) => {
+            let var = builder.make_var(type_by_expr.of(expr), None);
+            for (idx, field) in payload.iter().enumerate() {
+                let x = visit_expr(field, builder, names, block, exprs, type_by_expr);
+                builder.push(block, Instr::SetField(var, idx, x));
+            }
+            builder.push(block, Instr::Const(var, Const::Variant(*tag)));
+            var
+        }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn visit_item<'expr, 'tcx>(
     item: &Item<'expr, 'tcx>,
     arena: &'tcx Arena<Type<'tcx>>,
     builder: &mut IrBuilder<'tcx>,
     local_names: &mut HashMap<String, Var>,
     ret: Option<Var>,
-    after_loop: Option<Block>,
     block: Block,
     exprs: &'expr Arena<Expression<'expr>>,
     type_by_expr: &mut ExprToType<'tcx>,
 ) -> Block {
-    match item {
-        Item::Let { name, ty, expr } => {
+    // Items made up while desugaring point at the item they come from
+    let at = |kind| Item {
+        kind,
+        span: item.span,
+    };
+    match &item.kind {
+        ItemKind::Let { name, ty, expr } => {
             let var = builder.make_var(ty, Some(name.as_str()));
             local_names.insert(name.clone(), var);
 
             if let Some(expr) = expr {
-                let expr = visit_expr(&expr, builder, &local_names, block, exprs, type_by_expr);
+                let expr = visit_expr(expr, builder, local_names, block, exprs, type_by_expr);
                 builder.push(block, Instr::Copy(var, expr));
             }
 
             block
         }
-        Item::Assignment {
+        ItemKind::LetPattern { bindings, expr } => {
+            let value = visit_expr(expr, builder, local_names, block, exprs, type_by_expr);
+            for (idx, name, ty) in bindings {
+                let var = builder.make_var(ty, Some(name.as_str()));
+                builder.push(block, Instr::GetField(var, value, *idx));
+                local_names.insert(name.clone(), var);
+            }
+
+            block
+        }
+        ItemKind::Assignment {
             lhs,
             operator,
             expr,
         } => {
-            let rhs = visit_expr(&expr, builder, &local_names, block, exprs, type_by_expr);
-            let lhs = visit_expr(lhs, builder, &local_names, block, exprs, type_by_expr);
+            let rhs = visit_expr(expr, builder, local_names, block, exprs, type_by_expr);
+            // Element is written into the array or the slice itself, rather than into the value read from it
+            if let Expression::Index(base, index) = lhs {
+                let base = visit_expr(base, builder, local_names, block, exprs, type_by_expr);
+                let index = visit_expr(index, builder, local_names, block, exprs, type_by_expr);
+                let value = match operator {
+                    Some(op) => {
+                        let element = builder.make_var(type_by_expr.of(lhs), None);
+                        builder.push(block, Instr::GetElement(element, base, index));
+                        builder.push(block, Instr::BinaryOperation(element, *op, element, rhs));
+                        element
+                    }
+                    None => rhs,
+                };
+                builder.push(block, Instr::StoreElement(base, index, value));
+                return block;
+            }
+            let var = visit_expr(lhs, builder, local_names, block, exprs, type_by_expr);
             if let Some(op) = operator {
-                builder.push(block, Instr::BinaryOperation(lhs, *op, lhs, rhs));
+                builder.push(block, Instr::BinaryOperation(var, *op, var, rhs));
             } else {
-                builder.push(block, Instr::Copy(lhs, rhs));
+                builder.push(block, Instr::Copy(var, rhs));
+            }
+            // Static was read into the variable, so it has to be written back
+            if let Expression::Static(name) = lhs {
+                builder.push(block, Instr::StoreStatic(name.clone(), var));
             }
 
             block
         }
-        Item::Expression { expr } => {
-            visit_expr(&expr, builder, &local_names, block, exprs, type_by_expr);
+        ItemKind::Expression { expr } => {
+            visit_expr(expr, builder, local_names, block, exprs, type_by_expr);
             block
         }
-        Item::If {
+        ItemKind::If {
             condition,
             arm_true,
             arm_false,
         } => {
             let cond_var = visit_expr(
-                &condition,
+                condition,
                 builder,
-                &local_names,
+                local_names,
                 block,
                 exprs,
                 type_by_expr,
@@ -619,7 +1109,6 @@ fn visit_item<'expr, 'tcx>(
             let succ_block = builder.block();
 
             let mut block_true = first_block_true;
-            builder.set_terminator_of(first_block_true, Terminator::Jump(succ_block));
             for item in arm_true {
                 block_true = visit_item(
                     item,
@@ -627,18 +1116,19 @@ fn visit_item<'expr, 'tcx>(
                     builder,
                     local_names,
                     ret,
-                    after_loop,
                     block_true,
                     exprs,
                     type_by_expr,
                 );
             }
+            if !builder.is_terminated(block_true) {
+                builder.set_terminator_of(block_true, Terminator::Jump(succ_block));
+            }
 
             let block_false = if let Some(items) = arm_false {
                 let first_block_false = builder.block();
 
                 let mut block_false = first_block_false;
-                builder.set_terminator_of(block_false, Terminator::Jump(succ_block));
                 for item in items {
                     block_false = visit_item(
                         item,
@@ -646,12 +1136,14 @@ fn visit_item<'expr, 'tcx>(
                         builder,
                         local_names,
                         ret,
-                        after_loop,
                         block_false,
                         exprs,
                         type_by_expr,
                     );
                 }
+                if !builder.is_terminated(block_false) {
+                    builder.set_terminator_of(block_false, Terminator::Jump(succ_block));
+                }
 
                 first_block_false
             } else {
@@ -664,25 +1156,97 @@ fn visit_item<'expr, 'tcx>(
             );
             succ_block
         }
-        Item::Return(expr) => {
-            let var = visit_expr(&expr, builder, &local_names, block, exprs, type_by_expr);
-            builder.push(block, Instr::Copy(ret.unwrap(), var));
+        ItemKind::Match { scrutinee, arms } => {
+            let value = visit_expr(scrutinee, builder, local_names, block, exprs, type_by_expr);
+            let tag = builder.make_var(arena.alloc(Type::U32), None);
+            builder.push(block, Instr::GetTag(tag, value));
+
+            // Arms check the tag in order, the first one matching runs
+            let succ_block = builder.block();
+            let mut test_block = block;
+            for arm in arms {
+                let first_block = builder.block();
+                match arm.variant {
+                    Some(variant) => {
+                        let expected = builder.make_var(arena.alloc(Type::U32), None);
+                        let matches = builder.make_var(arena.alloc(Type::Bool), None);
+                        let next_block = builder.block();
+                        builder.push(
+                            test_block,
+                            Instr::Const(expected, Const::Integer((variant as u32).into())),
+                        );
+                        builder.push(
+                            test_block,
+                            Instr::BinaryOperation(matches, ast::Operator::Equal, tag, expected),
+                        );
+                        builder.set_terminator_of(
+                            test_block,
+                            Terminator::JumpIf(matches, first_block, next_block),
+                        );
+                        test_block = next_block;
+                    }
+                    None => {
+                        builder.set_terminator_of(test_block, Terminator::Jump(first_block));
+                        test_block = builder.block();
+                    }
+                }
+
+                for (idx, name, ty) in &arm.bindings {
+                    let var = builder.make_var(ty, Some(name.as_str()));
+                    builder.push(first_block, Instr::GetField(var, value, *idx));
+                    local_names.insert(name.clone(), var);
+                }
+                let mut arm_block = first_block;
+                for item in &arm.body {
+                    arm_block = visit_item(
+                        item,
+                        arena,
+                        builder,
+                        local_names,
+                        ret,
+                        arm_block,
+                        exprs,
+                        type_by_expr,
+                    );
+                }
+                if !builder.is_terminated(arm_block) {
+                    builder.set_terminator_of(arm_block, Terminator::Jump(succ_block));
+                }
+            }
+            succ_block
+        }
+        ItemKind::Return(expr) => {
+            if let Some(expr) = expr {
+                let var = visit_expr(expr, builder, local_names, block, exprs, type_by_expr);
+                builder.push(block, Instr::Copy(ret.unwrap(), var));
+            }
             builder.set_terminator_of(block, Terminator::Return);
             block
         }
-        Item::ForIn { name, expr, body } => match type_by_expr.of(expr) {
-            &Type::Array(len, item_ty) => {
+        ItemKind::Yield(expr) => {
+            let var = visit_expr(expr, builder, local_names, block, exprs, type_by_expr);
+            let resumed = builder.block();
+            builder.set_terminator_of(block, Terminator::Yield(var, resumed));
+            resumed
+        }
+        ItemKind::ForIn {
+            label,
+            name,
+            expr,
+            body,
+        } => match type_by_expr.of(expr) {
+            &Type::Array(_, item_ty) | &Type::Slice(item_ty) => {
                 let items_id = String::from("_items");
                 let index_id = String::from("_x");
 
                 let expr_ty = type_by_expr.of(expr);
-                let items = vec![
-                    Item::Let {
+                let prelude = [
+                    at(ItemKind::Let {
                         name: items_id.clone(),
                         ty: expr_ty,
                         expr: Some(expr),
-                    },
-                    Item::Let {
+                    }),
+                    at(ItemKind::Let {
                         name: index_id.clone(),
                         ty: arena.alloc(Type::I32),
                         expr: Some(make_expr(
@@ -691,225 +1255,195 @@ fn visit_item<'expr, 'tcx>(
                             &Type::I32,
                             Expression::Integer(0.into()),
                         )),
-                    },
-                    Item::Loop {
-                        body: {
-                            let expr = Expression::Infix(
-                                ast::Operator::Equal,
-                                make_expr(
-                                    exprs,
-                                    type_by_expr,
-                                    &Type::I32,
-                                    Expression::Integer((len as u32).into()),
-                                ),
-                                make_expr(
-                                    exprs,
-                                    type_by_expr,
-                                    &Type::I32,
-                                    Expression::Identifier(index_id.clone()),
-                                ),
-                            );
-
-                            let e = Expression::Index(
-                                make_expr(
-                                    exprs,
-                                    type_by_expr,
-                                    expr_ty,
-                                    Expression::Identifier(items_id),
-                                ),
-                                make_expr(
-                                    exprs,
-                                    type_by_expr,
-                                    &Type::U32,
-                                    Expression::Identifier(index_id.clone()),
-                                ),
-                            );
-
-                            let mut items = vec![
-                                Item::If {
-                                    condition: make_expr(exprs, type_by_expr, &Type::Bool, expr),
-                                    arm_true: vec![Item::Break],
-                                    arm_false: None,
-                                },
-                                Item::Let {
-                                    name: name.to_string(),
-                                    ty: item_ty,
-                                    expr: Some(make_expr(exprs, type_by_expr, item_ty, e)),
-                                },
-                            ];
-                            items.extend_from_slice(body);
-                            let e = Expression::Infix(
-                                ast::Operator::Add,
-                                make_expr(
-                                    exprs,
-                                    type_by_expr,
-                                    &Type::I32,
-                                    Expression::Identifier(index_id.clone()),
-                                ),
-                                make_expr(
-                                    exprs,
-                                    type_by_expr,
-                                    &Type::I32,
-                                    Expression::Integer(1.into()),
-                                ),
-                            );
-                            items.push(Item::Assignment {
-                                lhs: make_expr(
-                                    exprs,
-                                    type_by_expr,
-                                    &Type::I32,
-                                    Expression::Identifier(index_id),
-                                ),
-                                operator: None,
-                                expr: make_expr(exprs, type_by_expr, &Type::I32, e),
-                            });
-                            items
-                        },
-                    },
+                    }),
                 ];
+                let mut block = block;
+                for item in &prelude {
+                    block = visit_item(
+                        item,
+                        arena,
+                        builder,
+                        local_names,
+                        ret,
+                        block,
+                        exprs,
+                        type_by_expr,
+                    );
+                }
+
+                let header = builder.block();
+                builder.set_terminator_of(block, Terminator::Jump(header));
+                let len = match expr_ty {
+                    &Type::Array(len, _) => Expression::Integer((len as u32).into()),
+                    // Length of a slice is known only once it is made
+                    _ => {
+                        let ty = arena.alloc(Type::Function(vec![&Type::Any], &Type::U32));
+                        Expression::Call(
+                            make_expr(exprs, type_by_expr, ty, Expression::Intrinsic(Intrinsic::len)),
+                            vec![make_expr(
+                                exprs,
+                                type_by_expr,
+                                expr_ty,
+                                Expression::Identifier(items_id.clone()),
+                            )],
+                        )
+                    }
+                };
+                let condition = Expression::Infix(
+                    ast::Operator::NotEqual,
+                    make_expr(
+                        exprs,
+                        type_by_expr,
+                        &Type::I32,
+                        Expression::Identifier(index_id.clone()),
+                    ),
+                    make_expr(exprs, type_by_expr, &Type::I32, len),
+                );
+                let condition = make_expr(exprs, type_by_expr, &Type::Bool, condition);
+                let condition = visit_expr(condition, builder, local_names, header, exprs, type_by_expr);
 
-                visit_item(
-                    &Item::Block(items),
+                let e = Expression::Index(
+                    make_expr(
+                        exprs,
+                        type_by_expr,
+                        expr_ty,
+                        Expression::Identifier(items_id),
+                    ),
+                    make_expr(
+                        exprs,
+                        type_by_expr,
+                        &Type::U32,
+                        Expression::Identifier(index_id.clone()),
+                    ),
+                );
+                let mut items = vec![at(ItemKind::Let {
+                    name: name.to_string(),
+                    ty: item_ty,
+                    expr: Some(make_expr(exprs, type_by_expr, item_ty, e)),
+                })];
+                items.extend_from_slice(body);
+
+                let step = [increment(&index_id, item.span, exprs, type_by_expr)];
+                visit_loop(
+                    label,
+                    header,
+                    Some(condition),
+                    &items,
+                    &step,
                     arena,
                     builder,
                     local_names,
                     ret,
-                    None,
-                    block,
                     exprs,
                     type_by_expr,
                 )
             }
-            &Type::Range => {
-                let Expression::Range(start_expr, end_expr) = &expr else {
-                    todo!();
-                };
-                let end_expr = end_expr.as_ref().unwrap();
-
-                let start = visit_expr(
-                    &start_expr,
+            &Type::Range(item_ty) => {
+                let range = visit_expr(expr, builder, local_names, block, exprs, type_by_expr);
+                // Advancing moves the start of the range, the loop works on a copy
+                let cursor = builder.make_var(type_by_expr.of(expr), None);
+                builder.push(block, Instr::Copy(cursor, range));
+                let item = builder.make_var(item_ty, Some(name.as_str()));
+                let advanced = builder.make_var(&Type::Bool, None);
+                local_names.insert(name.clone(), item);
+
+                let header = builder.block();
+                builder.set_terminator_of(block, Terminator::Jump(header));
+                builder.push(header, Instr::Advance(item, advanced, cursor));
+                visit_loop(
+                    label,
+                    header,
+                    Some(advanced),
+                    body,
+                    &[],
+                    arena,
                     builder,
                     local_names,
-                    block,
+                    ret,
                     exprs,
                     type_by_expr,
-                );
-                let end = visit_expr(&end_expr, builder, local_names, block, exprs, type_by_expr);
-
-                let index = name.clone();
-
-                let items = vec![
-                    Item::Let {
-                        name: index.clone(),
-                        ty: &Type::I32,
-                        expr: Some(make_expr(
-                            exprs,
-                            type_by_expr,
-                            &Type::I32,
-                            Expression::Integer(0.into()),
-                        )),
-                    },
-                    Item::Loop {
-                        body: {
-                            let e = Expression::Infix(
-                                ast::Operator::Equal,
-                                make_expr(
-                                    exprs,
-                                    type_by_expr,
-                                    &Type::I32,
-                                    Expression::Identifier(index.clone()),
-                                ),
-                                make_expr(exprs, type_by_expr, &Type::I32, Expression::Var(end)),
-                            );
-                            let mut items = vec![Item::If {
-                                condition: make_expr(exprs, type_by_expr, &Type::Bool, e),
-                                arm_true: vec![Item::Break],
-                                arm_false: None,
-                            }];
-                            items.extend_from_slice(body);
-                            let e = Expression::Infix(
-                                ast::Operator::Add,
-                                make_expr(
-                                    exprs,
-                                    type_by_expr,
-                                    &Type::I32,
-                                    Expression::Identifier(index.clone()),
-                                ),
-                                make_expr(
-                                    exprs,
-                                    type_by_expr,
-                                    &Type::I32,
-                                    Expression::Integer(1.into()),
-                                ),
-                            );
-                            items.push(Item::Assignment {
-                                lhs: make_expr(
-                                    exprs,
-                                    type_by_expr,
-                                    &Type::I32,
-                                    Expression::Identifier(index),
-                                ),
-                                operator: None,
-                                expr: make_expr(exprs, type_by_expr, &Type::I32, e),
-                            });
-                            items
-                        },
-                    },
-                ];
-
-                visit_item(
-                    &Item::Block(items),
+                )
+            }
+            &Type::Generator(item_ty) => {
+                let generator = visit_expr(expr, builder, local_names, block, exprs, type_by_expr);
+                let item = builder.make_var(item_ty, Some(name.as_str()));
+                let yielded = builder.make_var(&Type::Bool, None);
+                local_names.insert(name.clone(), item);
+
+                let header = builder.block();
+                builder.set_terminator_of(block, Terminator::Jump(header));
+                builder.push(header, Instr::Resume(item, yielded, generator));
+                let exit = visit_loop(
+                    label,
+                    header,
+                    Some(yielded),
+                    body,
+                    &[],
                     arena,
                     builder,
                     local_names,
                     ret,
-                    None,
-                    block,
                     exprs,
                     type_by_expr,
-                )
+                );
+                builder.push(exit, Instr::Release(generator));
+                exit
             }
             other => {
                 log::error!("Unsupported {:?}", other);
                 block
             }
         },
-        Item::Break => {
-            builder.set_terminator_of(block, Terminator::Jump(after_loop.unwrap()));
+        ItemKind::Break { label } => {
+            let exit = builder.enclosing_loop(label).exit;
+            builder.set_terminator_of(block, Terminator::Jump(exit));
             block
         }
-        Item::Loop { body } => {
-            let entry = builder.block();
-            let mut current = entry;
-
-            let after = builder.block();
-
-            builder.set_terminator_of(block, Terminator::Jump(entry));
-            for item in body {
-                match item {
-                    Item::Break => {}
-                    Item::Yield(_) => {}
-                    Item::Return(_) => {}
-                    other => {
-                        current = visit_item(
-                            other,
-                            arena,
-                            builder,
-                            local_names,
-                            ret,
-                            Some(after),
-                            current,
-                            exprs,
-                            type_by_expr,
-                        );
-                        builder.set_terminator_of(current, Terminator::Jump(entry));
-                    }
-                }
-            }
-            builder.set_terminator_of(after, Terminator::Return);
-            after
+        ItemKind::Continue { label } => {
+            let next = builder.enclosing_loop(label).next;
+            builder.set_terminator_of(block, Terminator::Jump(next));
+            block
         }
-        Item::Block(body) => {
+        ItemKind::Loop { label, body } => {
+            let header = builder.block();
+            builder.set_terminator_of(block, Terminator::Jump(header));
+            visit_loop(
+                label,
+                header,
+                None,
+                body,
+                &[],
+                arena,
+                builder,
+                local_names,
+                ret,
+                exprs,
+                type_by_expr,
+            )
+        }
+        ItemKind::While {
+            label,
+            condition,
+            body,
+        } => {
+            let header = builder.block();
+            builder.set_terminator_of(block, Terminator::Jump(header));
+            let condition = visit_expr(condition, builder, local_names, header, exprs, type_by_expr);
+            visit_loop(
+                label,
+                header,
+                Some(condition),
+                body,
+                &[],
+                arena,
+                builder,
+                local_names,
+                ret,
+                exprs,
+                type_by_expr,
+            )
+        }
+        ItemKind::Block(body) => {
             // FIXME: build a new block?
             let mut block = block;
             for item in body {
@@ -919,7 +1453,6 @@ fn visit_item<'expr, 'tcx>(
                     builder,
                     local_names,
                     ret,
-                    after_loop,
                     block,
                     exprs,
                     type_by_expr,
@@ -927,22 +1460,174 @@ fn visit_item<'expr, 'tcx>(
             }
             block
         }
-        Item::Function { .. } => block,
-        Item::Assert(expr) => block,
+        ItemKind::Function { .. } | ItemKind::Struct { .. } => block,
+        ItemKind::Assert(expr) => block,
         other => unimplemented!("{:?}", other),
     }
 }
 
+/// Lowers loop starting at the header, returns the block executed once the loop is done
+///
+/// Loop ends when the condition computed in the header is false, or when there is no
+/// condition, only by `break`. Step runs after each iteration, also the ones cut short by
+/// `continue`.
+#[allow(clippy::too_many_arguments)]
+fn visit_loop<'expr, 'tcx>(
+    label: &Option<String>,
+    header: Block,
+    condition: Option<Var>,
+    body: &[Item<'expr, 'tcx>],
+    step: &[Item<'expr, 'tcx>],
+    arena: &'tcx Arena<Type<'tcx>>,
+    builder: &mut IrBuilder<'tcx>,
+    local_names: &mut HashMap<String, Var>,
+    ret: Option<Var>,
+    exprs: &'expr Arena<Expression<'expr>>,
+    type_by_expr: &mut ExprToType<'tcx>,
+) -> Block {
+    let entry = builder.block();
+    let exit = builder.block();
+    let terminator = match condition {
+        Some(condition) => Terminator::JumpIf(condition, entry, exit),
+        None => Terminator::Jump(entry),
+    };
+    builder.set_terminator_of(header, terminator);
+
+    let next = if step.is_empty() {
+        header
+    } else {
+        builder.block()
+    };
+    builder.loops.push(LoopScope {
+        label: label.clone(),
+        next,
+        exit,
+    });
+    let mut current = entry;
+    for item in body {
+        current = visit_item(
+            item,
+            arena,
+            builder,
+            local_names,
+            ret,
+            current,
+            exprs,
+            type_by_expr,
+        );
+    }
+    if !builder.is_terminated(current) {
+        builder.set_terminator_of(current, Terminator::Jump(next));
+    }
+    builder.loops.pop();
+
+    let mut current = next;
+    for item in step {
+        current = visit_item(
+            item,
+            arena,
+            builder,
+            local_names,
+            ret,
+            current,
+            exprs,
+            type_by_expr,
+        );
+    }
+    if !step.is_empty() {
+        builder.set_terminator_of(current, Terminator::Jump(header));
+    }
+    exit
+}
+
+/// Makes `name = name + 1`, for advancing the index of desugared loops
+fn increment<'expr, 'tcx>(
+    name: &str,
+    span: Span,
+    exprs: &'expr Arena<Expression<'expr>>,
+    type_by_expr: &mut ExprToType<'tcx>,
+) -> Item<'expr, 'tcx> {
+    let e = Expression::Infix(
+        ast::Operator::Add,
+        make_expr(
+            exprs,
+            type_by_expr,
+            &Type::I32,
+            Expression::Identifier(name.to_owned()),
+        ),
+        make_expr(
+            exprs,
+            type_by_expr,
+            &Type::I32,
+            Expression::Integer(1.into()),
+        ),
+    );
+    Item {
+        kind: ItemKind::Assignment {
+            lhs: make_expr(
+                exprs,
+                type_by_expr,
+                &Type::I32,
+                Expression::Identifier(name.to_owned()),
+            ),
+            operator: None,
+            expr: make_expr(exprs, type_by_expr, &Type::I32, e),
+        },
+        span,
+    }
+}
+
+/// Gives the name an instance of a generic function is built with
+pub(crate) fn instance_name(name: &str, type_args: &[TypeRef<'_>]) -> String {
+    let type_args: Vec<_> = type_args.iter().map(|ty| format!("{:?}", ty)).collect();
+    format!("{}<{}>", name, type_args.join(", "))
+}
+
+/// Finds which instance of the function is called, unless the function is not generic
+///
+/// Types of the arguments and of the result are passed through `subst` first, so that calls
+/// within a generic function refer to instances for its own type arguments.
+pub(crate) fn instance_of<'tcx>(
+    name: &str,
+    func: ExprRef<'_>,
+    args: &[ExprRef<'_>],
+    ret_ty: TypeRef<'tcx>,
+    type_by_expr: &ExprToType<'tcx>,
+    subst: impl Fn(TypeRef<'tcx>) -> TypeRef<'tcx>,
+) -> Option<(String, Vec<TypeRef<'tcx>>)> {
+    let func_ty = type_by_expr.of(func);
+    let Type::Function(params, ret) = func_ty else {
+        return None;
+    };
+    let count = type_params_count(func_ty);
+    if count == 0 {
+        return None;
+    }
+    let mut type_args = vec![None; count];
+    for defaults in [false, true] {
+        for (param, arg) in params.iter().zip(args) {
+            bind_placeholders(param, subst(type_by_expr.of(arg)), &mut type_args, defaults);
+        }
+        bind_placeholders(ret, ret_ty, &mut type_args, defaults);
+    }
+    let type_args: Vec<_> = type_args.into_iter().map(|ty| ty.unwrap_or(&Type::Error)).collect();
+    Some((instance_name(name, &type_args), type_args))
+}
+
+/// Builds the function, instantiating it for given type arguments when it is generic
 pub(crate) fn build_ir<'expr, 'tcx>(
     item: &Item<'expr, 'tcx>,
+    type_args: &[TypeRef<'tcx>],
     arena: &'tcx Arena<Type<'tcx>>,
     exprs: &'expr Arena<Expression<'expr>>,
     type_by_expr: &mut ExprToType<'tcx>,
 ) -> Result<FunctionIr<'tcx>, ()> {
-    let mut builder = IrBuilder::new();
-    match item {
-        Item::Function {
+    let mut builder = IrBuilder::new(arena, type_args);
+    match &item.kind {
+        ItemKind::Function {
             name,
+            attributes,
+            type_params,
             is_extern,
             args,
             ty,
@@ -963,14 +1648,22 @@ pub(crate) fn build_ir<'expr, 'tcx>(
                     &mut builder,
                     &mut names,
                     Some(ret),
-                    None,
                     block,
                     exprs,
                     type_by_expr,
                 );
             }
+            // Function returns once it runs out of items, generator finishes then
+            if !builder.is_terminated(block) {
+                builder.set_terminator_of(block, Terminator::Return);
+            }
 
-            return Ok(builder.build(name.to_owned()));
+            let name = if type_params.is_empty() {
+                name.to_owned()
+            } else {
+                instance_name(name, type_args)
+            };
+            return Ok(builder.build(name, attributes.clone()));
         }
         _ => eprintln!("trying generate ir of item that is not a function"),
     }
@@ -981,7 +1674,12 @@ pub(crate) fn build_ir<'expr, 'tcx>(
 struct ExecutionContext {
     vars: HashMap<Var, Const>,
     vars_arrays: HashMap<(Var, usize), Const>,
+    /// Fields of structs stored as elements or fields of the variables
+    nested: HashMap<(Var, usize), Vec<Const>>,
     memory: Vec<Const>,
+    /// Generators created by calls, along with the name of the called function. Slots of
+    /// generators which were released are reused
+    generators: Vec<Option<(String, Frame)>>,
 }
 
 impl ExecutionContext {
@@ -992,22 +1690,235 @@ impl ExecutionContext {
             self.vars.insert(dst, val);
         }
     }
+
+    /// Returns values of the fields of the struct in the variable
+    fn fields(&self, var: Var) -> Vec<Const> {
+        (0..)
+            .map_while(|idx| self.vars_arrays.get(&(var, idx)).copied())
+            .collect()
+    }
+
+    /// Stores the value of the variable as the element or the field of the target, along with its fields
+    fn store_element(&mut self, target: Var, idx: usize, value: Var) {
+        self.vars_arrays.insert((target, idx), self.vars[&value]);
+        if self.vars[&value].has_fields() {
+            self.nested.insert((target, idx), self.fields(value));
+        }
+    }
+
+    /// Loads the element or the field of the variable into the target, along with its fields
+    fn load_element(&mut self, target: Var, var: Var, idx: usize) {
+        let value = self.vars_arrays[&(var, idx)];
+        if let Some(fields) = self.nested.get(&(var, idx)).cloned() {
+            for (field, value) in fields.into_iter().enumerate() {
+                self.vars_arrays.insert((target, field), value);
+            }
+        }
+        self.insert_value(target, value);
+    }
+
+    /// Returns the pointer to the first element and the length of the slice in the variable, if
+    /// it holds a slice
+    fn slice(&self, var: Var) -> Option<(usize, usize)> {
+        match (self.vars.get(&var), self.vars_arrays.get(&(var, 0)), self.vars_arrays.get(&(var, 1))) {
+            (Some(Const::Struct), Some(Const::Pointer(ptr)), Some(Const::Integer(len))) => Some((*ptr, len.as_usize())),
+            _ => None,
+        }
+    }
+
+    /// Returns the start of the range in the variable and moves the range to the next item,
+    /// unless the range is done
+    ///
+    /// Ranges are structs of the start, the end, the step, and whether the end is included and
+    /// the items descend. Step is expected to be positive.
+    fn advance(&mut self, range: Var) -> Option<Const> {
+        let [Const::Integer(start), Const::Integer(end), Const::Integer(step), Const::Bool(inclusive), Const::Bool(descending)] =
+            self.fields(range)[..]
+        else {
+            unreachable!("{:?} is not a range", self.fields(range));
+        };
+        let (distance, order) = match descending {
+            false => (end.sub(&start), start.cmp(&end)),
+            true => (start.sub(&end), end.cmp(&start)),
+        };
+        if order.is_gt() || (order.is_eq() && !inclusive) {
+            return None;
+        }
+        // Stepping past the end could overflow, the range is left empty by moving to its end instead
+        let next = match (distance.cmp(&step).is_lt(), descending) {
+            (true, _) => {
+                self.vars_arrays.insert((range, 3), Const::Bool(false));
+                end
+            }
+            (false, false) => start.add(&step),
+            (false, true) => start.sub(&step),
+        };
+        self.vars_arrays.insert((range, 0), Const::Integer(next));
+        Some(Const::Integer(start))
+    }
+
+    /// Returns the value along with the fields kept for the variable
+    fn value_of(&self, var: Var, value: Const) -> Value {
+        if !value.has_fields() {
+            return value.into();
+        }
+        let fields = self
+            .fields(var)
+            .into_iter()
+            .enumerate()
+            .map(|(idx, field)| Value {
+                value: field,
+                fields: self.nested.get(&(var, idx)).map_or_else(Vec::new, |nested| {
+                    nested.iter().copied().map(Value::from).collect()
+                }),
+            })
+            .collect();
+        Value { value, fields }
+    }
+
+    /// Makes frame calling the function with values of the variables, structs and enums are passed
+    /// along with their fields, arrays are passed as their elements
+    ///
+    /// Values captured by a closure are passed first, before the arguments.
+    fn frame_for(&self, ir: &FunctionIr<'_>, captures: &[Const], args: &[Var]) -> Frame {
+        let values: Vec<_> = captures
+            .iter()
+            .copied()
+            .chain(args.iter().map(|it| self.vars.get(it).copied().unwrap_or(Const::Undefined)))
+            .collect();
+        let mut frame = Frame::new(ir, &values);
+        for (idx, arg) in args.iter().enumerate() {
+            let param = Var(captures.len() + idx);
+            match self.vars.get(arg) {
+                Some(value) if value.has_fields() => {
+                    for (field, value) in self.fields(*arg).into_iter().enumerate() {
+                        frame.ctx.vars_arrays.insert((param, field), value);
+                    }
+                }
+                Some(_) => {}
+                // Array has no value of its own, only the elements
+                None => {
+                    frame.ctx.vars.remove(&param);
+                    for (element, value) in self.fields(*arg).into_iter().enumerate() {
+                        frame.ctx.vars_arrays.insert((param, element), value);
+                        if let Some(fields) = self.nested.get(&(*arg, element)) {
+                            frame.ctx.nested.insert((param, element), fields.clone());
+                        }
+                    }
+                }
+            }
+        }
+        frame
+    }
+}
+
+/// State of a function being executed, kept between resumptions of a generator
+struct Frame {
+    ctx: ExecutionContext,
+    block: usize,
+    inst: usize,
+}
+
+impl Frame {
+    fn new(ir: &FunctionIr<'_>, args: &[Const]) -> Self {
+        let mut ctx = ExecutionContext::default();
+        for (idx, cnst) in args.iter().enumerate().take(ir.num_args) {
+            ctx.insert_value(Var(idx), *cnst);
+        }
+        Self {
+            ctx,
+            block: 0,
+            inst: 0,
+        }
+    }
+}
+
+/// Reason the execution of a frame stopped
+enum Exit {
+    Return(Const),
+    Yield(Const),
+    /// Runtime error stopped the function, the functions waiting for it stop as well
+    Abort,
 }
 
+/// Runs the function, statics keep their values between calls and extern functions are run by the host.
+/// Gives no value when the execution was stopped by a runtime error
 pub(crate) fn execute_ir(
     ir: &FunctionIr<'_>,
     args: &[Const],
-    functions: &HashMap<String, FunctionIr<'_>>,
-) -> Const {
-    let mut curr_block = 0;
-    let mut curr_inst = 0;
+    functions: &IndexMap<String, FunctionIr<'_>>,
+    statics: &mut HashMap<String, Const>,
+    host: &HostFunctions,
+) -> Option<Value> {
+    let mut frame = Frame::new(ir, args);
+    match resume(ir, &mut frame, functions, statics, host) {
+        Exit::Return(value) => Some(frame.ctx.value_of(Var(ir.num_args), value)),
+        Exit::Yield(_) => {
+            log::error!("{} yielded, but it was not called as a generator", ir.name);
+            Some(Const::Undefined.into())
+        }
+        Exit::Abort => None,
+    }
+}
 
-    let mut ctx = ExecutionContext::default();
-    for (idx, cnst) in args.iter().enumerate().take(ir.num_args) {
-       ctx.insert_value(Var(idx), *cnst);
+/// Runs the called function and stores its result in the target, along with the fields of a
+/// returned struct. Calling a generator gives it without running. Fails when the called function
+/// was stopped by a runtime error
+fn call(
+    ctx: &mut ExecutionContext,
+    target: Var,
+    name: &str,
+    mut callee: Frame,
+    functions: &IndexMap<String, FunctionIr<'_>>,
+    statics: &mut HashMap<String, Const>,
+    host: &HostFunctions,
+) -> Result<(), ()> {
+    let func = &functions[name];
+    if func.is_generator() {
+        let generator = Some((name.to_owned(), callee));
+        let idx = match ctx.generators.iter().position(Option::is_none) {
+            Some(idx) => {
+                ctx.generators[idx] = generator;
+                idx
+            }
+            None => {
+                ctx.generators.push(generator);
+                ctx.generators.len() - 1
+            }
+        };
+        ctx.insert_value(target, Const::Generator(idx));
+        return Ok(());
     }
+    std::mem::swap(&mut ctx.memory, &mut callee.ctx.memory);
+    let exit = resume(func, &mut callee, functions, statics, host);
+    std::mem::swap(&mut ctx.memory, &mut callee.ctx.memory);
+    let result = match exit {
+        Exit::Return(value) => value,
+        Exit::Yield(_) => unreachable!("{} is not a generator", name),
+        Exit::Abort => return Err(()),
+    };
+    if result.has_fields() {
+        for (idx, value) in callee.ctx.fields(Var(func.num_args)).into_iter().enumerate() {
+            ctx.vars_arrays.insert((target, idx), value);
+        }
+    }
+    ctx.insert_value(target, result);
+    Ok(())
+}
 
-    loop {
+/// Executes the frame from where it stopped, until the function returns or yields
+fn resume(
+    ir: &FunctionIr<'_>,
+    frame: &mut Frame,
+    functions: &IndexMap<String, FunctionIr<'_>>,
+    statics: &mut HashMap<String, Const>,
+    host: &HostFunctions,
+) -> Exit {
+    let mut curr_block = frame.block;
+    let mut curr_inst = frame.inst;
+    let ctx = &mut frame.ctx;
+
+    let exit = loop {
         match ir.blocks[curr_block].instrs.get(curr_inst) {
             Some(instr) => {
                 match instr {
@@ -1019,11 +1930,14 @@ pub(crate) fn execute_ir(
                             Some(var) => {
                                 ctx.insert_value(*dst, var);
 
-                                if let Const::Struct = var {
+                                if var.has_fields() {
                                     // Copy all fields
                                     let mut idx = 0;
                                     while let Some(c) = ctx.vars_arrays.get(&(*src, idx)) {
-                                        ctx.vars_arrays.insert((*dst, idx), c.clone());
+                                        ctx.vars_arrays.insert((*dst, idx), *c);
+                                        if let Some(fields) = ctx.nested.get(&(*src, idx)).cloned() {
+                                            ctx.nested.insert((*dst, idx), fields);
+                                        }
                                         idx += 1;
                                     }
                                 }
@@ -1038,6 +1952,9 @@ pub(crate) fn execute_ir(
 
                                 for to in to_copy {
                                     ctx.vars_arrays.insert((*dst, to), ctx.vars_arrays[&(*src, to)]);
+                                    if let Some(fields) = ctx.nested.get(&(*src, to)).cloned() {
+                                        ctx.nested.insert((*dst, to), fields);
+                                    }
                                 }
                             }
                         }
@@ -1048,9 +1965,13 @@ pub(crate) fn execute_ir(
                                 Const::Integer(v.negate())
                             }
                             (ast::Operator::Negate, Const::F32(v)) => Const::F32(-v),
+                            (ast::Operator::Not, Const::Bool(v)) => Const::Bool(!v),
                             (ast::Operator::Ref, val) => {
                                 let idx = ctx.memory.len();
                                 ctx.memory.push(val);
+                                if val.has_fields() {
+                                    ctx.memory.extend(ctx.fields(*a));
+                                }
                                 Const::Pointer(idx)
                             }
                             (ast::Operator::Deref, val) => val,
@@ -1061,71 +1982,6 @@ pub(crate) fn execute_ir(
                         ctx.insert_value(*dst, val);
                     }
                     Instr::BinaryOperation(dst, op, a, b) => {
-                        fn arithmetic_operation(op: ast::Operator, a: Const, b: Const) -> Option<Const> {
-                            Some(match (op, a, b) {
-                                (ast::Operator::And, Const::Bool(a), Const::Bool(b)) => {
-                                    Const::Bool(a && b)
-                                }
-                                (ast::Operator::Or, Const::Bool(a), Const::Bool(b)) => {
-                                    Const::Bool(a || b)
-                                }
-                                (ast::Operator::Add, Const::Integer(a), Const::Integer(b)) => {
-                                    Const::Integer(a.add(&b))
-                                }
-                                (ast::Operator::Mul, Const::Integer(a), Const::Integer(b)) => {
-                                    Const::Integer(a.mul(&b))
-                                }
-                                (ast::Operator::Sub, Const::Integer(a), Const::Integer(b)) => {
-                                    Const::Integer(a.sub(&b))
-                                }
-                                (ast::Operator::Div, Const::Integer(a), Const::Integer(b)) => {
-                                    Const::Integer(a.div(&b))
-                                }
-                                (ast::Operator::Less, Const::Integer(a), Const::Integer(b)) => {
-                                    Const::Bool(a.cmp(&b).is_lt())
-                                }
-                                (ast::Operator::Greater, Const::Integer(a), Const::Integer(b)) => {
-                                    Const::Bool(a.cmp(&b).is_gt())
-                                }
-                                (ast::Operator::Equal, Const::Integer(a), Const::Integer(b)) => {
-                                    Const::Bool(a.cmp(&b).is_eq())
-                                }
-                                (ast::Operator::NotEqual, Const::Integer(a), Const::Integer(b)) => {
-                                    Const::Bool(a.cmp(&b).is_ne())
-                                }
-                                (ast::Operator::LessEqual, Const::Integer(a), Const::Integer(b)) => {
-                                    Const::Bool(a.cmp(&b).is_le())
-                                }
-                                (ast::Operator::GreaterEqual, Const::Integer(a), Const::Integer(b)) => {
-                                    Const::Bool(a.cmp(&b).is_ge())
-                                }
-
-                                (ast::Operator::Add, Const::F32(a), Const::F32(b)) => Const::F32(a + b),
-                                (ast::Operator::Mul, Const::F32(a), Const::F32(b)) => Const::F32(a * b),
-                                (ast::Operator::Sub, Const::F32(a), Const::F32(b)) => Const::F32(a - b),
-                                (ast::Operator::Div, Const::F32(a), Const::F32(b)) => Const::F32(a / b),
-                                (ast::Operator::Less, Const::F32(a), Const::F32(b)) => {
-                                    Const::Bool(a < b)
-                                }
-                                (ast::Operator::Greater, Const::F32(a), Const::F32(b)) => {
-                                    Const::Bool(a > b)
-                                }
-                                (ast::Operator::Equal, Const::F32(a), Const::F32(b)) => {
-                                    Const::Bool(a == b)
-                                }
-                                (ast::Operator::NotEqual, Const::F32(a), Const::F32(b)) => {
-                                    Const::Bool(a != b)
-                                }
-                                (ast::Operator::LessEqual, Const::F32(a), Const::F32(b)) => {
-                                    Const::Bool(a <= b)
-                                }
-                                (ast::Operator::GreaterEqual, Const::F32(a), Const::F32(b)) => {
-                                    Const::Bool(a >= b)
-                                }
-                                _ => return None,
-                            })
-                        }
-
                         let a = ctx.vars.get(a).copied().unwrap_or(Const::Undefined);
                         let b = ctx.vars.get(b).copied().unwrap_or(Const::Undefined);
                         let val = match arithmetic_operation(*op, a, b) {
@@ -1153,15 +2009,69 @@ pub(crate) fn execute_ir(
                         };
                         ctx.insert_value(*dst, val);
                     }
-                    Instr::SetElement(arr, index, val) => {
-                        ctx.vars_arrays.insert((*arr, *index), ctx.vars[val]);
-                    }
+                    Instr::SetElement(arr, index, val) => ctx.store_element(*arr, *index, *val),
                     Instr::GetElement(var, arr, index) => {
                         let index = match ctx.vars[index] {
                             Const::Integer(v) => v.as_usize(),
                             other => unimplemented!("{:?}", other),
                         };
-                        ctx.insert_value(*var, ctx.vars_arrays[&(*arr, index)]);
+                        match ctx.slice(*arr) {
+                            // Elements of a slice are in memory
+                            Some((ptr, len)) if index < len => ctx.insert_value(*var, ctx.memory[ptr + index]),
+                            Some((_, len)) => {
+                                log::error!("index {} out of bounds of a slice of {} elements", index, len);
+                                break Exit::Abort;
+                            }
+                            None if !ctx.vars_arrays.contains_key(&(*arr, index)) => {
+                                log::error!("index {} out of bounds of an array", index);
+                                break Exit::Abort;
+                            }
+                            None => ctx.load_element(*var, *arr, index),
+                        }
+                    }
+                    Instr::StoreElement(arr, index, val) => {
+                        let index = match ctx.vars[index] {
+                            Const::Integer(v) => v.as_usize(),
+                            other => unimplemented!("{:?}", other),
+                        };
+                        match ctx.slice(*arr) {
+                            Some((ptr, len)) if index < len => ctx.memory[ptr + index] = ctx.vars[val],
+                            Some((_, len)) => {
+                                log::error!("index {} out of bounds of a slice of {} elements", index, len);
+                                break Exit::Abort;
+                            }
+                            None if !ctx.vars_arrays.contains_key(&(*arr, index)) => {
+                                log::error!("index {} out of bounds of an array", index);
+                                break Exit::Abort;
+                            }
+                            None => ctx.store_element(*arr, index, *val),
+                        }
+                    }
+                    Instr::Store(target, array, len) => {
+                        let ptr = ctx.memory.len();
+                        for idx in 0..*len {
+                            ctx.memory.push(ctx.vars_arrays[&(*array, idx)]);
+                        }
+                        ctx.vars_arrays.insert((*target, 0), Const::Pointer(ptr));
+                        ctx.vars_arrays.insert((*target, 1), Const::Integer((*len as u32).into()));
+                        ctx.insert_value(*target, Const::Struct);
+                    }
+                    Instr::Slice(target, slice, start, end) => {
+                        let Some((ptr, len)) = ctx.slice(*slice) else {
+                            unreachable!("{:?} is not a slice", ctx.vars[slice]);
+                        };
+                        let bound = |var: &Var| match ctx.vars[var] {
+                            Const::Integer(v) => v.as_usize(),
+                            other => unimplemented!("{:?}", other),
+                        };
+                        let (start, end) = (bound(start), end.as_ref().map_or(len, bound));
+                        if start > end || end > len {
+                            log::error!("slice {}..{} out of bounds of a slice of {} elements", start, end, len);
+                            break Exit::Abort;
+                        }
+                        ctx.vars_arrays.insert((*target, 0), Const::Pointer(ptr + start));
+                        ctx.vars_arrays.insert((*target, 1), Const::Integer(((end - start) as u32).into()));
+                        ctx.insert_value(*target, Const::Struct);
                     }
                     Instr::Cast(target, source, mode) => {
                         let source = ctx.vars.get(source).copied().unwrap_or(Const::Undefined);
@@ -1179,7 +2089,7 @@ pub(crate) fn execute_ir(
                                     Const::F32(val.as_u32() as _)
                                 }
                                 (CastType::U32ToI32, Const::Integer(val)) => {
-                                    Const::Integer(val.try_into().unwrap())
+                                    Const::Integer(val.as_i32().into())
                                 }
                                 _ => todo!("{:?} {:?}", mode, &source),
                             },
@@ -1192,20 +2102,102 @@ pub(crate) fn execute_ir(
                             log::debug!("Debug value: {:?}", &value);
                             ctx.insert_value(*target, value);
                         }
-                        name => {
-                            let func = &functions[name];
-                            let args: Vec<_> = args.iter().map(|it| ctx.vars[it]).collect();
-                            let result = execute_ir(func, &args, functions);
-                            ctx.insert_value(*target, result);
-                        }
+                        name => match functions.get(name) {
+                            Some(ir) => {
+                                let callee = ctx.frame_for(ir, &[], args);
+                                if call(ctx, *target, name, callee, functions, statics, host).is_err() {
+                                    break Exit::Abort;
+                                }
+                            }
+                            // Only extern functions have no IR, they were checked to be in the host
+                            None => {
+                                let args: Vec<_> = args.iter().map(|it| ctx.vars[it]).collect();
+                                ctx.insert_value(*target, host.get(name).unwrap().call(&args));
+                            }
+                        },
                     },
+                    Instr::CallIndirect(target, func, args) => {
+                        let Const::Function(idx) = ctx.vars[func] else {
+                            unreachable!("{:?} is not a function", ctx.vars[func]);
+                        };
+                        let (name, ir) = functions.get_index(idx).unwrap();
+                        let callee = ctx.frame_for(ir, &ctx.fields(*func), args);
+                        if call(ctx, *target, name, callee, functions, statics, host).is_err() {
+                            break Exit::Abort;
+                        }
+                    }
+                    Instr::Closure(target, name) => {
+                        let Some(idx) = functions.get_index_of(name) else {
+                            unreachable!("no function {}", name);
+                        };
+                        ctx.insert_value(*target, Const::Function(idx));
+                    }
+                    Instr::LoadStatic(target, name) => ctx.insert_value(*target, statics[name]),
+                    Instr::StoreStatic(name, value) => {
+                        statics.insert(name.clone(), ctx.vars[value]);
+                    }
+                    Instr::Resume(value, yielded, generator) => {
+                        let Const::Generator(idx) = ctx.vars[generator] else {
+                            unimplemented!("{:?}", ctx.vars[generator]);
+                        };
+                        let Some((name, generator)) = &mut ctx.generators[idx] else {
+                            unreachable!("generator {} was released", idx);
+                        };
+                        std::mem::swap(&mut ctx.memory, &mut generator.ctx.memory);
+                        let exit = resume(&functions[name.as_str()], generator, functions, statics, host);
+                        std::mem::swap(&mut ctx.memory, &mut generator.ctx.memory);
+                        match exit {
+                            Exit::Yield(result) => {
+                                ctx.insert_value(*value, result);
+                                ctx.insert_value(*yielded, Const::Bool(true));
+                            }
+                            Exit::Return(_) => ctx.insert_value(*yielded, Const::Bool(false)),
+                            Exit::Abort => break Exit::Abort,
+                        }
+                    }
+                    Instr::Release(generator) => {
+                        let Const::Generator(idx) = ctx.vars[generator] else {
+                            unimplemented!("{:?}", ctx.vars[generator]);
+                        };
+                        ctx.generators[idx] = None;
+                    }
+                    Instr::Advance(item, advanced, range) => {
+                        // Step given at runtime is checked only once the range is iterated
+                        if let Const::Integer(step) = ctx.fields(*range)[2] {
+                            if step.cmp(&step.sub(&step)).is_le() {
+                                log::error!("step of a range has to be positive, got {}", step.value());
+                                break Exit::Abort;
+                            }
+                        }
+                        match ctx.advance(*range) {
+                            Some(value) => {
+                                ctx.insert_value(*item, value);
+                                ctx.insert_value(*advanced, Const::Bool(true));
+                            }
+                            None => ctx.insert_value(*advanced, Const::Bool(false)),
+                        }
+                    }
                     Instr::SetField(lhs, idx, rhs) => {
-                        ctx.vars_arrays.insert((*lhs, *idx), ctx.vars[rhs]);
+                        ctx.store_element(*lhs, *idx, *rhs);
                         ctx.insert_value(*lhs, Const::Struct);
                     }
-                    Instr::GetField(target, lhs, idx) => {
-                        ctx.insert_value(*target, ctx.vars_arrays.get(&(*lhs, *idx)).cloned().unwrap());
+                    Instr::GetTag(target, value) => {
+                        // Value matched through a pointer is kept in memory
+                        let value = match ctx.vars[value] {
+                            Const::Pointer(ptr) => ctx.memory[ptr],
+                            value => value,
+                        };
+                        let tag = match value {
+                            Const::Variant(tag) => tag,
+                            other => unreachable!("{:?} is not a value of an enum", other),
+                        };
+                        ctx.insert_value(*target, Const::Integer((tag as u32).into()));
                     }
+                    Instr::GetField(target, lhs, idx) => match ctx.vars.get(lhs) {
+                        // Fields of the struct follow it in memory
+                        Some(Const::Pointer(ptr)) => ctx.insert_value(*target, ctx.memory[ptr + 1 + idx]),
+                        _ => ctx.load_element(*target, *lhs, *idx),
+                    },
                 }
                 curr_inst += 1;
             }
@@ -1221,20 +2213,20 @@ pub(crate) fn execute_ir(
                     }
                     Const::Undefined => {
                         log::error!("trying to jump to undefined");
-                        return Const::Undefined;
+                        break Exit::Return(Const::Undefined);
                     }
                     other => unimplemented!("{:?}", other),
                 },
                 Terminator::Return => {
-                    return match ctx.vars.get(&Var(ir.num_args)) {
+                    break Exit::Return(match ctx.vars.get(&Var(ir.num_args)) {
                         Some(Const::Pointer(x)) => ctx.memory[*x],
                         Some(x) => *x,
                         None => Const::Undefined,
-                    };
+                    });
                 }
                 Terminator::Unreachable => {
                     log::warn!("executing unreachable");
-                    return Const::Undefined;
+                    break Exit::Return(Const::Undefined);
                 }
                 Terminator::Assert(var, block) => match ctx.vars[&var] {
                     Const::Bool(true) => {
@@ -1246,7 +2238,16 @@ pub(crate) fn execute_ir(
                     }
                     other => unimplemented!("{:?}", other),
                 },
+                Terminator::Yield(var, block) => {
+                    curr_block = block.0;
+                    curr_inst = 0;
+                    break Exit::Yield(ctx.vars[&var]);
+                }
             },
         }
-    }
+    };
+
+    frame.block = curr_block;
+    frame.inst = curr_inst;
+    exit
 }
@@ -20,6 +20,25 @@ macro_rules! keywords {
     }
 }
 
+/// Macro used to generate OpKind enum based on collection of operator spellings
+macro_rules! operators {
+    ($($val:expr => $name:ident),*) => {
+        #[derive(Copy, Clone, PartialEq)]
+        pub enum OpKind { $($name,)* }
+
+        impl fmt::Debug for OpKind {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                match self {
+                    $(OpKind::$name => write!(f, $val)?),*
+                }
+                Ok(())
+            }
+        }
+
+        const OPERATORS: [(&'static str, OpKind); count_idents!($($name),*)] = [$(($val, OpKind::$name)),*];
+    }
+}
+
 /// Macro used to count idents separated by commas
 macro_rules! count_idents {
     () => {0};
@@ -36,67 +55,146 @@ keywords! {
     "loop" => Loop,
     "fn" => Fn,
     "struct" => Struct,
+    "impl" => Impl,
+    "enum" => Enum,
+    "match" => Match,
     "yield" => Yield,
     "range" => Range,
     "return" => Return,
     "break" => Break,
+    "continue" => Continue,
     "true" => True,
     "false" => False,
     "in" => In,
     "to" => To,
+    "through" => Through,
+    "step" => Step,
     "assert" => Assert,
+    "import" => Import,
+    "const" => Const,
+    "static" => Static,
+    "mut" => Mut,
     "as" => As,
     "and" => And,
-    "or" => Or
+    "or" => Or,
+    "not" => Not
+}
+
+operators! {
+    "==" => EqEq,
+    "!=" => NotEq,
+    "<>" => LessGreater,
+    "<=" => LessEq,
+    ">=" => GreaterEq,
+    "->" => Arrow,
+    "=>" => FatArrow,
+    "::" => ColonColon,
+    "+=" => PlusEq,
+    "-=" => MinusEq,
+    "*=" => StarEq,
+    "/=" => SlashEq,
+    "<<" => Shl,
+    ">>" => Shr
 }
 
-/// Kind of punctuation mark
+/// Type suffix of a numeric literal, eg. `u32` in `10u32`
 #[derive(Clone, Copy, Debug, PartialEq)]
-pub enum PunctKind {
-    Single,
-    #[allow(dead_code)]
-    Joint,
+pub enum NumberSuffix {
+    I32,
+    U32,
+    F32,
 }
 
 /// Storage for values associated in a single token
 #[derive(Clone, Debug, PartialEq)]
 pub enum TokenValue {
     None,
-    Punct(char, PunctKind),
+    Punct(char),
+    Op(OpKind),
     Identifier,
-    IntegralNumber(i64),
-    FloatingNumber(f64),
+    IntegralNumber(i64, Option<NumberSuffix>),
+    FloatingNumber(f64, Option<NumberSuffix>),
     String(String),
+    Char(char),
+    Label,
     Keyword(Keyword),
+    Error(LexerError),
 }
 
 /// Type of the token
 #[derive(Copy, Clone, PartialEq)]
 pub enum TokenType {
     Punct(char),
+    Op(OpKind),
     Identifier,
     IntegralNumber,
     FloatingNumber,
     String,
+    Char,
+    Label,
     Keyword(Keyword),
     EndOfSource,
+    Error,
 }
 
 impl fmt::Debug for TokenType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             TokenType::Punct(ch) => write!(f, "`{:?}`", ch)?,
+            TokenType::Op(op) => write!(f, "`{:?}`", op)?,
             TokenType::Identifier => write!(f, "identifier")?,
             TokenType::IntegralNumber => write!(f, "integral literal")?,
             TokenType::FloatingNumber => write!(f, "floating literal")?,
             TokenType::String => write!(f, "string")?,
+            TokenType::Char => write!(f, "char literal")?,
+            TokenType::Label => write!(f, "label")?,
             TokenType::Keyword(keyword) => write!(f, "`{:?} keyword`", keyword)?,
             TokenType::EndOfSource => write!(f, "end of source")?,
+            TokenType::Error => write!(f, "invalid token")?,
         }
         Ok(())
     }
 }
 
+/// Kind of the trivia preceding a token
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TriviaKind {
+    /// Sequence of whitespace characters
+    Whitespace,
+    /// Comment starting with `//` and ending at the end of the line
+    LineComment,
+    /// Comment enclosed in `/*` and `*/`, possibly nested
+    BlockComment,
+}
+
+/// Part of the source that has no meaning for the parser, but should be kept for tooling
+#[derive(Clone, PartialEq)]
+pub struct Trivia<'a> {
+    /// Kind of the trivia
+    kind: TriviaKind,
+    /// Slice of the raw source including comment delimiters
+    span: SourceSpan<'a>,
+}
+
+impl<'a> Trivia<'a> {
+    /// Returns kind of the trivia
+    pub fn kind(&self) -> TriviaKind {
+        self.kind
+    }
+
+    /// Returns raw slice of the input that represents the trivia in the source
+    pub fn get_span(&self) -> SourceSpan<'a> {
+        self.span
+    }
+}
+
+impl<'a> fmt::Debug for Trivia<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} {:?} at {}:{}", self.kind, self.span, self.span.line, self.span.column)?;
+        Ok(())
+    }
+}
+
 /// Lexical unit produced by lexical analysis of source code
 #[derive(Clone, PartialEq)]
 pub struct Token<'a> {
@@ -104,10 +202,21 @@ pub struct Token<'a> {
     value: TokenValue,
     /// Slice of the raw source with raw representation of the token
     span: SourceSpan<'a>,
+    /// Whitespace and comments preceding the token
+    trivia: Vec<Trivia<'a>>,
 }
 
 /// Single lexical unit of the source, eg. identifier, literals, etc
 impl<'a> Token<'a> {
+    /// Returns token with given value and no leading trivia
+    fn new(value: TokenValue, span: SourceSpan<'a>) -> Token<'a> {
+        Token {
+            value,
+            span,
+            trivia: Vec::new(),
+        }
+    }
+
     /// Returns raw slice of the input that represents the token in the source
     pub fn get_span(&self) -> SourceSpan<'a> {
         self.span
@@ -123,23 +232,47 @@ impl<'a> Token<'a> {
         self.span.column
     }
 
+    /// Returns whitespace and comments that precede the token in the source
+    pub fn trivia(&self) -> &[Trivia<'a>] {
+        &self.trivia
+    }
+
+    /// Returns comments that precede the token in the source
+    pub fn comments(&self) -> impl Iterator<Item = &Trivia<'a>> {
+        self.trivia
+            .iter()
+            .filter(|trivia| trivia.kind != TriviaKind::Whitespace)
+    }
+
     /// Returns type of the token
     pub fn get_type(&self) -> TokenType {
         match self.value {
-            TokenValue::Punct(ch, _) => TokenType::Punct(ch),
+            TokenValue::Punct(ch) => TokenType::Punct(ch),
+            TokenValue::Op(op) => TokenType::Op(op),
             TokenValue::Identifier => TokenType::Identifier,
             TokenValue::Keyword(kw) => TokenType::Keyword(kw),
-            TokenValue::IntegralNumber(_) => TokenType::IntegralNumber,
-            TokenValue::FloatingNumber(_) => TokenType::FloatingNumber,
+            TokenValue::IntegralNumber(..) => TokenType::IntegralNumber,
+            TokenValue::FloatingNumber(..) => TokenType::FloatingNumber,
             TokenValue::None => TokenType::EndOfSource,
             TokenValue::String(_) => TokenType::String,
+            TokenValue::Char(_) => TokenType::Char,
+            TokenValue::Label => TokenType::Label,
+            TokenValue::Error(_) => TokenType::Error,
         }
     }
 
     /// Returns the char that is representing the token when it is a punctuation mark
-    pub fn as_punct(&self) -> Option<(char, PunctKind)> {
+    pub fn as_punct(&self) -> Option<char> {
         match self.value {
-            TokenValue::Punct(ch, kind) => Some((ch, kind)),
+            TokenValue::Punct(ch) => Some(ch),
+            _ => None,
+        }
+    }
+
+    /// Returns the kind of the operator when token is a multi-character operator
+    pub fn as_op(&self) -> Option<OpKind> {
+        match self.value {
+            TokenValue::Op(op) => Some(op),
             _ => None,
         }
     }
@@ -147,7 +280,7 @@ impl<'a> Token<'a> {
     /// Returns the integral number when token is a integer literal
     pub fn as_integer(&self) -> Option<i64> {
         match self.value {
-            TokenValue::IntegralNumber(val) => Some(val),
+            TokenValue::IntegralNumber(val, _) => Some(val),
             _ => None,
         }
     }
@@ -155,7 +288,31 @@ impl<'a> Token<'a> {
     /// Returns the float number when token is a float literal
     pub fn as_float(&self) -> Option<f64> {
         match self.value {
-            TokenValue::FloatingNumber(val) => Some(val),
+            TokenValue::FloatingNumber(val, _) => Some(val),
+            _ => None,
+        }
+    }
+
+    /// Returns the type suffix when token is a numeric literal that has one
+    pub fn number_suffix(&self) -> Option<NumberSuffix> {
+        match self.value {
+            TokenValue::IntegralNumber(_, suffix) | TokenValue::FloatingNumber(_, suffix) => suffix,
+            _ => None,
+        }
+    }
+
+    /// Returns the character when token is a char literal
+    pub fn as_char(&self) -> Option<char> {
+        match self.value {
+            TokenValue::Char(ch) => Some(ch),
+            _ => None,
+        }
+    }
+
+    /// Returns the error when token stands for a lexical error
+    pub fn as_error(&self) -> Option<LexerError> {
+        match self.value {
+            TokenValue::Error(err) => Some(err),
             _ => None,
         }
     }
@@ -245,12 +402,20 @@ impl<'a> SourceSpan<'a> {
         SourceSpan {
             source: str,
             start: 0,
-            length: str.bytes().len(),
+            length: str.len(),
             line,
             column,
         }
     }
 
+    /// Returns span from the start of current span to the end of the other
+    pub fn to(&self, other: &SourceSpan<'a>) -> SourceSpan<'a> {
+        SourceSpan {
+            length: other.start + other.length - self.start,
+            ..*self
+        }
+    }
+
     /// Checks whether current span directly adjoins with the other
     #[allow(dead_code)]
     pub fn adjoins_with(&self, other: &SourceSpan<'a>) -> bool {
@@ -258,6 +423,38 @@ impl<'a> SourceSpan<'a> {
     }
 }
 
+impl<'a> From<SourceSpan<'a>> for Span {
+    fn from(span: SourceSpan<'a>) -> Self {
+        Span {
+            start: span.start,
+            length: span.length,
+            line: span.line,
+            column: span.column,
+        }
+    }
+}
+
+/// Location of a lexeme in the source, detached from the source itself
+///
+/// Used by the later stages which outlive the source or don't carry its lifetime.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Span {
+    /// Index of the first byte of the lexeme in the source
+    pub start: usize,
+    /// Length of the lexeme (in bytes)
+    pub length: usize,
+    /// Line number the span starts at
+    pub line: usize,
+    /// Column number the span starts at
+    pub column: usize,
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
 impl<'a> PartialEq for SourceSpan<'a> {
     fn eq(&self, other: &SourceSpan<'a>) -> bool {
         self.as_slice() == other.as_slice()
@@ -276,9 +473,40 @@ impl<'a> fmt::Debug for SourceSpan<'a> {
 }
 
 /// Error returned by lexer
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum LexerError {
     UnexpectedEndOfSource(usize, usize),
+    UnexpectedCharacter(char, usize, usize),
+    UnterminatedString(usize, usize),
+    InvalidNumber(usize, usize),
+    InvalidEscape(usize, usize),
+    InvalidCharLiteral(usize, usize),
+}
+
+impl fmt::Display for LexerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LexerError::UnexpectedEndOfSource(line, column) => {
+                write!(f, "Unexpected end of source at {}:{}", line, column)?
+            }
+            LexerError::UnexpectedCharacter(ch, line, column) => {
+                write!(f, "Unexpected character {:?} at {}:{}", ch, line, column)?
+            }
+            LexerError::UnterminatedString(line, column) => {
+                write!(f, "Unterminated string starting at {}:{}", line, column)?
+            }
+            LexerError::InvalidNumber(line, column) => {
+                write!(f, "Invalid number literal at {}:{}", line, column)?
+            }
+            LexerError::InvalidEscape(line, column) => {
+                write!(f, "Invalid escape sequence at {}:{}", line, column)?
+            }
+            LexerError::InvalidCharLiteral(line, column) => {
+                write!(f, "Invalid char literal at {}:{}", line, column)?
+            }
+        }
+        Ok(())
+    }
 }
 
 pub type LexerResult<T> = Result<T, LexerError>;
@@ -317,12 +545,22 @@ impl SourceSpanHandle {
 impl<'a> Lexer<'a> {
     /// Returns next token from the source
     pub fn next(&mut self) -> LexerResult<Token<'a>> {
-        self.skip_space();
+        let trivia = self.skip_trivia()?;
+        let mut token = self.match_token()?;
+        token.trivia = trivia;
+        Ok(token)
+    }
+
+    /// Returns token starting at the current position, without leading trivia
+    fn match_token(&mut self) -> LexerResult<Token<'a>> {
         let token = match self.peek(0) {
             Some(ch) if self.can_start_identifier(ch) => self.match_keyword_or_identifier()?,
-            Some(ch) if ch.is_digit(10) => self.match_number()?,
+            Some(ch) if ch.is_ascii_digit() => self.match_number()?,
             Some('"') => self.match_string()?,
-            Some(ch) => self.match_punct(ch)?,
+            Some('\'') => self.match_char()?,
+            Some(ch) if self.starts_operator(ch) => self.match_operator()?,
+            Some(ch) if ch.is_ascii_punctuation() => self.match_punct(ch)?,
+            Some(ch) => return Err(LexerError::UnexpectedCharacter(ch, self.line, self.column)),
             None => self.match_end_of_source()?,
         };
         Ok(token)
@@ -348,8 +586,9 @@ impl<'a> Lexer<'a> {
         }
     }
 
-    /// Skips all whitespaces
-    fn skip_space(&mut self) {
+    /// Skips all whitespaces, returning them when there was any
+    fn skip_space(&mut self) -> Option<Trivia<'a>> {
+        let handle = self.begin_span();
         while let Some(ch) = self.peek(0) {
             if ch.is_whitespace() {
                 self.advance().unwrap();
@@ -357,6 +596,75 @@ impl<'a> Lexer<'a> {
                 break;
             }
         }
+        let span = handle.get_span(self);
+        if span.length == 0 {
+            return None;
+        }
+        Some(Trivia {
+            kind: TriviaKind::Whitespace,
+            span,
+        })
+    }
+
+    /// Skips all whitespaces and comments, returning them
+    fn skip_trivia(&mut self) -> LexerResult<Vec<Trivia<'a>>> {
+        let mut trivia = vec![];
+        loop {
+            trivia.extend(self.skip_space());
+            let comment = match (self.peek(0), self.peek(1)) {
+                (Some('/'), Some('/')) => self.match_line_comment(),
+                (Some('/'), Some('*')) => self.match_block_comment()?,
+                _ => break,
+            };
+            trivia.push(comment);
+        }
+        Ok(trivia)
+    }
+
+    /// Returns current trivia when it is a line comment
+    fn match_line_comment(&mut self) -> Trivia<'a> {
+        let handle = self.begin_span();
+        while let Some(ch) = self.peek(0) {
+            if ch == '\n' {
+                break;
+            }
+            self.advance().unwrap();
+        }
+        Trivia {
+            kind: TriviaKind::LineComment,
+            span: handle.get_span(self),
+        }
+    }
+
+    /// Returns current trivia when it is a (possibly nested) block comment
+    fn match_block_comment(&mut self) -> LexerResult<Trivia<'a>> {
+        let handle = self.begin_span();
+        let mut depth = 0;
+        loop {
+            match (self.peek(0), self.peek(1)) {
+                (Some('/'), Some('*')) => {
+                    self.advance().unwrap();
+                    self.advance().unwrap();
+                    depth += 1;
+                }
+                (Some('*'), Some('/')) => {
+                    self.advance().unwrap();
+                    self.advance().unwrap();
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                (Some(_), _) => {
+                    self.advance().unwrap();
+                }
+                (None, _) => return Err(LexerError::UnexpectedEndOfSource(self.line, self.column)),
+            }
+        }
+        Ok(Trivia {
+            kind: TriviaKind::BlockComment,
+            span: handle.get_span(self),
+        })
     }
 
     /// Returns current token when it is a keyword or an identifier
@@ -374,12 +682,13 @@ impl<'a> Lexer<'a> {
             Some(keyword) => TokenValue::Keyword(keyword),
             None => TokenValue::Identifier,
         };
-        Ok(Token { value: kind, span })
+        Ok(Token::new(kind, span))
     }
 
     /// Returns current token when it is a string literal
     fn match_string(&mut self) -> LexerResult<Token<'a>> {
         let handle = self.begin_span();
+        let unterminated = LexerError::UnterminatedString(self.line, self.column);
         // '"'
         self.advance().unwrap();
         let mut string = String::new();
@@ -390,98 +699,239 @@ impl<'a> Lexer<'a> {
                         self.advance().unwrap();
                         break;
                     }
+                    '\\' => string.push(self.match_escape().map_err(|err| match err {
+                        LexerError::UnexpectedEndOfSource(..) => unterminated,
+                        err => err,
+                    })?),
                     ch => {
                         string.push(ch);
                         self.advance().unwrap();
                     }
                 },
-                None => return Err(LexerError::UnexpectedEndOfSource(self.line, self.column)),
+                None => return Err(unterminated),
             }
         }
-        Ok(Token {
-            value: TokenValue::String(string),
-            span: handle.get_span(self),
-        })
+        Ok(Token::new(TokenValue::String(string), handle.get_span(self)))
     }
 
-    /// Returns current token when it is built of a single punctuation mark
-    fn match_punct(&mut self, first: char) -> LexerResult<Token<'a>> {
+    /// Returns current token when it is a char literal or a label
+    fn match_char(&mut self) -> LexerResult<Token<'a>> {
+        // Quote followed by an identifier starts a label, unless it is closed like a char literal
+        if self.peek(1).is_some_and(|ch| self.can_start_identifier(ch)) && self.peek(2) != Some('\'') {
+            return self.match_label();
+        }
         let handle = self.begin_span();
-        let span = {
-            self.advance().unwrap();
-            handle.get_span(self)
+        let invalid_char = LexerError::InvalidCharLiteral(self.line, self.column);
+        // '\''
+        self.advance().unwrap();
+        let ch = match self.peek(0) {
+            Some('\\') => self.match_escape()?,
+            Some('\'') => return Err(invalid_char),
+            Some(ch) => {
+                self.advance().unwrap();
+                ch
+            }
+            None => return Err(LexerError::UnexpectedEndOfSource(self.line, self.column)),
         };
+        match self.peek(0) {
+            Some('\'') => {
+                self.advance().unwrap();
+            }
+            Some(_) => return Err(invalid_char),
+            None => return Err(LexerError::UnexpectedEndOfSource(self.line, self.column)),
+        }
+        Ok(Token::new(TokenValue::Char(ch), handle.get_span(self)))
+    }
 
-        let is_joint = self
-            .peek(0)
-            .map(|c| c.is_ascii_punctuation())
-            .unwrap_or(false);
-        let kind = if is_joint {
-            PunctKind::Joint
-        } else {
-            PunctKind::Single
+    /// Returns current token when it is a label, which is an identifier preceded by a quote
+    fn match_label(&mut self) -> LexerResult<Token<'a>> {
+        let handle = self.begin_span();
+        let invalid_char = LexerError::InvalidCharLiteral(self.line, self.column);
+        // '\''
+        self.advance().unwrap();
+        while let Some(ch) = self.peek(0) {
+            if self.can_be_in_identifier(ch) {
+                self.advance().unwrap();
+            } else {
+                break;
+            }
+        }
+        if self.peek(0) == Some('\'') {
+            return Err(invalid_char);
+        }
+        Ok(Token::new(TokenValue::Label, handle.get_span(self)))
+    }
+
+    /// Returns the character denoted by the escape sequence starting at current position
+    fn match_escape(&mut self) -> LexerResult<char> {
+        let invalid_escape = LexerError::InvalidEscape(self.line, self.column);
+        // '\\'
+        self.advance().unwrap();
+        let ch = match self.advance() {
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('0') => '\0',
+            Some('\\') => '\\',
+            Some('\'') => '\'',
+            Some('"') => '"',
+            Some('x') => {
+                let mut code = 0;
+                for _ in 0..2 {
+                    match self.peek(0).and_then(|ch| ch.to_digit(16)) {
+                        Some(digit) => code = code * 16 + digit,
+                        None => return Err(invalid_escape),
+                    }
+                    self.advance().unwrap();
+                }
+                match char::from_u32(code) {
+                    Some(ch) if ch.is_ascii() => ch,
+                    _ => return Err(invalid_escape),
+                }
+            }
+            Some('u') => {
+                if self.peek(0) != Some('{') {
+                    return Err(invalid_escape);
+                }
+                self.advance().unwrap();
+                let mut code = 0u32;
+                let mut length = 0;
+                while let Some(digit) = self.peek(0).and_then(|ch| ch.to_digit(16)) {
+                    code = code.saturating_mul(16).saturating_add(digit);
+                    length += 1;
+                    self.advance().unwrap();
+                }
+                if length == 0 || length > 6 || self.peek(0) != Some('}') {
+                    return Err(invalid_escape);
+                }
+                self.advance().unwrap();
+                char::from_u32(code).ok_or(invalid_escape)?
+            }
+            Some(_) => return Err(invalid_escape),
+            None => return Err(LexerError::UnexpectedEndOfSource(self.line, self.column)),
         };
+        Ok(ch)
+    }
 
-        Ok(Token {
-            value: TokenValue::Punct(first, kind),
-            span,
-        })
+    /// Checks whether the source at current position starts with a multi-character operator
+    fn starts_operator(&mut self, first: char) -> bool {
+        let second = self.peek(1);
+        self.get_operator(first, second).is_some()
+    }
+
+    /// Returns current token when it is a multi-character operator
+    fn match_operator(&mut self) -> LexerResult<Token<'a>> {
+        let handle = self.begin_span();
+        let first = self.advance().unwrap();
+        let second = self.advance();
+        let op = self
+            .get_operator(first, second)
+            .expect("operator checked by the caller");
+        Ok(Token::new(TokenValue::Op(op), handle.get_span(self)))
+    }
+
+    /// Returns current token when it is built of a single punctuation mark
+    fn match_punct(&mut self, first: char) -> LexerResult<Token<'a>> {
+        let handle = self.begin_span();
+        self.advance().unwrap();
+        Ok(Token::new(TokenValue::Punct(first), handle.get_span(self)))
     }
 
     fn match_end_of_source(&mut self) -> LexerResult<Token<'a>> {
-        Ok(Token {
-            value: TokenValue::None,
-            span: self.begin_span().get_span(self),
-        })
+        Ok(Token::new(TokenValue::None, self.begin_span().get_span(self)))
     }
 
     /// Returns current token when it is a number
     fn match_number(&mut self) -> LexerResult<Token<'a>> {
         let handle = self.begin_span();
-        let mut is_floating = false;
-        self.advance_while_digits();
-        if let Some('.') = self.peek(0) {
+        let invalid_number = LexerError::InvalidNumber(self.line, self.column);
+        let radix = match (self.peek(0), self.peek(1)) {
+            (Some('0'), Some('x')) => 16,
+            (Some('0'), Some('o')) => 8,
+            (Some('0'), Some('b')) => 2,
+            _ => 10,
+        };
+        if radix != 10 {
+            self.advance().unwrap();
             self.advance().unwrap();
-            self.advance_while_digits();
-            is_floating = true;
         }
-        let has_exponent = match self.peek(0) {
-            Some('e' | 'E') => {
+
+        let digits_start = self.position;
+        let mut is_floating = false;
+        self.advance_while_digits(radix);
+        if radix == 10 {
+            // A dot not followed by a digit is not a part of the number, eg. in `1..2`
+            if let (Some('.'), Some('0'..='9')) = (self.peek(0), self.peek(1)) {
                 self.advance().unwrap();
-                true
+                self.advance_while_digits(radix);
+                is_floating = true;
             }
-            _ => false,
-        };
-        if has_exponent {
-            match self.peek(0) {
-                Some('+' | '-') => {
+            if let Some('e' | 'E') = self.peek(0) {
+                self.advance().unwrap();
+                if let Some('+' | '-') = self.peek(0) {
                     self.advance().unwrap();
                 }
-                _ => (),
+                self.advance_while_digits(radix);
+                is_floating = true;
             }
-            self.advance_while_digits();
-            is_floating = true;
+        }
+        let digits = self.source[digits_start..self.position].replace('_', "");
+
+        let suffix_start = self.position;
+        while let Some(ch) = self.peek(0) {
+            if self.can_be_in_identifier(ch) {
+                self.advance().unwrap();
+            } else {
+                break;
+            }
+        }
+        let suffix = match &self.source[suffix_start..self.position] {
+            "" => None,
+            "i32" => Some(NumberSuffix::I32),
+            "u32" => Some(NumberSuffix::U32),
+            "f32" => Some(NumberSuffix::F32),
+            _ => return Err(invalid_number),
         };
 
-        let span = handle.get_span(self);
-        let value = if is_floating {
-            let parsed = span.as_slice().parse().expect("valid float");
-            TokenValue::FloatingNumber(parsed)
-        } else {
-            let parsed = span.as_slice().parse().expect("valid integer");
-            TokenValue::IntegralNumber(parsed)
+        let value = match suffix {
+            Some(NumberSuffix::I32 | NumberSuffix::U32) if is_floating => {
+                return Err(invalid_number);
+            }
+            Some(NumberSuffix::F32) if radix != 10 => return Err(invalid_number),
+            Some(NumberSuffix::F32) => {
+                let parsed = digits.parse().map_err(|_| invalid_number)?;
+                TokenValue::FloatingNumber(parsed, suffix)
+            }
+            _ if is_floating => {
+                let parsed = digits.parse().map_err(|_| invalid_number)?;
+                TokenValue::FloatingNumber(parsed, suffix)
+            }
+            _ => {
+                let parsed = i64::from_str_radix(&digits, radix).map_err(|_| invalid_number)?;
+                // Value has to fit the type given by the suffix, it is never negative as the sign is an operator
+                let max = match suffix {
+                    Some(NumberSuffix::I32) => i32::MAX.into(),
+                    Some(NumberSuffix::U32) => u32::MAX.into(),
+                    _ => i64::MAX,
+                };
+                if parsed > max {
+                    return Err(invalid_number);
+                }
+                TokenValue::IntegralNumber(parsed, suffix)
+            }
         };
 
-        Ok(Token { value, span })
+        Ok(Token::new(value, handle.get_span(self)))
     }
 
-    fn advance_while_digits(&mut self) {
-        #[allow(clippy::while_let_loop)]
-        loop {
-            match self.peek(0) {
-                Some('0'..='9' | '_') => self.advance().unwrap(),
-                _ => break,
-            };
+    /// Advances the iterator past all digits of given radix and separators
+    fn advance_while_digits(&mut self, radix: u32) {
+        while let Some(ch) = self.peek(0) {
+            if ch == '_' || ch.is_digit(radix) {
+                self.advance().unwrap();
+            } else {
+                break;
+            }
         }
     }
 
@@ -494,13 +944,26 @@ impl<'a> Lexer<'a> {
             .next()
     }
 
+    /// Returns an operator when given pair of characters can be one
+    fn get_operator(&self, first: char, second: Option<char>) -> Option<OpKind> {
+        let second = second?;
+        OPERATORS
+            .iter()
+            .filter(|&&(lex, _val)| {
+                let mut chars = lex.chars();
+                chars.next() == Some(first) && chars.next() == Some(second)
+            })
+            .map(|&(_lex, val)| val)
+            .next()
+    }
+
     /// Returns next nth character without advancing the iterator
     #[inline]
     fn peek(&mut self, nth: usize) -> Option<char> {
+        self.ensure_peeked();
         if nth != 0 {
-            unimplemented!();
+            return self.iter.clone().nth(nth - 1).map(|x| x.1);
         }
-        self.ensure_peeked();
         self.peeked.map(|x| x.1)
     }
 
@@ -555,14 +1018,37 @@ impl<'a> Lexer<'a> {
 impl<'a> Iterator for Lexer<'a> {
     type Item = Token<'a>;
 
+    /// Returns next token, with lexical errors turned into error tokens
+    ///
+    /// Error token spans the source consumed while trying to match a token, and at least one
+    /// character, so that iteration can continue past it.
     fn next(&mut self) -> Option<Self::Item> {
-        self.next().ok()
+        let handle = self.begin_span();
+        let trivia = match self.skip_trivia() {
+            Ok(trivia) => trivia,
+            Err(err) => return Some(Token::new(TokenValue::Error(err), handle.get_span(self))),
+        };
+        let handle = self.begin_span();
+        let mut token = match self.match_token() {
+            Ok(token) => token,
+            Err(err) => {
+                if self.position == handle.start_position {
+                    self.advance();
+                }
+                Token::new(TokenValue::Error(err), handle.get_span(self))
+            }
+        };
+        token.trivia = trivia;
+        Some(token)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Keyword, Lexer, LexerError, SourceSpan, Token, TokenType, TokenValue};
+    use super::{
+        Keyword, Lexer, LexerError, NumberSuffix, OpKind, SourceSpan, Token, TokenType, TokenValue,
+        TriviaKind,
+    };
 
     macro_rules! assert_token_type_eq {
         ($actual:expr, $expected:expr, $line:expr, $column:expr) => {
@@ -579,11 +1065,8 @@ mod tests {
             let token = $actual;
             let token = token.as_ref();
             assert_eq!(
-                token,
-                Ok(&Token {
-                    value: $value,
-                    span: SourceSpan::from_str($span, $line, $column),
-                }),
+                token.map(|t| (&t.value, t.span)),
+                Ok((&$value, SourceSpan::from_str($span, $line, $column))),
                 "token value"
             );
             assert_eq!(token.map(|t| t.line()), Ok($line), "line number");
@@ -654,7 +1137,240 @@ mod tests {
 
     #[test]
     fn single_unterminated_string() {
-        let mut lex = Lexer::from_source("\"simple");
-        assert_eq!(lex.next(), Err(LexerError::UnexpectedEndOfSource(1, 8)));
+        let mut lex = Lexer::from_source("  \"simple");
+        assert_eq!(lex.next(), Err(LexerError::UnterminatedString(1, 3)));
+        let mut lex = Lexer::from_source("\"simple\\");
+        assert_eq!(lex.next(), Err(LexerError::UnterminatedString(1, 1)));
+    }
+
+    #[test]
+    fn line_comment() {
+        let mut lex = Lexer::from_source("// note\nif");
+        let token = lex.next().unwrap();
+        assert_eq!(token.get_type(), TokenType::Keyword(Keyword::If));
+        let comments: Vec<_> = token.comments().collect();
+        assert_eq!(comments.len(), 1);
+        assert_eq!(comments[0].kind(), TriviaKind::LineComment);
+        assert_eq!(comments[0].get_span().as_slice(), "// note");
+        assert_eq!(token.trivia()[1].kind(), TriviaKind::Whitespace);
+        assert_eq!(token.trivia()[1].get_span().as_slice(), "\n");
+        assert_eq!((token.line(), token.column()), (2, 1));
+    }
+
+    #[test]
+    fn nested_block_comment() {
+        let mut lex = Lexer::from_source("/* a /* b */ c */ /**/iff");
+        let token = lex.next().unwrap();
+        assert_eq!(token.get_type(), TokenType::Identifier);
+        let comments: Vec<_> = token.comments().map(|t| t.get_span().as_slice()).collect();
+        assert_eq!(comments, ["/* a /* b */ c */", "/**/"]);
+        assert_eq!(token.trivia().len(), 3);
+        assert_eq!(token.column(), 23);
+    }
+
+    #[test]
+    fn trailing_comment_is_attached_to_end_of_source() {
+        let mut lex = Lexer::from_source("( // done");
+        assert_token_type_eq!(lex.next(), TokenType::Punct('('), 1, 1);
+        let token = lex.next().unwrap();
+        assert_eq!(token.get_type(), TokenType::EndOfSource);
+        assert_eq!(token.comments().next().unwrap().get_span().as_slice(), "// done");
+    }
+
+    #[test]
+    fn unterminated_block_comment() {
+        let mut lex = Lexer::from_source("/* a /* b */");
+        assert_eq!(lex.next(), Err(LexerError::UnexpectedEndOfSource(1, 13)));
+    }
+
+    #[test]
+    fn slash_is_not_a_comment() {
+        let mut lex = Lexer::from_source("a / b");
+        assert_token_eq!(lex.next(), TokenValue::Identifier, "a", 1, 1);
+        assert_token_eq!(lex.next(), TokenValue::Punct('/'), "/", 1, 3);
+    }
+
+    #[test]
+    fn operators() {
+        let mut lex = Lexer::from_source("a == b->c<=d");
+        assert_token_eq!(lex.next(), TokenValue::Identifier, "a", 1, 1);
+        assert_token_eq!(lex.next(), TokenValue::Op(OpKind::EqEq), "==", 1, 3);
+        assert_token_eq!(lex.next(), TokenValue::Identifier, "b", 1, 6);
+        assert_token_eq!(lex.next(), TokenValue::Op(OpKind::Arrow), "->", 1, 7);
+        assert_token_eq!(lex.next(), TokenValue::Identifier, "c", 1, 9);
+        assert_token_eq!(lex.next(), TokenValue::Op(OpKind::LessEq), "<=", 1, 10);
+        assert_token_eq!(lex.next(), TokenValue::Identifier, "d", 1, 12);
+    }
+
+    #[test]
+    fn shift_and_bitwise_operators() {
+        let mut lex = Lexer::from_source("a<<b>>c%d^not e");
+        assert_token_eq!(lex.next(), TokenValue::Identifier, "a", 1, 1);
+        assert_token_eq!(lex.next(), TokenValue::Op(OpKind::Shl), "<<", 1, 2);
+        assert_token_eq!(lex.next(), TokenValue::Identifier, "b", 1, 4);
+        assert_token_eq!(lex.next(), TokenValue::Op(OpKind::Shr), ">>", 1, 5);
+        assert_token_eq!(lex.next(), TokenValue::Identifier, "c", 1, 7);
+        assert_token_eq!(lex.next(), TokenValue::Punct('%'), "%", 1, 8);
+        assert_token_eq!(lex.next(), TokenValue::Identifier, "d", 1, 9);
+        assert_token_eq!(lex.next(), TokenValue::Punct('^'), "^", 1, 10);
+        assert_token_eq!(lex.next(), TokenValue::Keyword(Keyword::Not), "not", 1, 11);
+        assert_token_eq!(lex.next(), TokenValue::Identifier, "e", 1, 15);
+    }
+
+    #[test]
+    fn paths_and_match_arms() {
+        let mut lex = Lexer::from_source("A::b=>c: d");
+        assert_token_eq!(lex.next(), TokenValue::Identifier, "A", 1, 1);
+        assert_token_eq!(lex.next(), TokenValue::Op(OpKind::ColonColon), "::", 1, 2);
+        assert_token_eq!(lex.next(), TokenValue::Identifier, "b", 1, 4);
+        assert_token_eq!(lex.next(), TokenValue::Op(OpKind::FatArrow), "=>", 1, 5);
+        assert_token_eq!(lex.next(), TokenValue::Identifier, "c", 1, 7);
+        assert_token_eq!(lex.next(), TokenValue::Punct(':'), ":", 1, 8);
+        assert_token_eq!(lex.next(), TokenValue::Identifier, "d", 1, 10);
+    }
+
+    #[test]
+    fn adjacent_punctuation_is_not_an_operator() {
+        let mut lex = Lexer::from_source("a<-b");
+        assert_token_eq!(lex.next(), TokenValue::Identifier, "a", 1, 1);
+        assert_token_eq!(lex.next(), TokenValue::Punct('<'), "<", 1, 2);
+        assert_token_eq!(lex.next(), TokenValue::Punct('-'), "-", 1, 3);
+        assert_token_eq!(lex.next(), TokenValue::Identifier, "b", 1, 4);
+    }
+
+    #[test]
+    fn operator_is_matched_greedily() {
+        let mut lex = Lexer::from_source("===");
+        assert_token_eq!(lex.next(), TokenValue::Op(OpKind::EqEq), "==", 1, 1);
+        assert_token_eq!(lex.next(), TokenValue::Punct('='), "=", 1, 3);
+    }
+
+    #[test]
+    fn integral_number_radixes_and_separators() {
+        let mut lex = Lexer::from_source("0xff_ff 0o17 0b1010 1_000");
+        assert_token_eq!(lex.next(), TokenValue::IntegralNumber(0xffff, None), "0xff_ff", 1, 1);
+        assert_token_eq!(lex.next(), TokenValue::IntegralNumber(0o17, None), "0o17", 1, 9);
+        assert_token_eq!(lex.next(), TokenValue::IntegralNumber(0b1010, None), "0b1010", 1, 14);
+        assert_token_eq!(lex.next(), TokenValue::IntegralNumber(1000, None), "1_000", 1, 21);
+    }
+
+    #[test]
+    fn number_suffixes() {
+        let mut lex = Lexer::from_source("10u32 3i32 2.5f32 7f32 0x10u32");
+        assert_token_eq!(lex.next(), TokenValue::IntegralNumber(10, Some(NumberSuffix::U32)), "10u32", 1, 1);
+        assert_token_eq!(lex.next(), TokenValue::IntegralNumber(3, Some(NumberSuffix::I32)), "3i32", 1, 7);
+        assert_token_eq!(lex.next(), TokenValue::FloatingNumber(2.5, Some(NumberSuffix::F32)), "2.5f32", 1, 12);
+        assert_token_eq!(lex.next(), TokenValue::FloatingNumber(7.0, Some(NumberSuffix::F32)), "7f32", 1, 19);
+        assert_token_eq!(lex.next(), TokenValue::IntegralNumber(16, Some(NumberSuffix::U32)), "0x10u32", 1, 24);
+    }
+
+    #[test]
+    fn invalid_numbers() {
+        assert_eq!(Lexer::from_source("0x").next(), Err(LexerError::InvalidNumber(1, 1)));
+        assert_eq!(Lexer::from_source(" 12abc").next(), Err(LexerError::InvalidNumber(1, 2)));
+        assert_eq!(Lexer::from_source("1.5u32").next(), Err(LexerError::InvalidNumber(1, 1)));
+        assert_eq!(Lexer::from_source("0x1.5").next().map(|t| t.as_integer()), Ok(Some(1)));
+        assert_eq!(Lexer::from_source("99999999999999999999").next(), Err(LexerError::InvalidNumber(1, 1)));
+        assert_eq!(Lexer::from_source("4294967296u32").next(), Err(LexerError::InvalidNumber(1, 1)));
+        assert_eq!(Lexer::from_source("2147483648i32").next(), Err(LexerError::InvalidNumber(1, 1)));
+        assert_eq!(Lexer::from_source("3000000000i32").next(), Err(LexerError::InvalidNumber(1, 1)));
+    }
+
+    #[test]
+    fn suffixed_numbers_at_bounds() {
+        let mut lex = Lexer::from_source("4294967295u32 2147483647i32 0xffff_ffffu32");
+        assert_token_eq!(lex.next(), TokenValue::IntegralNumber(4294967295, Some(NumberSuffix::U32)), "4294967295u32", 1, 1);
+        assert_token_eq!(lex.next(), TokenValue::IntegralNumber(2147483647, Some(NumberSuffix::I32)), "2147483647i32", 1, 15);
+        assert_token_eq!(lex.next(), TokenValue::IntegralNumber(0xffff_ffff, Some(NumberSuffix::U32)), "0xffff_ffffu32", 1, 29);
+    }
+
+    #[test]
+    fn dot_after_integer_is_not_a_fraction() {
+        let mut lex = Lexer::from_source("1..2");
+        assert_token_eq!(lex.next(), TokenValue::IntegralNumber(1, None), "1", 1, 1);
+        assert_token_eq!(lex.next(), TokenValue::Punct('.'), ".", 1, 2);
+        assert_token_eq!(lex.next(), TokenValue::Punct('.'), ".", 1, 3);
+        assert_token_eq!(lex.next(), TokenValue::IntegralNumber(2, None), "2", 1, 4);
+    }
+
+    #[test]
+    fn string_escapes() {
+        let mut lex = Lexer::from_source(r#""a\n\t\\\"\'\0\x41\u{1F600}""#);
+        let token = lex.next().unwrap();
+        assert_eq!(token.as_str(), "a\n\t\\\"'\0A\u{1F600}");
+        assert_token_type_eq!(lex.next(), TokenType::EndOfSource, 1, 29);
+    }
+
+    #[test]
+    fn invalid_escapes() {
+        assert_eq!(Lexer::from_source(r#""ab\q""#).next(), Err(LexerError::InvalidEscape(1, 4)));
+        assert_eq!(Lexer::from_source(r#""\x80""#).next(), Err(LexerError::InvalidEscape(1, 2)));
+        assert_eq!(Lexer::from_source(r#""\u{}""#).next(), Err(LexerError::InvalidEscape(1, 2)));
+        assert_eq!(Lexer::from_source(r#""\u{D800}""#).next(), Err(LexerError::InvalidEscape(1, 2)));
+    }
+
+    #[test]
+    fn char_literals() {
+        let mut lex = Lexer::from_source(r"'a' '\n' '\'' 'ł'");
+        assert_token_eq!(lex.next(), TokenValue::Char('a'), "'a'", 1, 1);
+        assert_token_eq!(lex.next(), TokenValue::Char('\n'), r"'\n'", 1, 5);
+        assert_token_eq!(lex.next(), TokenValue::Char('\''), r"'\''", 1, 10);
+        assert_token_eq!(lex.next(), TokenValue::Char('ł'), "'ł'", 1, 15);
+    }
+
+    #[test]
+    fn invalid_char_literals() {
+        assert_eq!(Lexer::from_source("''").next(), Err(LexerError::InvalidCharLiteral(1, 1)));
+        assert_eq!(Lexer::from_source(" 'ab'").next(), Err(LexerError::InvalidCharLiteral(1, 2)));
+        assert_eq!(Lexer::from_source("'1").next(), Err(LexerError::UnexpectedEndOfSource(1, 3)));
+    }
+
+    #[test]
+    fn labels() {
+        let mut lex = Lexer::from_source("'outer: loop { break 'outer; } 'a'");
+        assert_token_eq!(lex.next(), TokenValue::Label, "'outer", 1, 1);
+        assert_token_eq!(lex.next(), TokenValue::Punct(':'), ":", 1, 7);
+        assert_token_eq!(lex.next(), TokenValue::Keyword(Keyword::Loop), "loop", 1, 9);
+        assert_token_eq!(lex.next(), TokenValue::Punct('{'), "{", 1, 14);
+        assert_token_eq!(lex.next(), TokenValue::Keyword(Keyword::Break), "break", 1, 16);
+        assert_token_eq!(lex.next(), TokenValue::Label, "'outer", 1, 22);
+        assert_token_eq!(lex.next(), TokenValue::Punct(';'), ";", 1, 28);
+        assert_token_eq!(lex.next(), TokenValue::Punct('}'), "}", 1, 30);
+        assert_token_eq!(lex.next(), TokenValue::Char('a'), "'a'", 1, 32);
+    }
+
+    #[test]
+    fn unexpected_character() {
+        let mut lex = Lexer::from_source("a\n  § b");
+        assert_token_eq!(lex.next(), TokenValue::Identifier, "a", 1, 1);
+        assert_eq!(lex.next(), Err(LexerError::UnexpectedCharacter('§', 2, 3)));
+    }
+
+    #[test]
+    fn iterator_yields_error_tokens() {
+        let mut lex = Lexer::from_source("a \"b");
+        assert_eq!(Iterator::next(&mut lex).map(|t| t.get_type()), Some(TokenType::Identifier));
+        let token = Iterator::next(&mut lex).unwrap();
+        assert_eq!(token.get_type(), TokenType::Error);
+        assert_eq!(token.as_error(), Some(LexerError::UnterminatedString(1, 3)));
+        assert_eq!(token.get_span().as_slice(), "\"b");
+    }
+
+    #[test]
+    fn iterator_continues_after_error_token() {
+        let mut lex = Lexer::from_source("a § b");
+        let types: Vec<_> = (&mut lex)
+            .take(4)
+            .map(|t| (t.get_type(), t.get_span().as_slice().to_string()))
+            .collect();
+        assert_eq!(
+            types,
+            [
+                (TokenType::Identifier, "a".to_string()),
+                (TokenType::Error, "§".to_string()),
+                (TokenType::Identifier, "b".to_string()),
+                (TokenType::EndOfSource, "".to_string()),
+            ]
+        );
     }
 }
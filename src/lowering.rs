@@ -0,0 +1,723 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::ast;
+use crate::cst::{Node, NodeKind};
+use crate::index_arena::{Handle, IndexArena};
+use crate::lexer::{Keyword, NumberSuffix, OpKind, Span, Token, TokenType};
+
+/// Builds the abstract syntax tree from the concrete syntax tree
+///
+/// Tree is expected to come from the parser, so its shape is not validated again.
+pub(crate) struct Lowering<'arena> {
+    arena: &'arena mut IndexArena<ast::Expr>,
+    /// Name of the module being lowered, unless it is the main file
+    module: Option<String>,
+    /// Names of functions defined at the top level of the module
+    functions: HashSet<String>,
+    /// Names of modules imported by the file
+    imports: HashSet<String>,
+    /// Names of parameters and locals in scope, which shadow functions of the module
+    locals: Vec<String>,
+}
+
+impl<'arena> Lowering<'arena> {
+    pub(crate) fn new(arena: &'arena mut IndexArena<ast::Expr>) -> Self {
+        Self {
+            arena,
+            module: None,
+            functions: HashSet::new(),
+            imports: HashSet::new(),
+            locals: Vec::new(),
+        }
+    }
+
+    /// Qualifies names of top level functions with the name of the module
+    pub(crate) fn in_module(mut self, module: &str) -> Self {
+        self.module = Some(module.to_owned());
+        self
+    }
+
+    /// Lowers all items of the source file, skipping parts that failed to parse
+    pub(crate) fn lower_source_file(&mut self, node: &Node) -> Vec<ast::Item> {
+        for item in node.nodes() {
+            match item.kind() {
+                // Functions implemented by the host are known by the same name in every module
+                NodeKind::Function if self.module.is_some() && item.token(TokenType::Keyword(Keyword::Extern)).is_none() => {
+                    self.functions.insert(Self::name(item));
+                }
+                NodeKind::Import => {
+                    self.imports.insert(module_name(&Self::import_path(item)));
+                }
+                _ => {}
+            }
+        }
+        let mut items = self.scoped([], |this| this.lower_block_items(node));
+        for item in &mut items {
+            if let ast::ItemKind::Function { name, .. } = &mut item.kind {
+                *name = self.qualified(name);
+            }
+        }
+        items
+    }
+
+    /// Returns the name the function defined in the module being lowered is known by
+    fn qualified(&self, name: &str) -> String {
+        match &self.module {
+            Some(module) if self.functions.contains(name) && !self.locals.iter().any(|it| it == name) => {
+                format!("{}::{}", module, name)
+            }
+            _ => name.to_owned(),
+        }
+    }
+
+    /// Lowers part of the tree with the names bound in it, which go out of scope afterwards
+    fn scoped<T>(&mut self, names: impl IntoIterator<Item = String>, lower: impl FnOnce(&mut Self) -> T) -> T {
+        let len = self.locals.len();
+        self.locals.extend(names);
+        let lowered = lower(self);
+        self.locals.truncate(len);
+        lowered
+    }
+
+    fn lower_block(&mut self, node: &Node) -> Vec<ast::Item> {
+        // Functions nested in the block shadow the ones of the module
+        let functions = node.nodes().filter(|it| it.kind() == NodeKind::Function).map(Self::name);
+        self.scoped(functions, |this| this.lower_block_items(node))
+    }
+
+    fn lower_block_items(&mut self, node: &Node) -> Vec<ast::Item> {
+        let mut nodes: Vec<_> = node.nodes().filter(|it| it.kind() != NodeKind::Error).collect();
+        let tail = match nodes.last() {
+            Some(last) if Self::has_value(last) => nodes.pop(),
+            _ => None,
+        };
+        let mut items: Vec<_> = nodes.into_iter().map(|it| self.lower_item(it)).collect();
+        items.extend(tail.map(|it| self.lower_tail(it)));
+        items
+    }
+
+    fn lower_tail(&mut self, node: &Node) -> ast::Item {
+        ast::Item {
+            kind: ast::ItemKind::Tail {
+                expr: self.lower_expr_ref(node),
+            },
+            span: Self::span(node),
+        }
+    }
+
+    /// Checks whether the node ending a block gives the value of the block
+    ///
+    /// `if` and `match` give a value only when all of their arms do and `loop` only when it is broken out of
+    /// with a value, otherwise they are statements.
+    fn has_value(node: &Node) -> bool {
+        match node.kind() {
+            NodeKind::Loop => Self::breaks_with_value(node),
+            NodeKind::Match => node.nodes().skip(1).all(|arm| {
+                let body = arm.nodes().last().unwrap();
+                match body.kind() {
+                    NodeKind::Block => body.nodes().last().is_some_and(Self::has_value),
+                    _ => true,
+                }
+            }),
+            // Arm leaving the function or the loop gives no value, but does not prevent the other one from giving it
+            NodeKind::If => {
+                let arms: Vec<_> = node.nodes().skip(1).collect();
+                let gives_value = |arm: &&Node| match arm.kind() {
+                    NodeKind::Block => arm.nodes().last().is_some_and(Self::has_value),
+                    _ => Self::has_value(arm),
+                };
+                let diverges = |arm: &&Node| {
+                    arm.kind() == NodeKind::Block
+                        && arm.nodes().last().is_some_and(|it| {
+                            matches!(it.kind(), NodeKind::Return | NodeKind::Break | NodeKind::Continue)
+                        })
+                };
+                arms.len() == 2
+                    && arms.iter().all(|arm| gives_value(arm) || diverges(arm))
+                    && arms.iter().any(gives_value)
+            }
+            kind => kind.is_expr(),
+        }
+    }
+
+    /// Checks whether the loop is broken out of with a value, outside of nested functions
+    ///
+    /// Within nested loops only the breaks labeled with the label of the loop leave it.
+    fn breaks_with_value(node: &Node) -> bool {
+        fn visit(node: &Node, label: Option<&str>, nested: bool) -> bool {
+            node.nodes().any(|it| match it.kind() {
+                NodeKind::Break => {
+                    it.exprs().next().is_some()
+                        && match Lowering::label(it) {
+                            Some(target) => Some(&*target) == label,
+                            None => !nested,
+                        }
+                }
+                NodeKind::Function | NodeKind::Closure => false,
+                NodeKind::Loop | NodeKind::While | NodeKind::ForIn => visit(it, label, true),
+                _ => visit(it, label, nested),
+            })
+        }
+        visit(node, Self::label(node).as_deref(), false)
+    }
+
+    fn lower_item(&mut self, node: &Node) -> ast::Item {
+        ast::Item {
+            kind: self.lower_item_kind(node),
+            span: Self::span(node),
+        }
+    }
+
+    fn lower_item_kind(&mut self, node: &Node) -> ast::ItemKind {
+        match node.kind() {
+            NodeKind::Function => {
+                let params = self.lower_params(node);
+                let names = params.iter().map(|param| param.name.clone());
+                let mut body = self.scoped(names, |this| {
+                    node.node(NodeKind::Block)
+                        .map_or_else(Vec::new, |block| this.lower_block(block))
+                });
+                // Value of the body is the result of the function
+                if let Some(ast::Item { kind, .. }) = body.last_mut() {
+                    if let ast::ItemKind::Tail { expr } = *kind {
+                        *kind = ast::ItemKind::Return(Some(Box::new(expr)));
+                    }
+                }
+                ast::ItemKind::Function {
+                    name: Self::name(node),
+                    attributes: Self::attributes(node),
+                    type_params: Self::type_params(node),
+                    is_extern: node.token(TokenType::Keyword(Keyword::Extern)).is_some(),
+                    params,
+                    ty: node.ty().map_or(ast::Type::Unit, |ty| self.lower_ty(ty)),
+                    body,
+                }
+            }
+            NodeKind::Struct => ast::ItemKind::Struct {
+                name: Self::name(node),
+                attributes: Self::attributes(node),
+                type_params: Self::type_params(node),
+                fields: node
+                    .nodes()
+                    .filter(|it| it.kind() == NodeKind::Field)
+                    .map(|field| ast::Field {
+                        name: Self::name(field),
+                        r#type: self.lower_ty(field.ty().unwrap()),
+                    })
+                    .collect(),
+            },
+            NodeKind::Enum => ast::ItemKind::Enum {
+                name: Self::name(node),
+                variants: node
+                    .nodes()
+                    .map(|variant| ast::Variant {
+                        name: Self::name(variant),
+                        types: variant.nodes().map(|ty| self.lower_ty(ty)).collect(),
+                    })
+                    .collect(),
+            },
+            NodeKind::Match => ast::ItemKind::Expr {
+                expr: self.lower_expr_ref(node),
+            },
+            NodeKind::Import => ast::ItemKind::Import {
+                path: Self::import_path(node),
+            },
+            NodeKind::Impl => ast::ItemKind::Impl {
+                name: Self::name(node),
+                functions: node.nodes().map(|function| self.lower_item(function)).collect(),
+            },
+            NodeKind::Const => ast::ItemKind::Const {
+                name: Self::name(node),
+                r#type: self.lower_ty(node.ty().unwrap()),
+                expr: self.lower_expr_ref(Self::expr(node)),
+            },
+            NodeKind::Static => ast::ItemKind::Static {
+                name: Self::name(node),
+                mutable: node.token(TokenType::Keyword(Keyword::Mut)).is_some(),
+                r#type: self.lower_ty(node.ty().unwrap()),
+                expr: self.lower_expr_ref(Self::expr(node)),
+            },
+            // Names are bound after lowering the value, which can still refer to what they shadow
+            NodeKind::Let if Self::destructuring(node).is_some() => {
+                let pattern = Self::destructuring(node).unwrap();
+                let r#type = node.ty().map(|ty| self.lower_ty(ty));
+                let expr = self.lower_expr_ref(Self::expr(node));
+                self.locals.extend(Self::bound_names(&pattern));
+                ast::ItemKind::LetPattern { pattern, r#type, expr }
+            }
+            NodeKind::Let => {
+                let r#type = node.ty().map(|ty| self.lower_ty(ty));
+                let expr = node.exprs().next().map(|expr| self.lower_expr_ref(expr));
+                self.locals.push(Self::name(node));
+                ast::ItemKind::Let {
+                    name: Self::name(node),
+                    r#type,
+                    expr,
+                }
+            }
+            NodeKind::Assignment => {
+                let operator = node.tokens().find_map(|token| match token.get_type() {
+                    TokenType::Punct('=') => Some(None),
+                    TokenType::Op(OpKind::PlusEq) => Some(Some(ast::Operator::Add)),
+                    TokenType::Op(OpKind::MinusEq) => Some(Some(ast::Operator::Sub)),
+                    TokenType::Op(OpKind::StarEq) => Some(Some(ast::Operator::Mul)),
+                    TokenType::Op(OpKind::SlashEq) => Some(Some(ast::Operator::Div)),
+                    _ => None,
+                });
+                let mut exprs = node.exprs();
+                ast::ItemKind::Assignment {
+                    lhs: self.lower_expr_ref(exprs.next().unwrap()),
+                    operator: operator.unwrap(),
+                    expr: self.lower_expr_ref(exprs.next().unwrap()),
+                }
+            }
+            NodeKind::ExprStmt => ast::ItemKind::Expr {
+                expr: self.lower_expr_ref(Self::expr(node)),
+            },
+            NodeKind::If => {
+                // Arms follow the condition
+                let mut arms = node.nodes().skip(1);
+                let arm_true = self.lower_block(arms.next().unwrap());
+                let arm_false = arms.next().map(|arm| match arm.kind() {
+                    NodeKind::Block => self.lower_block(arm),
+                    _ => vec![self.lower_item(arm)],
+                });
+                ast::ItemKind::If {
+                    condition: self.lower_expr_ref(Self::expr(node)),
+                    arm_true,
+                    arm_false,
+                }
+            }
+            NodeKind::ForIn => {
+                let expr = self.lower_expr_ref(Self::expr(node));
+                let pattern = Self::destructuring(node);
+                let names = match &pattern {
+                    Some(pattern) => Self::bound_names(pattern),
+                    None => vec![Self::name(node)],
+                };
+                let mut body = self.scoped(names, |this| this.lower_block(node.node(NodeKind::Block).unwrap()));
+                // Each item is bound to a hidden name and then taken apart at the start of the body
+                let name = match pattern {
+                    Some(pattern) => {
+                        let span = Self::span(node);
+                        let name = format!("_item{}", span.start);
+                        let expr = self.arena.alloc_with_span(ast::Expr::Identifier(name.clone()), span);
+                        body.insert(
+                            0,
+                            ast::Item {
+                                kind: ast::ItemKind::LetPattern {
+                                    pattern,
+                                    r#type: None,
+                                    expr,
+                                },
+                                span,
+                            },
+                        );
+                        name
+                    }
+                    None => Self::name(node),
+                };
+                ast::ItemKind::ForIn {
+                    label: Self::label(node),
+                    name,
+                    expr,
+                    body,
+                }
+            }
+            NodeKind::Loop => ast::ItemKind::Loop {
+                label: Self::label(node),
+                body: self.lower_block(node.node(NodeKind::Block).unwrap()),
+            },
+            NodeKind::While => ast::ItemKind::While {
+                label: Self::label(node),
+                condition: self.lower_expr_ref(Self::expr(node)),
+                body: self.lower_block(node.node(NodeKind::Block).unwrap()),
+            },
+            NodeKind::Break => ast::ItemKind::Break {
+                label: Self::label(node),
+                value: node.exprs().next().map(|it| self.lower_expr_ref(it)),
+            },
+            NodeKind::Continue => ast::ItemKind::Continue {
+                label: Self::label(node),
+            },
+            NodeKind::Yield => ast::ItemKind::Yield(Box::new(self.lower_expr_ref(Self::expr(node)))),
+            NodeKind::Return => ast::ItemKind::Return(node.exprs().next().map(|it| Box::new(self.lower_expr_ref(it)))),
+            NodeKind::Assert => ast::ItemKind::Assert(Box::new(self.lower_expr_ref(Self::expr(node)))),
+            kind => unreachable!("{:?} is not an item", kind),
+        }
+    }
+
+    fn lower_expr_ref(&mut self, node: &Node) -> Handle<ast::Expr> {
+        let expr = self.lower_expr(node);
+        self.arena.alloc_with_span(expr, Self::span(node))
+    }
+
+    fn lower_exprs<'a>(&mut self, nodes: impl Iterator<Item = &'a Node<'a>>) -> Vec<(ast::Expr, Span)> {
+        nodes.map(|it| (self.lower_expr(it), Self::span(it))).collect()
+    }
+
+    fn lower_expr(&mut self, node: &Node) -> ast::Expr {
+        match node.kind() {
+            NodeKind::Literal => {
+                let token = node.tokens().next().unwrap();
+                match token.get_type() {
+                    TokenType::IntegralNumber => {
+                        ast::Expr::Integer(token.as_integer().unwrap(), token.number_suffix())
+                    }
+                    TokenType::FloatingNumber => {
+                        ast::Expr::Float(token.as_float().unwrap(), token.number_suffix())
+                    }
+                    // Char stands for its code point
+                    TokenType::Char => ast::Expr::Integer(token.as_char().unwrap() as i64, Some(NumberSuffix::U32)),
+                    TokenType::Keyword(Keyword::True) => ast::Expr::Bool(true),
+                    TokenType::Keyword(Keyword::False) => ast::Expr::Bool(false),
+                    token_type => unreachable!("{:?} is not a literal", token_type),
+                }
+            }
+            NodeKind::NameRef => ast::Expr::Identifier(self.qualified(&Self::name(node))),
+            NodeKind::PathExpr => {
+                let mut names = Self::names(node);
+                match self.imports.contains(&names[0]) {
+                    true => ast::Expr::Identifier(names.join("::")),
+                    false => ast::Expr::Path(names.remove(0), names.remove(0)),
+                }
+            }
+            NodeKind::PrefixExpr => {
+                let op = match node.tokens().next().unwrap().get_type() {
+                    TokenType::Punct('-') => ast::Operator::Negate,
+                    TokenType::Punct('&') => ast::Operator::Ref,
+                    TokenType::Punct('*') => ast::Operator::Deref,
+                    TokenType::Keyword(Keyword::Not) => ast::Operator::Not,
+                    _ => unreachable!(),
+                };
+                ast::Expr::Prefix(op, self.lower_expr_ref(Self::expr(node)))
+            }
+            NodeKind::BinaryExpr => {
+                let op = match node.tokens().next().unwrap().get_type() {
+                    TokenType::Punct('+') => ast::Operator::Add,
+                    TokenType::Punct('-') => ast::Operator::Sub,
+                    TokenType::Punct('*') => ast::Operator::Mul,
+                    TokenType::Punct('/') => ast::Operator::Div,
+                    TokenType::Punct('%') => ast::Operator::Rem,
+                    TokenType::Punct('&') => ast::Operator::BitAnd,
+                    TokenType::Punct('|') => ast::Operator::BitOr,
+                    TokenType::Punct('^') => ast::Operator::BitXor,
+                    TokenType::Op(OpKind::Shl) => ast::Operator::Shl,
+                    TokenType::Op(OpKind::Shr) => ast::Operator::Shr,
+                    TokenType::Keyword(Keyword::And) => ast::Operator::And,
+                    TokenType::Keyword(Keyword::Or) => ast::Operator::Or,
+                    TokenType::Punct('<') => ast::Operator::Less,
+                    TokenType::Punct('>') => ast::Operator::Greater,
+                    TokenType::Op(OpKind::EqEq) => ast::Operator::Equal,
+                    TokenType::Op(OpKind::NotEq | OpKind::LessGreater) => ast::Operator::NotEqual,
+                    TokenType::Op(OpKind::LessEq) => ast::Operator::LessEqual,
+                    TokenType::Op(OpKind::GreaterEq) => ast::Operator::GreaterEqual,
+                    _ => unreachable!(),
+                };
+                let mut exprs = node.exprs();
+                let lhs = self.lower_expr_ref(exprs.next().unwrap());
+                let rhs = self.lower_expr_ref(exprs.next().unwrap());
+                ast::Expr::Infix(op, lhs, rhs)
+            }
+            NodeKind::ParenExpr => self.lower_expr(Self::expr(node)),
+            NodeKind::TupleExpr => {
+                let values = self.lower_exprs(node.exprs());
+                ast::Expr::Tuple(self.arena.alloc_many_with_spans(values))
+            }
+            NodeKind::ArrayExpr => {
+                let values = self.lower_exprs(node.exprs());
+                ast::Expr::Array(self.arena.alloc_many_with_spans(values))
+            }
+            NodeKind::RangeExpr => {
+                let mut exprs = node.exprs();
+                let through = node.token(TokenType::Keyword(Keyword::Through));
+                // Without `to` or `through` the only bound given is the end
+                let start = node
+                    .token(TokenType::Keyword(Keyword::To))
+                    .or(through)
+                    .map(|_| self.lower_expr_ref(exprs.next().unwrap()));
+                let end = self.lower_expr_ref(exprs.next().unwrap());
+                let step = exprs.next().map(|it| self.lower_expr_ref(it));
+                ast::Expr::Range {
+                    start,
+                    end,
+                    step,
+                    inclusive: through.is_some(),
+                }
+            }
+            NodeKind::FieldExpr => {
+                let mut exprs = node.exprs();
+                let lhs = self.lower_expr_ref(exprs.next().unwrap());
+                let field = exprs.next().unwrap();
+                // `t.0.1` is lexed with a floating number, which names fields of the nested tuples
+                match Self::tuple_fields(field) {
+                    Some((outer, inner)) => {
+                        let span = Self::span(field);
+                        let outer = self.arena.alloc_with_span(ast::Expr::Integer(outer, None), span);
+                        let lhs = self.arena.alloc_with_span(ast::Expr::Place(lhs, outer), Self::span(node));
+                        let inner = self.arena.alloc_with_span(ast::Expr::Integer(inner, None), span);
+                        ast::Expr::Place(lhs, inner)
+                    }
+                    None => ast::Expr::Place(lhs, self.lower_expr_ref(field)),
+                }
+            }
+            NodeKind::CallExpr => {
+                let callee = self.lower_expr_ref(Self::expr(node));
+                let args = self.lower_exprs(node.node(NodeKind::ArgList).unwrap().exprs());
+                ast::Expr::Call(callee, self.arena.alloc_many_with_spans(args))
+            }
+            NodeKind::IndexExpr => {
+                let mut exprs = node.exprs();
+                let base = self.lower_expr_ref(exprs.next().unwrap());
+                match node.node(NodeKind::SliceBounds) {
+                    Some(bounds) => {
+                        // A bound is left out when `..` begins or ends the bounds
+                        let is_dot = |token: Option<&Token>| token.and_then(Token::as_punct) == Some('.');
+                        let mut exprs = bounds.exprs();
+                        let start = match is_dot(bounds.first_token()) {
+                            true => None,
+                            false => Some(self.lower_expr_ref(exprs.next().unwrap())),
+                        };
+                        let end = match is_dot(bounds.last_token()) {
+                            true => None,
+                            false => Some(self.lower_expr_ref(exprs.next().unwrap())),
+                        };
+                        ast::Expr::Slice { base, start, end }
+                    }
+                    None => ast::Expr::Index(base, self.lower_expr_ref(exprs.next().unwrap())),
+                }
+            }
+            NodeKind::CastExpr => {
+                let expr = self.lower_expr_ref(Self::expr(node));
+                ast::Expr::Cast(expr, self.lower_ty(node.ty().unwrap()))
+            }
+            NodeKind::Closure => {
+                let params = self.lower_params(node);
+                let names = params.iter().map(|param| param.name.clone());
+                ast::Expr::Closure {
+                    ty: node.ty().map(|ty| self.lower_ty(ty)),
+                    body: self.scoped(names, |this| this.lower_expr_ref(node.nodes().last().unwrap())),
+                    params,
+                }
+            }
+            NodeKind::StructLiteral => {
+                let name = Self::expr(node);
+                let name = match name.kind() {
+                    NodeKind::NameRef => Some(Self::name(name)),
+                    _ => None,
+                };
+                let fields = node
+                    .nodes()
+                    .filter(|it| it.kind() == NodeKind::FieldInit)
+                    .map(|field| (Self::name(field), self.lower_expr_ref(Self::expr(field))))
+                    .collect();
+                ast::Expr::StructLiteral(name, fields)
+            }
+            NodeKind::If => {
+                let mut arms = node.nodes().skip(1);
+                let arm_true = self.lower_block(arms.next().unwrap());
+                let arm_false = arms.next().map(|arm| match arm.kind() {
+                    NodeKind::Block => self.lower_block(arm),
+                    _ => vec![self.lower_tail(arm)],
+                });
+                ast::Expr::If {
+                    condition: self.lower_expr_ref(Self::expr(node)),
+                    arm_true,
+                    arm_false,
+                }
+            }
+            NodeKind::Block => ast::Expr::Block(self.lower_block(node)),
+            NodeKind::Match => {
+                // Arms follow the scrutinee
+                let arms = node
+                    .nodes()
+                    .skip(1)
+                    .map(|arm| {
+                        let mut nodes = arm.nodes();
+                        let pattern = nodes.next().unwrap();
+                        let pattern = match pattern.kind() {
+                            NodeKind::VariantPattern => {
+                                let mut names = Self::names(pattern);
+                                let (r#type, name) = (names.remove(0), names.remove(0));
+                                ast::Pattern::Variant {
+                                    r#type,
+                                    name,
+                                    bindings: names,
+                                }
+                            }
+                            _ => ast::Pattern::Wildcard,
+                        };
+                        let names = match &pattern {
+                            ast::Pattern::Variant { bindings, .. } => bindings.clone(),
+                            ast::Pattern::Wildcard => vec![],
+                        };
+                        let body = nodes.next().unwrap();
+                        let body = self.scoped(names, |this| match body.kind() {
+                            NodeKind::Block => this.lower_block(body),
+                            _ => vec![this.lower_tail(body)],
+                        });
+                        ast::MatchArm { pattern, body }
+                    })
+                    .collect();
+                ast::Expr::Match {
+                    scrutinee: self.lower_expr_ref(Self::expr(node)),
+                    arms,
+                }
+            }
+            NodeKind::Loop => ast::Expr::Loop {
+                label: Self::label(node),
+                body: self.lower_block(node.node(NodeKind::Block).unwrap()),
+            },
+            kind => unreachable!("{:?} is not an expression", kind),
+        }
+    }
+
+    fn lower_params(&mut self, node: &Node) -> Vec<ast::Argument> {
+        node.node(NodeKind::ParamList)
+            .unwrap()
+            .nodes()
+            .map(|param| ast::Argument {
+                name: Self::name(param),
+                r#type: self.lower_ty(param.ty().unwrap()),
+            })
+            .collect()
+    }
+
+    fn lower_ty(&mut self, node: &Node) -> ast::Type {
+        match node.kind() {
+            NodeKind::NameType => {
+                let args: Vec<_> = node.nodes().map(|ty| self.lower_ty(ty)).collect();
+                match args.is_empty() {
+                    true => ast::Type::Name(Self::name(node)),
+                    false => ast::Type::Generic(Self::name(node), args),
+                }
+            }
+            NodeKind::PointerType => ast::Type::Pointer(Box::new(self.lower_ty(node.ty().unwrap()))),
+            NodeKind::ArrayType => {
+                let ty = Box::new(self.lower_ty(node.ty().unwrap()));
+                match (node.token(TokenType::IntegralNumber), node.token(TokenType::Identifier)) {
+                    (Some(length), _) => ast::Type::Array(length.as_integer().unwrap() as usize, ty),
+                    (_, Some(name)) => ast::Type::ConstArray(name.as_string(), ty),
+                    _ => ast::Type::Slice(ty),
+                }
+            }
+            NodeKind::FnType => {
+                let mut types = node.nodes();
+                let args = match self.lower_ty(types.next().unwrap()) {
+                    ast::Type::Tuple(args) => args,
+                    _ => unreachable!(),
+                };
+                let ret = types.next().map_or(ast::Type::Unit, |it| self.lower_ty(it));
+                ast::Type::Function(args, Box::new(ret))
+            }
+            NodeKind::TupleType => {
+                ast::Type::Tuple(node.nodes().map(|it| self.lower_ty(it)).collect())
+            }
+            NodeKind::RangeType => ast::Type::Range(Box::new(self.lower_ty(node.ty().unwrap()))),
+            kind => unreachable!("{:?} is not a type", kind),
+        }
+    }
+
+    /// Returns location of the node in the source, without its leading trivia
+    fn span(node: &Node) -> Span {
+        node.span().expect("node should contain at least one token").into()
+    }
+
+    /// Returns the first identifier directly within the node
+    fn name(node: &Node) -> String {
+        node.token(TokenType::Identifier).unwrap().as_string()
+    }
+
+    /// Returns the path given to the import
+    fn import_path(node: &Node) -> String {
+        node.token(TokenType::String).unwrap().as_string()
+    }
+
+    /// Returns names of type parameters of the function or the struct
+    fn type_params(node: &Node) -> Vec<String> {
+        node.node(NodeKind::TypeParams).map_or_else(Vec::new, Self::names)
+    }
+
+    /// Returns all identifiers directly within the node
+    fn names(node: &Node) -> Vec<String> {
+        node.tokens()
+            .filter(|it| it.get_type() == TokenType::Identifier)
+            .map(|it| it.as_string())
+            .collect()
+    }
+
+    /// Returns the pattern directly within the node, if the node binds more than a name
+    fn destructuring(node: &Node) -> Option<ast::Destructuring> {
+        if let Some(pattern) = node.node(NodeKind::TuplePattern) {
+            return Some(ast::Destructuring::Tuple(Self::names(pattern)));
+        }
+        let pattern = node.node(NodeKind::StructPattern)?;
+        let fields = pattern
+            .nodes()
+            .map(|field| {
+                let mut names = Self::names(field);
+                let name = names.remove(0);
+                // Field is bound to its own name unless given another one
+                let binding = names.pop().unwrap_or_else(|| name.clone());
+                (name, binding)
+            })
+            .collect();
+        Some(ast::Destructuring::Struct {
+            r#type: Self::name(pattern),
+            fields,
+        })
+    }
+
+    /// Returns the names bound by the pattern
+    fn bound_names(pattern: &ast::Destructuring) -> Vec<String> {
+        match pattern {
+            ast::Destructuring::Tuple(names) => names.clone(),
+            ast::Destructuring::Struct { fields, .. } => fields.iter().map(|(_, binding)| binding.clone()).collect(),
+        }
+    }
+
+    /// Returns the indices of both fields when the node is a floating number such as `0.1`
+    fn tuple_fields(node: &Node) -> Option<(i64, i64)> {
+        let token = node.token(TokenType::FloatingNumber)?;
+        let (outer, inner) = token.as_str().split_once('.')?;
+        if !outer.bytes().chain(inner.bytes()).all(|it| it.is_ascii_digit()) {
+            return None;
+        }
+        Some((outer.parse().ok()?, inner.parse().ok()?))
+    }
+
+    /// Returns attributes annotating the function or the struct
+    fn attributes(node: &Node) -> Vec<ast::Attribute> {
+        node.nodes()
+            .filter(|it| it.kind() == NodeKind::Attribute)
+            .map(|attribute| {
+                // Arguments are the tokens between the parentheses, other than commas
+                let mut tokens = attribute.tokens().skip_while(|it| it.get_type() != TokenType::Identifier);
+                let name = tokens.next().unwrap().as_string();
+                let args = tokens
+                    .filter(|it| !matches!(it.get_type(), TokenType::Punct('(' | ')' | ',' | ']')))
+                    .map(|it| it.as_char().map_or_else(|| it.as_string(), String::from))
+                    .collect();
+                ast::Attribute { name, args }
+            })
+            .collect()
+    }
+
+    /// Returns the label directly within the node, if there is one
+    fn label(node: &Node) -> Option<String> {
+        node.token(TokenType::Label).map(|it| it.as_string())
+    }
+
+    /// Returns the first expression directly within the node
+    fn expr<'a>(node: &'a Node<'a>) -> &'a Node<'a> {
+        node.exprs().next().unwrap()
+    }
+}
+
+/// Returns the name of the module defined by the file at the path, which is its stem
+pub(crate) fn module_name(path: &str) -> String {
+    Path::new(path)
+        .file_stem()
+        .map_or_else(String::new, |it| it.to_string_lossy().into_owned())
+}
@@ -1,28 +1,33 @@
-#![feature(if_let_guard)]
 #![allow(clippy::match_like_matches_macro)]
 #![allow(unused)]
 
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::env;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use indexmap::IndexMap;
 
-use lexer::{Keyword, Lexer, PunctKind, Token, TokenType};
+use lexer::{Keyword, Lexer, Token, TokenType};
 use parser::Parser;
 
 use crate::arena::Arena;
+use crate::host::builtins;
 use crate::ast::Expr::Index;
 use crate::ast::Operator;
 use crate::index_arena::IndexArena;
-use crate::ir::{build_ir, Const, dump_ir, execute_ir, validate_types};
-use crate::type_checking::{Expression, ExprToType, Item, TypeCheckerContext};
+use crate::lowering::module_name;
+use crate::ir::{build_ir, Const, dump_ir, execute_ir, instance_of, validate_types};
+use crate::types::TypeRef;
+use crate::type_checking::{Diagnostic, Expression, ExprToSpan, ExprToType, Item, ItemKind, Severity, TypeCheckerContext};
 
 mod arena;
 mod ast;
+mod cst;
+mod host;
 mod ir;
 mod lexer;
+mod lowering;
 mod multi_peek;
 mod parser;
 mod type_checking;
@@ -38,7 +43,11 @@ fn main() {
         let mut total = 0;
         let mut successful = 0;
         for entry in std::fs::read_dir("tests").unwrap() {
-            if run_test(entry.unwrap().path()) {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|it| it != "ft") {
+                continue;
+            }
+            if run_test(path) {
                 successful += 1;
             }
             total += 1;
@@ -50,83 +59,254 @@ fn main() {
 
 fn run_test(path: impl AsRef<Path>) -> bool {
     let path = path.as_ref();
-    print!(">>> START: {} <<<\n", path.display());
+    println!(">>> START: {} <<<", path.display());
     let res = std::panic::catch_unwind(|| compile_file(path)).unwrap_or(false);
     print!("<<< END <<<");
     res
 }
 
-fn compile_file(path: impl AsRef<Path>) -> bool {
-    let content = std::fs::read(path.as_ref()).unwrap();
-    let content = String::from_utf8(content).unwrap();
+/// Parses the file, preceded by all modules it imports, returning items of each along with its path
+fn load_modules(
+    path: &Path,
+    module: Option<&str>,
+    arena: &mut IndexArena<ast::Expr>,
+    modules: &mut Vec<(PathBuf, Vec<ast::Item>)>,
+    seen: &mut HashSet<PathBuf>,
+) -> bool {
+    // Module imported more than once is loaded only the first time
+    if !seen.insert(path.to_owned()) {
+        return true;
+    }
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) => {
+            println!("{}: {}", path.display(), err);
+            return false;
+        }
+    };
+
+    let mut parser = Parser::new(Lexer::from_source(&content), arena);
+    let (items, errors) = match module {
+        Some(module) => parser.parse_module(module),
+        None => parser.parse(),
+    };
+    if !errors.is_empty() {
+        for err in errors {
+            println!("{}: {:?}", path.display(), err);
+        }
+        return false;
+    }
+
+    for item in &items {
+        if let ast::ItemKind::Import { path: import } = &item.kind {
+            let import_path = path.parent().unwrap_or(Path::new("")).join(import);
+            if !load_modules(&import_path, Some(&module_name(import)), arena, modules, seen) {
+                return false;
+            }
+        }
+    }
+    modules.push((path.to_owned(), items));
+    true
+}
 
-    let lex = Lexer::from_source(&content);
+/// Prints problems found in the file, returning whether any of them is an error
+fn report(path: &Path, diagnostics: &mut Vec<Diagnostic>) -> bool {
+    let mut failed = false;
+    for diagnostic in diagnostics.drain(..) {
+        println!("{}:{}", path.display(), diagnostic);
+        failed |= diagnostic.severity == Severity::Error;
+    }
+    failed
+}
+
+/// Finds which instance of a generic function the expression calls, as its mangled name along with
+/// the name and the type arguments of the generic function
+fn called_instance<'tcx>(expr: &Expression<'_>, type_by_expr: &ExprToType<'tcx>) -> Option<(String, String, Vec<TypeRef<'tcx>>)> {
+    let Expression::Call(fun @ Expression::Identifier(name), args) = expr else {
+        return None;
+    };
+    let (mangled, type_args) = instance_of(name, fun, args, type_by_expr.of(expr), type_by_expr, |ty| ty)?;
+    Some((mangled, name.clone(), type_args))
+}
+
+/// Checks whether the item is a function marked with `#[test]`
+fn is_test(item: &Item) -> bool {
+    match &item.kind {
+        ItemKind::Function { attributes, .. } => ast::attribute(attributes, "test").is_some(),
+        _ => false,
+    }
+}
+
+fn compile_file(path: impl AsRef<Path>) -> bool {
     let arena = Arena::default();
     let mut arena2 = IndexArena::default();
-    let mut parser = Parser::new(lex, &mut arena2);
-    match parser.parse() {
-        Ok(mut items) => {
-            let mut tc_ctx = TypeCheckerContext {
-                arena: &arena,
-                locals: HashMap::new(),
-                defined_types: HashMap::new(),
-                exprs: &Arena::default(),
-                ast_expr_arena: &arena2,
-                type_by_expr: ExprToType::new(),
-            };
-
-            let items = tc_ctx.infer_types(&mut items, None);
-
-            let mut functions = HashMap::new();
-            let mut asserts = Vec::new();
-            for item in &items {
-                match item {
-                    Item::Function { name, .. } => {
-                        let ir = build_ir(&item, &arena, &tc_ctx.exprs, &mut tc_ctx.type_by_expr)
-                            .unwrap();
-                        validate_types(&ir);
-                        dump_ir(&ir, &mut std::io::stdout()).unwrap();
-                        functions.insert(name.clone(), ir);
-                    }
-                    Item::Assert(expr) => {
-                        asserts.push(expr);
-                    }
-                    _ => (),
+    let mut modules = Vec::new();
+    if !load_modules(path.as_ref(), None, &mut arena2, &mut modules, &mut HashSet::new()) {
+        return false;
+    }
+
+    let host = builtins();
+    let mut tc_ctx = TypeCheckerContext {
+        arena: &arena,
+        locals: HashMap::new(),
+        defined_types: HashMap::new(),
+        type_params: Vec::new(),
+        methods: HashMap::new(),
+        exprs: &Arena::default(),
+        ast_expr_arena: &arena2,
+        type_by_expr: ExprToType::new(),
+        span_by_expr: ExprToSpan::new(),
+        ret_ty: None,
+        hoisted: Vec::new(),
+        loops: Vec::new(),
+        bindings: Vec::new(),
+        captures: None,
+        closures: Vec::new(),
+        globals: HashMap::new(),
+        attributes: HashMap::new(),
+        host: &host,
+        diagnostics: Vec::new(),
+        iterated: false,
+        arithmetic: HashMap::new(),
+        arithmetic_params: Vec::new(),
+        literals: Vec::new(),
+        untyped_lets: Vec::new(),
+    };
+
+    // Only assertions and tests of the file being compiled are run, not of the modules it imports
+    let mut items = Vec::new();
+    let mut failed = false;
+    let (main, imported) = modules.split_last().unwrap();
+    for (module, module_items) in imported {
+        log::debug!("type checking {}", module.display());
+        let typed = tc_ctx.infer_types(module_items, None);
+        failed |= report(module, &mut tc_ctx.diagnostics);
+        items.extend(typed.into_iter().filter(|it| !matches!(it.kind, ItemKind::Assert(_)) && !is_test(it)));
+    }
+    log::debug!("type checking {}", main.0.display());
+    items.extend(tc_ctx.infer_types(&main.1, None));
+    failed |= report(&main.0, &mut tc_ctx.diagnostics);
+    if failed {
+        return false;
+    }
+    items.append(&mut tc_ctx.closures);
+
+    let mut functions = IndexMap::new();
+    let mut generics = HashMap::new();
+    let mut statics = HashMap::new();
+    let mut asserts = Vec::new();
+    let mut tests = Vec::new();
+    for item in &items {
+        match &item.kind {
+            ItemKind::Function {
+                name, type_params, ..
+            } if !type_params.is_empty() => {
+                generics.insert(name.clone(), item);
+            }
+            // Calls of extern functions are dispatched to the host
+            ItemKind::Function { is_extern: true, .. } => (),
+            ItemKind::Function { name, .. } => {
+                let ir = build_ir(item, &[], &arena, tc_ctx.exprs, &mut tc_ctx.type_by_expr)
+                    .unwrap();
+                validate_types(&ir);
+                dump_ir(&ir, &mut std::io::stdout()).unwrap();
+                functions.insert(name.clone(), ir);
+                if is_test(item) {
+                    tests.push((name, item.span));
                 }
             }
+            ItemKind::Static { name, value } => {
+                statics.insert(name.clone(), value.as_const(&tc_ctx.type_by_expr).unwrap());
+            }
+            ItemKind::Assert(expr) => {
+                asserts.push((expr, item.span));
+            }
+            _ => (),
+        }
+    }
+
+    // Generic functions are built once for each distinct set of type arguments they are called with
+    let mut pending: Vec<_> = functions
+        .values()
+        .flat_map(|ir| ir.instances().iter().cloned())
+        .collect();
+    for (assert, _) in &asserts {
+        if let Expression::Infix(Operator::Equal, lhs, _) = assert {
+            pending.extend(called_instance(lhs, &tc_ctx.type_by_expr));
+        }
+    }
+    while let Some((mangled, name, type_args)) = pending.pop() {
+        if functions.contains_key(&mangled) {
+            continue;
+        }
+        let ir = build_ir(generics[&name], &type_args, &arena, tc_ctx.exprs, &mut tc_ctx.type_by_expr)
+            .unwrap();
+        validate_types(&ir);
+        dump_ir(&ir, &mut std::io::stdout()).unwrap();
+        pending.extend(ir.instances().iter().cloned());
+        functions.insert(mangled, ir);
+    }
 
-            let mut success = true;
-            for assert in &asserts {
-                let Expression::Infix(Operator::Equal, lhs, rhs) = assert else {
-                    panic!("not a comparison");
-                };
+    let mut success = true;
+    for (assert, span) in &asserts {
+        let Expression::Infix(Operator::Equal, lhs, rhs) = assert else {
+            panic!("not a comparison");
+        };
 
-                let Expression::Call(fun, args) = lhs else {
-                    panic!("not a call");
-                };
+        let Expression::Call(fun, args) = lhs else {
+            panic!("not a call");
+        };
 
-                let Expression::Identifier(name) = fun else {
-                    panic!("not a function call");
-                };
+        let Expression::Identifier(name) = fun else {
+            panic!("not a function call");
+        };
 
-                let expected = rhs.as_const().unwrap();
-                let args: Vec<_> = args.iter().map(|it| it.as_const().unwrap()).collect();
-                let actual = execute_ir(&functions[name], &args, &functions);
+        let name = match called_instance(lhs, &tc_ctx.type_by_expr) {
+            Some((mangled, ..)) => mangled,
+            None => name.clone(),
+        };
 
-                if expected != actual {
-                    println!("Assertion failed! {:?} {:?}", expected, actual);
-                    success = false;
-                }
-            }
+        let expected = rhs.as_value(&tc_ctx.type_by_expr).unwrap();
+        let args: Vec<_> = args.iter().map(|it| it.as_const(&tc_ctx.type_by_expr).unwrap()).collect();
+        let actual = match functions.get(&name) {
+            Some(ir) => execute_ir(ir, &args, &functions, &mut statics, &host),
+            None => Some(host.get(&name).unwrap().call(&args).into()),
+        };
+        let Some(actual) = actual else {
+            println!("{}:{}: Assertion stopped by a runtime error", path.as_ref().display(), span);
+            success = false;
+            continue;
+        };
 
-            if asserts.is_empty() {
-                println!("no assertions");
-            } else if success {
-                println!("OK");
-                return true;
-            }
+        if expected != actual {
+            println!(
+                "{}:{}: Assertion failed! {:?} {:?}",
+                path.as_ref().display(),
+                span,
+                expected,
+                actual
+            );
+            success = false;
         }
-        Err(e) => println!("{:?}", e),
+    }
+
+    for (name, span) in &tests {
+        let Some(actual) = execute_ir(&functions[*name], &[], &functions, &mut statics, &host) else {
+            println!("{}:{}: Test {} stopped by a runtime error", path.as_ref().display(), span, name);
+            success = false;
+            continue;
+        };
+        if actual.value != Const::Bool(true) {
+            println!("{}:{}: Test {} failed! {:?}", path.as_ref().display(), span, name, actual);
+            success = false;
+        }
+    }
+
+    if asserts.is_empty() && tests.is_empty() {
+        println!("no assertions");
+    } else if success {
+        println!("OK");
+        return true;
     }
     false
 }
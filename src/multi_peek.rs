@@ -1,5 +1,5 @@
 pub(crate) struct MultiPeek<T, I: Iterator<Item = T>> {
-    peeked: [Option<T>; 2],
+    peeked: [Option<T>; 3],
     index: usize,
     length: usize,
     input: I,
@@ -8,7 +8,7 @@ pub(crate) struct MultiPeek<T, I: Iterator<Item = T>> {
 impl<T, I: Iterator<Item = T>> MultiPeek<T, I> {
     pub(crate) fn new(input: I) -> Self {
         MultiPeek {
-            peeked: [None, None],
+            peeked: [None, None, None],
             index: 0,
             length: 0,
             input,
@@ -32,9 +32,9 @@ impl<T, I: Iterator<Item = T>> MultiPeek<T, I> {
         item
     }
 
-    /// Ensures that next item (if any) is taken from input
+    /// Ensures that items up to the offset (if any) are taken from input
     fn ensure_peeked(&mut self, offset: usize) {
-        if self.length <= offset {
+        while self.length <= offset {
             self.peeked[(self.index + self.length) % self.peeked.len()] = self.input.next();
             self.length += 1;
         }
@@ -1,20 +1,33 @@
 use std::fmt;
 
-use crate::arena::Arena;
 use crate::ast;
-use crate::index_arena::{Handle, IndexArena};
+use crate::cst::{self, Checkpoint, NodeKind, TreeBuilder};
+use crate::index_arena::IndexArena;
+use crate::lexer::{LexerError, OpKind};
+use crate::lowering::Lowering;
 use crate::multi_peek::MultiPeek;
 
-use super::{Keyword, Lexer, PunctKind, Token, TokenType};
+use super::{Keyword, Lexer, Token, TokenType};
 
 pub struct Parser<'lex, 'arena> {
     peek: MultiPeek<Token<'lex>, Lexer<'lex>>,
+    builder: TreeBuilder<'lex>,
+    /// Errors reported so far, parsing continues after each of them
+    errors: Vec<ParseError>,
+    /// Number of tokens consumed so far
+    consumed: usize,
+    /// Whether `{` can start a struct literal, disabled in conditions where it opens the body
+    struct_literals: bool,
+    /// Whether `<` after a type name opens its type arguments, disabled in casts where it compares
+    type_args: bool,
+    /// Whether `>` closing type arguments was already consumed as a part of `>>`
+    split_shr: bool,
     arena: &'arena mut IndexArena<ast::Expr>,
 }
 
 pub enum ParseError {
     UnexpectedToken(TokenType, usize, usize, Option<TokenType>),
-    Custom(&'static str),
+    Lexer(LexerError),
 }
 
 impl fmt::Debug for ParseError {
@@ -28,7 +41,7 @@ impl fmt::Debug for ParseError {
                 "Unexpected {:?} at {}:{}, expected {:?}",
                 actual, line, column, expected
             )?,
-            ParseError::Custom(msg) => write!(f, "{}", msg)?,
+            ParseError::Lexer(err) => write!(f, "{}", err)?,
         }
         Ok(())
     }
@@ -36,537 +49,994 @@ impl fmt::Debug for ParseError {
 
 type ParseResult<T> = Result<T, ParseError>;
 
+/// Binding power of `as`, tighter than any infix operator
+const CAST_BINDING_POWER: u8 = 19;
+/// Binding power of operand of prefix operators, so that they apply before casts
+const PREFIX_BINDING_POWER: u8 = 21;
+/// Binding power of field access, calls, struct literals and indexing
+const POSTFIX_BINDING_POWER: u8 = 23;
+
 impl<'lex, 'arena> Parser<'lex, 'arena> where 'lex: 'arena {
     pub(crate) fn new(lex: Lexer<'lex>, arena: &'arena mut IndexArena<ast::Expr>) -> Parser<'lex, 'arena> {
         Parser {
             peek: MultiPeek::new(lex),
+            builder: TreeBuilder::new(),
+            errors: vec![],
+            consumed: 0,
+            struct_literals: true,
+            type_args: true,
+            split_shr: false,
             arena,
         }
     }
 
-    pub(crate) fn parse(&mut self) -> ParseResult<Vec<ast::Item>> {
-        let mut items = vec![];
+    /// Parses the whole source, returning items that were parsed successfully and all errors
+    pub(crate) fn parse(&mut self) -> (Vec<ast::Item>, Vec<ParseError>) {
+        let (tree, errors) = self.parse_tree();
+        (Lowering::new(self.arena).lower_source_file(&tree), errors)
+    }
+
+    /// Parses the source of an imported module, its functions are named `module::function`
+    pub(crate) fn parse_module(&mut self, module: &str) -> (Vec<ast::Item>, Vec<ParseError>) {
+        let (tree, errors) = self.parse_tree();
+        (Lowering::new(self.arena).in_module(module).lower_source_file(&tree), errors)
+    }
+
+    /// Parses the whole source into a lossless syntax tree, returning it along with all errors
+    ///
+    /// Parts of the source that could not be parsed end up in error nodes.
+    pub(crate) fn parse_tree(&mut self) -> (cst::Node<'lex>, Vec<ParseError>) {
+        self.builder.start_node(NodeKind::SourceFile);
         loop {
+            let checkpoint = self.builder.checkpoint();
+            let (depth, consumed) = (self.builder.depth(), self.consumed);
             let token = self.peek(0);
             let item = match token.get_type() {
-                TokenType::Keyword(Keyword::Extern) => self.parse_fn(true),
-                TokenType::Keyword(Keyword::Fn) => self.parse_fn(false),
-                TokenType::Keyword(Keyword::Struct) => self.parse_struct(),
+                TokenType::Keyword(Keyword::Extern) => self.parse_fn(checkpoint, true),
+                TokenType::Keyword(Keyword::Fn) => self.parse_fn(checkpoint, false),
+                TokenType::Keyword(Keyword::Struct) => self.parse_struct(checkpoint),
+                TokenType::Punct('#') => self.parse_attributed(checkpoint),
+                TokenType::Keyword(Keyword::Impl) => self.parse_impl(),
+                TokenType::Keyword(Keyword::Enum) => self.parse_enum(),
                 TokenType::Keyword(Keyword::Assert) => self.parse_assert(),
+                TokenType::Keyword(Keyword::Import) => self.parse_import(),
+                TokenType::Keyword(Keyword::Const) => self.parse_global(NodeKind::Const, Keyword::Const),
+                TokenType::Keyword(Keyword::Static) => self.parse_global(NodeKind::Static, Keyword::Static),
                 TokenType::EndOfSource => break,
-                token_type => unimplemented!("{:?}", token_type),
+                _ => Err(self.unexpected()),
             };
-            items.push(item?);
+            if let Err(err) = item {
+                self.recover(err, checkpoint, depth, consumed, true);
+            }
         }
-        Ok(items)
+        // End of source carries the trivia that trails the last item
+        self.advance();
+        self.builder.finish_node();
+        let tree = std::mem::replace(&mut self.builder, TreeBuilder::new()).finish();
+        (tree, std::mem::take(&mut self.errors))
     }
 
-    fn parse_stmts(&mut self) -> ParseResult<Vec<ast::Item>> {
-        let mut items = vec![];
+    /// Reports an error and skips tokens up to a point where parsing can be resumed
+    ///
+    /// Nodes left unfinished by the failed item are wrapped together with the skipped tokens in
+    /// an error node. Skipping stops after `;`, before `}` closing the enclosing block or before
+    /// a keyword starting an item, and always moves past the token the item started with.
+    fn recover(
+        &mut self,
+        err: ParseError,
+        checkpoint: Checkpoint,
+        depth: usize,
+        consumed: usize,
+        top_level: bool,
+    ) {
+        let err = self.lexer_error().unwrap_or(err);
+        self.errors.push(err);
+        self.builder.finish_nodes_until(depth);
+        self.builder.start_node_at(checkpoint, NodeKind::Error);
+
+        let token_type = self.peek(0).get_type();
+        if self.consumed == consumed
+            && token_type != TokenType::EndOfSource
+            && (top_level || token_type != TokenType::Punct('}'))
+        {
+            self.bump();
+        }
+
+        let mut nesting = 0;
         loop {
+            match self.peek(0).get_type() {
+                TokenType::EndOfSource => break,
+                TokenType::Punct('{') => nesting += 1,
+                TokenType::Punct('}') if nesting > 0 => nesting -= 1,
+                TokenType::Punct('}') => {
+                    if top_level {
+                        self.bump();
+                    }
+                    break;
+                }
+                TokenType::Punct(';') if nesting == 0 => {
+                    self.bump();
+                    break;
+                }
+                TokenType::Keyword(keyword) if nesting == 0 && Self::starts_item(keyword) => break,
+                _ => {}
+            }
+            self.bump();
+        }
+        self.builder.finish_node();
+    }
+
+    /// Checks whether given keyword can start an item or a statement
+    fn starts_item(keyword: Keyword) -> bool {
+        match keyword {
+            Keyword::Fn
+            | Keyword::Extern
+            | Keyword::Struct
+            | Keyword::Impl
+            | Keyword::Enum
+            | Keyword::Assert
+            | Keyword::Import
+            | Keyword::Const
+            | Keyword::Static
+            | Keyword::Let
+            | Keyword::If
+            | Keyword::Match
+            | Keyword::For
+            | Keyword::Loop
+            | Keyword::While
+            | Keyword::Yield
+            | Keyword::Return
+            | Keyword::Break
+            | Keyword::Continue => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the lexical error that stopped parsing, if any
+    ///
+    /// Parser can't move past a token with lexical error, so any error reported while that token
+    /// is next is caused by the lexer rather than by the syntax.
+    fn lexer_error(&mut self) -> Option<ParseError> {
+        self.peek(0).as_error().map(ParseError::Lexer)
+    }
+
+    fn parse_stmts(&mut self) -> ParseResult<()> {
+        loop {
+            let checkpoint = self.builder.checkpoint();
+            let (depth, consumed) = (self.builder.depth(), self.consumed);
             let token = self.peek(0);
-            let item = match token.get_type() {
+            let stmt = match token.get_type() {
                 TokenType::Keyword(Keyword::Let) => self.parse_let(),
                 TokenType::Keyword(Keyword::Loop) => self.parse_loop(),
+                TokenType::Keyword(Keyword::While) => self.parse_while(),
                 TokenType::Keyword(Keyword::For) => self.parse_for(),
-                TokenType::Keyword(Keyword::Extern) => self.parse_fn(true),
-                TokenType::Keyword(Keyword::Fn) => self.parse_fn(false),
+                TokenType::Keyword(Keyword::Extern) => self.parse_fn(checkpoint, true),
+                TokenType::Keyword(Keyword::Fn) => self.parse_fn(checkpoint, false),
                 TokenType::Keyword(Keyword::If) => self.parse_if(),
+                TokenType::Keyword(Keyword::Match) => self.parse_match(),
                 TokenType::Keyword(Keyword::Yield) => self.parse_yield(),
                 TokenType::Keyword(Keyword::Return) => self.parse_return(),
                 TokenType::Keyword(Keyword::Assert) => self.parse_assert(),
-                TokenType::Keyword(Keyword::Break) => {
+                TokenType::Keyword(Keyword::Break) => self.parse_break(),
+                TokenType::Keyword(Keyword::Continue) => {
+                    self.builder.start_node(NodeKind::Continue);
                     self.advance();
+                    self.match_label();
                     self.expect_one(';')?;
-                    Ok(ast::Item::Break)
-                }
-                TokenType::Punct('*') => {
-                    let lhs = self
-                        .parse_expr_opt(0)?
-                        .ok_or(ParseError::Custom("expected expression"))?;
-                    self.parse_assign_or_expr(lhs)
+                    self.builder.finish_node();
+                    Ok(())
                 }
-                TokenType::Identifier => {
-                    let lhs = self
-                        .parse_expr_opt(0)?
-                        .unwrap_or_else(|| ast::Expr::Identifier(token.as_string()));
-                    self.parse_assign_or_expr(lhs)
+                TokenType::Label => match self.peek(2).get_type() {
+                    TokenType::Keyword(Keyword::Loop) => self.parse_loop(),
+                    TokenType::Keyword(Keyword::While) => self.parse_while(),
+                    TokenType::Keyword(Keyword::For) => self.parse_for(),
+                    _ => Err(self.unexpected()),
+                },
+                TokenType::Punct('*' | '-' | '&' | '|' | '(' | '[' | '{')
+                | TokenType::Identifier
+                | TokenType::IntegralNumber
+                | TokenType::FloatingNumber
+                | TokenType::Char
+                | TokenType::Keyword(Keyword::True | Keyword::False | Keyword::Not | Keyword::Range) => {
+                    self.parse_assign_or_expr()
                 }
-                _ => break,
+                TokenType::Punct('}') | TokenType::EndOfSource => break,
+                _ => Err(self.unexpected()),
             };
-            items.push(item?);
+            if let Err(err) = stmt {
+                self.recover(err, checkpoint, depth, consumed, false);
+            }
         }
-        Ok(items)
+        Ok(())
     }
 
-    fn parse_assign_or_expr(&mut self, lhs: ast::Expr) -> ParseResult<ast::Item> {
-        let lhs = self.arena.alloc(lhs);
-        let item = if self.match_many(['+', '=']) {
-            ast::Item::Assignment {
-                lhs,
-                operator: Some(ast::Operator::Add),
-                expr: self.parse_expr_ref(0)?,
-            }
-        } else if self.match_one('=') {
-            ast::Item::Assignment {
-                lhs,
-                operator: None,
-                expr: self.parse_expr_ref(0)?,
-            }
-        } else {
-            ast::Item::Expr { expr: lhs }
+    fn parse_assign_or_expr(&mut self) -> ParseResult<()> {
+        let checkpoint = self.builder.checkpoint();
+        self.parse_expr(0)?;
+        let is_assignment = match self.peek(0).get_type() {
+            TokenType::Punct('=')
+            | TokenType::Op(OpKind::PlusEq | OpKind::MinusEq | OpKind::StarEq | OpKind::SlashEq) => true,
+            _ => false,
         };
+        if is_assignment {
+            self.builder.start_node_at(checkpoint, NodeKind::Assignment);
+            self.advance();
+            self.parse_expr(0)?;
+        } else if self.peek(0).as_punct() == Some('}') {
+            // Expression ending a block without `;` is the value of the block
+            return Ok(());
+        } else {
+            self.builder.start_node_at(checkpoint, NodeKind::ExprStmt);
+        }
         self.expect_one(';')?;
-        Ok(item)
+        self.builder.finish_node();
+        Ok(())
     }
 
-    fn parse_expr(&mut self, precedence: isize) -> ParseResult<ast::Expr> {
-        self.parse_expr_opt(precedence)?
-            .ok_or(ParseError::Custom("missing expression"))
+    fn parse_expr(&mut self, min_binding_power: u8) -> ParseResult<()> {
+        if self.parse_expr_opt(min_binding_power)? {
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
     }
 
-    fn parse_expr_ref(&mut self, precedence: isize) -> ParseResult<Handle<ast::Expr>> {
-        let expr = self.parse_expr(precedence)?;
-        Ok(self.arena.alloc(expr))
-    }
+    /// Parses an expression if there is one, returns whether anything was parsed
+    ///
+    /// Only operators binding at least as tight as `min_binding_power` become part of the
+    /// expression, looser ones are left for the caller.
+    fn parse_expr_opt(&mut self, min_binding_power: u8) -> ParseResult<bool> {
+        let checkpoint = self.builder.checkpoint();
+        match self.peek(0).get_type() {
+            TokenType::Keyword(Keyword::If) => self.parse_if()?,
+            TokenType::Keyword(Keyword::Match) => self.parse_match()?,
+            TokenType::Keyword(Keyword::Loop) | TokenType::Label => self.parse_loop()?,
+            TokenType::Punct('{') => self.parse_block()?,
+            _ => {
+                if !self.parse_operand()? {
+                    return Ok(false);
+                }
+            }
+        }
 
-    fn parse_expr_opt(&mut self, precedence: isize) -> ParseResult<Option<ast::Expr>> {
-        let token = self.peek(0);
-        let lhs = match token.get_type() {
-            TokenType::Punct('-' | '&' | '*') => {
-                self.advance();
-                let op = match token.get_type() {
-                    TokenType::Punct('-') => ast::Operator::Negate,
-                    TokenType::Punct('&') => ast::Operator::Ref,
-                    TokenType::Punct('*') => ast::Operator::Deref,
+        loop {
+            let token = self.peek(0);
+
+            if self.is_postfix_operator(&token) {
+                if POSTFIX_BINDING_POWER < min_binding_power {
+                    break;
+                }
+                let kind = match token.get_type() {
+                    TokenType::Punct('.') => NodeKind::FieldExpr,
+                    TokenType::Punct('(') => NodeKind::CallExpr,
+                    TokenType::Punct('{') => NodeKind::StructLiteral,
+                    TokenType::Punct('[') => NodeKind::IndexExpr,
                     _ => unreachable!(),
                 };
-                let operand = self.parse_expr_ref(10)?;
-                ast::Expr::Prefix(op, operand)
-            }
-            TokenType::Keyword(Keyword::Range) => {
+                self.builder.start_node_at(checkpoint, kind);
+                match kind {
+                    NodeKind::FieldExpr => {
+                        self.advance();
+                        self.parse_expr(POSTFIX_BINDING_POWER + 1)?;
+                    }
+                    NodeKind::CallExpr => {
+                        self.builder.start_node(NodeKind::ArgList);
+                        self.advance();
+                        self.parse_comma_separated_exprs()?;
+                        self.expect_one(')')?;
+                        self.builder.finish_node();
+                    }
+                    NodeKind::StructLiteral => {
+                        self.advance();
+                        self.parse_comma_separated_field_exprs()?;
+                        self.expect_one('}')?;
+                    }
+                    _ => {
+                        self.advance();
+                        // Bounds of a slice are separated by `..`, either of them can be left out
+                        let bounds = self.builder.checkpoint();
+                        let start = self.parse_expr_opt(0)?;
+                        if self.peek(0).as_punct() == Some('.') && self.peek(1).as_punct() == Some('.') {
+                            self.builder.start_node_at(bounds, NodeKind::SliceBounds);
+                            self.advance();
+                            self.advance();
+                            self.parse_expr_opt(0)?;
+                            self.builder.finish_node();
+                        } else if !start {
+                            return Err(self.unexpected());
+                        }
+                        self.expect_one(']')?;
+                    }
+                }
+            } else if token.get_type() == TokenType::Keyword(Keyword::As) {
+                if CAST_BINDING_POWER < min_binding_power {
+                    break;
+                }
+                self.builder.start_node_at(checkpoint, NodeKind::CastExpr);
                 self.advance();
-                let operand = self.parse_expr_ref(10)?;
-                if self.match_keyword(Keyword::To).is_some() {
-                    let end = self.parse_expr_ref(10)?;
-                    ast::Expr::Range(operand, Some(end))
-                } else {
-                    ast::Expr::Range(operand, None)
+                let type_args = std::mem::replace(&mut self.type_args, false);
+                let result = self.parse_ty();
+                self.type_args = type_args;
+                result?;
+            } else if let Some((left, right)) = Self::infix_binding_power(&token) {
+                if left < min_binding_power {
+                    break;
                 }
+                self.builder.start_node_at(checkpoint, NodeKind::BinaryExpr);
+                self.advance();
+                self.parse_expr(right)?;
+            } else {
+                break;
             }
-            TokenType::Identifier => {
+            self.builder.finish_node();
+        }
+
+        Ok(true)
+    }
+
+    /// Parses an expression without any postfix or infix operators applied to it
+    fn parse_operand(&mut self) -> ParseResult<bool> {
+        let checkpoint = self.builder.checkpoint();
+        match self.peek(0).get_type() {
+            TokenType::Punct('-' | '&' | '*') | TokenType::Keyword(Keyword::Not) => {
+                self.builder.start_node(NodeKind::PrefixExpr);
                 self.advance();
-                ast::Expr::Identifier(token.as_string())
+                self.parse_expr(PREFIX_BINDING_POWER)?;
             }
-            TokenType::IntegralNumber => {
+            TokenType::Keyword(Keyword::Range) => {
+                self.builder.start_node(NodeKind::RangeExpr);
                 self.advance();
-                ast::Expr::Integer(token.as_integer().unwrap())
+                self.parse_expr(PREFIX_BINDING_POWER)?;
+                // `through` includes the end in the range, `to` leaves it out
+                if self.match_keyword(Keyword::To).is_some() || self.match_keyword(Keyword::Through).is_some() {
+                    self.parse_expr(PREFIX_BINDING_POWER)?;
+                }
+                if self.match_keyword(Keyword::Step).is_some() {
+                    self.parse_expr(PREFIX_BINDING_POWER)?;
+                }
             }
-            TokenType::FloatingNumber => {
+            TokenType::Identifier if self.peek(1).as_op() == Some(OpKind::ColonColon) => {
+                self.builder.start_node(NodeKind::PathExpr);
                 self.advance();
-                ast::Expr::Float(token.as_float().unwrap())
+                self.advance();
+                self.expect_identifier()?;
             }
-            TokenType::Keyword(Keyword::True) => {
+            TokenType::Identifier => {
+                self.builder.start_node(NodeKind::NameRef);
                 self.advance();
-                ast::Expr::Bool(true)
             }
-            TokenType::Keyword(Keyword::False) => {
+            TokenType::IntegralNumber
+            | TokenType::FloatingNumber
+            | TokenType::Char
+            | TokenType::Keyword(Keyword::True | Keyword::False) => {
+                self.builder.start_node(NodeKind::Literal);
                 self.advance();
-                ast::Expr::Bool(false)
             }
             TokenType::Punct('(') => {
                 self.advance();
-                let values = self.parse_comma_separated_exprs()?;
+                let count = self.parse_comma_separated_exprs()?;
                 self.expect_one(')')?;
-                match values.len() {
-                    1 => values.into_iter().next().unwrap(),
-                    _ => ast::Expr::Tuple(self.arena.alloc_many(values)),
+                let kind = match count {
+                    1 => NodeKind::ParenExpr,
+                    _ => NodeKind::TupleExpr,
+                };
+                self.builder.start_node_at(checkpoint, kind);
+            }
+            TokenType::Punct('|') => {
+                self.builder.start_node(NodeKind::Closure);
+                self.parse_params('|', '|')?;
+                if self.match_op(OpKind::Arrow) {
+                    self.parse_ty()?;
                 }
+                self.parse_expr(0)?;
             }
             TokenType::Punct('[') => {
+                self.builder.start_node(NodeKind::ArrayExpr);
                 self.advance();
-                let mut values = vec![];
                 loop {
                     if self.match_one(']') {
                         break;
                     }
-                    values.push(self.parse_expr(0)?);
+                    self.parse_expr(0)?;
                     self.match_one(',');
                 }
-                ast::Expr::Array(self.arena.alloc_many(values))
             }
             _other => {
-                return Ok(None);
+                return Ok(false);
             }
         };
+        self.builder.finish_node();
+        Ok(true)
+    }
 
-        let mut expr = lhs;
-        loop {
-            let token = self.peek(0);
+    /// Parses a comma separated list of expressions, returns the number of parsed expressions
+    fn parse_comma_separated_exprs(&mut self) -> ParseResult<usize> {
+        let mut count = 0;
+        while self.parse_expr_opt(0)? {
+            count += 1;
+            if !self.match_one(',') {
+                break;
+            }
+        }
+        Ok(count)
+    }
 
-            let new_precedence = Self::get_precedence(&token);
-            if new_precedence < precedence
-                || new_precedence == precedence && Self::is_left_associative(&token)
-            {
+    fn parse_comma_separated_field_exprs(&mut self) -> ParseResult<()> {
+        while self.peek(0).as_punct() == Some('.') {
+            self.builder.start_node(NodeKind::FieldInit);
+            self.advance();
+            self.expect_identifier()?;
+            self.expect_one('=')?;
+            self.parse_expr(0)?;
+            self.builder.finish_node();
+            if !self.match_one(',') {
                 break;
             }
+        }
+        Ok(())
+    }
 
-            let next = match token.get_type() {
-                TokenType::Punct('+' | '-' | '*' | '/') | TokenType::Keyword(Keyword::And | Keyword::Or) => {
-                    if self.peek(1).get_type() == TokenType::Punct('=') {
-                        break;
-                    }
+    /// Returns left and right binding power of an infix operator
+    ///
+    /// Operators are left associative, the right binding power is always greater than the left.
+    fn infix_binding_power(token: &Token) -> Option<(u8, u8)> {
+        let power = match token.get_type() {
+            TokenType::Keyword(Keyword::Or) => 1,
+            TokenType::Keyword(Keyword::And) => 3,
+            TokenType::Punct('<' | '>')
+            | TokenType::Op(
+                OpKind::EqEq
+                | OpKind::NotEq
+                | OpKind::LessGreater
+                | OpKind::LessEq
+                | OpKind::GreaterEq,
+            ) => 5,
+            TokenType::Punct('|') => 7,
+            TokenType::Punct('^') => 9,
+            TokenType::Punct('&') => 11,
+            TokenType::Op(OpKind::Shl | OpKind::Shr) => 13,
+            TokenType::Punct('+' | '-') => 15,
+            TokenType::Punct('*' | '/' | '%') => 17,
+            _ => return None,
+        };
+        Some((power, power + 1))
+    }
 
-                    let token = self.advance();
-                    let op = match token.get_type() {
-                        TokenType::Punct('+') => ast::Operator::Add,
-                        TokenType::Punct('-') => ast::Operator::Sub,
-                        TokenType::Punct('*') => ast::Operator::Mul,
-                        TokenType::Punct('/') => ast::Operator::Div,
-                        TokenType::Keyword(Keyword::And) => ast::Operator::And,
-                        TokenType::Keyword(Keyword::Or) => ast::Operator::Or,
-                        _ => unreachable!(),
-                    };
-
-                    let rhs = self.parse_expr_ref(new_precedence)?;
-                    ast::Expr::Infix(op, self.arena.alloc(expr), rhs)
-                }
-                TokenType::Punct('.') => {
-                    self.advance();
-                    let rhs = self.parse_expr_ref(new_precedence)?;
-                    ast::Expr::Place(self.arena.alloc(expr), rhs)
+    /// Checks whether token starts a field access, call, struct literal or indexing
+    fn is_postfix_operator(&mut self, token: &Token) -> bool {
+        match token.get_type() {
+            // `..` separates bounds of a slice, it does not access a field
+            TokenType::Punct('.') => self.peek(1).as_punct() != Some('.'),
+            TokenType::Punct('(' | '[') => true,
+            TokenType::Punct('{') => {
+                self.struct_literals && matches!(self.peek(1).as_punct(), Some('.' | '}'))
+            }
+            _ => false,
+        }
+    }
+
+    /// Parses attributes followed by the function or the struct they annotate
+    fn parse_attributed(&mut self, start: Checkpoint) -> ParseResult<()> {
+        while self.peek(0).get_type() == TokenType::Punct('#') {
+            self.parse_attribute()?;
+        }
+        match self.peek(0).get_type() {
+            TokenType::Keyword(Keyword::Extern) => self.parse_fn(start, true),
+            TokenType::Keyword(Keyword::Fn) => self.parse_fn(start, false),
+            TokenType::Keyword(Keyword::Struct) => self.parse_struct(start),
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    /// Parses `#[name]` or `#[name(args)]`, where the arguments are identifiers or literals
+    fn parse_attribute(&mut self) -> ParseResult<()> {
+        self.builder.start_node(NodeKind::Attribute);
+        self.expect_one('#')?;
+        self.expect_one('[')?;
+        self.expect_identifier()?;
+        if self.match_one('(') {
+            loop {
+                if self.match_one(')') {
+                    break;
                 }
-                TokenType::Punct('<' | '>' | '!' | '=') => {
-                    let first = self.peek(0);
-                    let second = self.peek(1);
-                    let is_joint = match first.as_punct() {
-                        Some((_, PunctKind::Joint)) => true,
-                        _ => false,
-                    };
-                    let op = match (first.get_type(), second.get_type(), is_joint) {
-                        (TokenType::Punct('<'), TokenType::Punct('='), true) => ast::Operator::LessEqual,
-                        (TokenType::Punct('<'), TokenType::Punct('>'), true) => ast::Operator::NotEqual,
-                        (TokenType::Punct('>'), TokenType::Punct('='), true) => {
-                            ast::Operator::GreaterEqual
-                        }
-                        (TokenType::Punct('<'), _, _) => ast::Operator::Less,
-                        (TokenType::Punct('>'), _, _) => ast::Operator::Greater,
-                        (TokenType::Punct('='), TokenType::Punct('='), true) => ast::Operator::Equal,
-                        (TokenType::Punct('!'), TokenType::Punct('='), true) => ast::Operator::NotEqual,
-                        (TokenType::Punct('='), _, _) => return Ok(Some(expr)),
-                        _ => {
-                            return Err(ParseError::UnexpectedToken(
-                                second.get_type(),
-                                second.line(),
-                                second.column(),
-                                None,
-                            ));
-                        }
-                    };
-                    if let ast::Operator::Less | ast::Operator::Greater = op {
-                        self.advance();
-                    } else {
-                        self.advance();
+                match self.peek(0).get_type() {
+                    TokenType::Identifier
+                    | TokenType::IntegralNumber
+                    | TokenType::FloatingNumber
+                    | TokenType::Char
+                    | TokenType::String
+                    | TokenType::Keyword(Keyword::True | Keyword::False) => {
                         self.advance();
                     }
-                    let rhs = self.parse_expr_ref(new_precedence)?;
-                    ast::Expr::Infix(op, self.arena.alloc(expr), rhs)
+                    _ => return Err(self.unexpected()),
                 }
-                TokenType::Punct('(') => {
-                    self.advance();
-                    let args = self.parse_comma_separated_exprs()?;
+                if !self.match_one(',') {
                     self.expect_one(')')?;
-                    ast::Expr::Call(self.arena.alloc(expr), self.arena.alloc_many(args))
-                }
-                TokenType::Punct('{') if let Some(('.' | '}', _)) = self.peek(1).as_punct() => {
-                    self.advance();
-                    let args = self.parse_comma_separated_field_exprs()?;
-                    self.expect_one('}')?;
-                    ast::Expr::StructLiteral(expr.as_str().map(String::from), args)
-                }
-                TokenType::Punct('[') => {
-                    self.advance();
-                    let index_expr = self.parse_expr_ref(0)?;
-                    self.expect_one(']')?;
-                    ast::Expr::Index(self.arena.alloc(expr), index_expr)
-                }
-                TokenType::Keyword(Keyword::As) => {
-                    self.advance();
-                    let ty = self.parse_ty()?;
-                    ast::Expr::Cast(self.arena.alloc(expr), ty)
+                    break;
                 }
-                _ => break,
-            };
-            expr = next;
-        }
-
-        Ok(Some(expr))
-    }
-
-    fn parse_comma_separated_exprs(&mut self) -> ParseResult<Vec<ast::Expr>> {
-        let mut values = vec![];
-        while let Some(value) = self.parse_expr_opt(0)? {
-            values.push(value);
-            if !self.match_one(',') {
-                break;
             }
         }
-        Ok(values)
+        self.expect_one(']')?;
+        self.builder.finish_node();
+        Ok(())
     }
 
-    fn parse_comma_separated_field_exprs(&mut self) -> ParseResult<Vec<(String, Handle<ast::Expr>)>> {
-        let mut values = vec![];
-        while self.match_punct(PunctKind::Single, '.') {
-            let field_name = self.expect_identifier()?;
-            self.expect_punct(PunctKind::Single, '=')?;
-            let value = self.parse_expr_ref(0)?;
-            values.push((field_name.as_string(), value));
+    /// Parses a struct, along with the attributes parsed since the checkpoint
+    fn parse_struct(&mut self, start: Checkpoint) -> ParseResult<()> {
+        self.builder.start_node_at(start, NodeKind::Struct);
+        self.expect_keyword(Keyword::Struct)?;
+        self.expect_identifier()?;
+        self.parse_type_params()?;
+        self.expect_one('{')?;
+        while self.peek(0).get_type() == TokenType::Identifier {
+            self.builder.start_node(NodeKind::Field);
+            self.advance();
+            self.expect_one(':')?;
+            self.parse_ty()?;
+            self.builder.finish_node();
             if !self.match_one(',') {
                 break;
             }
         }
-        Ok(values)
-    }
-
-    fn get_precedence(token: &Token) -> isize {
-        match token.get_type() {
-            TokenType::Punct('+') => 1,
-            TokenType::Punct('-') => 1,
-            TokenType::Punct('*') => 2,
-            TokenType::Punct('/') => 3,
-            TokenType::Punct('.') => 4,
-            TokenType::Punct('(') => 5,
-            TokenType::Keyword(Keyword::Or) => 6,
-            TokenType::Keyword(Keyword::And) => 7,
-            _ => 0,
-        }
+        self.expect_one('}')?;
+        self.builder.finish_node();
+        Ok(())
     }
 
-    fn is_left_associative(token: &Token) -> bool {
-        match token.get_type() {
-            TokenType::Punct('-' | '.') => true,
-            _ => false,
+    fn parse_impl(&mut self) -> ParseResult<()> {
+        self.builder.start_node(NodeKind::Impl);
+        self.expect_keyword(Keyword::Impl)?;
+        self.expect_identifier()?;
+        self.expect_one('{')?;
+        loop {
+            let checkpoint = self.builder.checkpoint();
+            match self.peek(0).get_type() {
+                TokenType::Keyword(Keyword::Fn) => self.parse_fn(checkpoint, false)?,
+                TokenType::Punct('#') => self.parse_attributed(checkpoint)?,
+                _ => break,
+            }
         }
+        self.expect_one('}')?;
+        self.builder.finish_node();
+        Ok(())
     }
 
-    fn parse_struct(&mut self) -> ParseResult<ast::Item> {
-        self.expect_keyword(Keyword::Struct)?;
-        let identifier = self.expect_identifier()?.as_string();
+    fn parse_enum(&mut self) -> ParseResult<()> {
+        self.builder.start_node(NodeKind::Enum);
+        self.expect_keyword(Keyword::Enum)?;
+        self.expect_identifier()?;
         self.expect_one('{')?;
-        let mut fields = vec![];
-        while let Some(t) = self.match_identifier() {
-            self.expect_one(':')?;
-            let ty = self.parse_ty()?;
-            fields.push(ast::Field {
-                name: t.as_string(),
-                r#type: ty,
-            });
+        while self.peek(0).get_type() == TokenType::Identifier {
+            self.builder.start_node(NodeKind::Variant);
+            self.advance();
+            if self.match_one('(') {
+                self.parse_ty_tuple()?;
+            }
+            self.builder.finish_node();
             if !self.match_one(',') {
                 break;
             }
         }
         self.expect_one('}')?;
-        Ok(ast::Item::Struct {
-            name: identifier,
-            fields,
-        })
+        self.builder.finish_node();
+        Ok(())
     }
 
-    fn parse_assert(&mut self) -> ParseResult<ast::Item> {
+    fn parse_assert(&mut self) -> ParseResult<()> {
+        self.builder.start_node(NodeKind::Assert);
         self.expect_keyword(Keyword::Assert)?;
-        let value = self.parse_expr_ref(0)?;
+        self.parse_expr(0)?;
+        self.expect_one(';')?;
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    fn parse_import(&mut self) -> ParseResult<()> {
+        self.builder.start_node(NodeKind::Import);
+        self.expect_keyword(Keyword::Import)?;
+        if self.peek(0).get_type() != TokenType::String {
+            return Err(self.expected(TokenType::String));
+        }
+        self.advance();
         self.expect_one(';')?;
-        Ok(ast::Item::Assert(Box::new(value)))
+        self.builder.finish_node();
+        Ok(())
     }
 
-    fn parse_fn(&mut self, is_extern: bool) -> ParseResult<ast::Item> {
+    /// Parses `const` or `static` item, only a static can be marked `mut`
+    fn parse_global(&mut self, kind: NodeKind, keyword: Keyword) -> ParseResult<()> {
+        self.builder.start_node(kind);
+        self.expect_keyword(keyword)?;
+        if kind == NodeKind::Static {
+            self.match_keyword(Keyword::Mut);
+        }
+        self.expect_identifier()?;
+        self.expect_one(':')?;
+        self.parse_ty()?;
+        self.expect_one('=')?;
+        self.parse_expr(0)?;
+        self.expect_one(';')?;
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    /// Parses a function, along with the attributes parsed since the checkpoint
+    fn parse_fn(&mut self, start: Checkpoint, is_extern: bool) -> ParseResult<()> {
+        self.builder.start_node_at(start, NodeKind::Function);
         if is_extern {
             self.expect_keyword(Keyword::Extern)?;
         }
         self.expect_keyword(Keyword::Fn)?;
-        let identifier = self.expect_identifier()?.as_string();
-        self.expect_one('(')?;
-        let mut args = vec![];
-        while let Some(t) = self.match_identifier() {
+        self.expect_identifier()?;
+        self.parse_type_params()?;
+        self.parse_params('(', ')')?;
+
+        if self.match_op(OpKind::Arrow) {
+            self.parse_ty()?;
+        }
+
+        if is_extern {
+            self.expect_one(';')?;
+        } else {
+            self.parse_block()?;
+        }
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    /// Parses typed parameters between the delimiters
+    fn parse_params(&mut self, open: char, close: char) -> ParseResult<()> {
+        self.builder.start_node(NodeKind::ParamList);
+        self.expect_one(open)?;
+        while self.peek(0).get_type() == TokenType::Identifier {
+            self.builder.start_node(NodeKind::Param);
+            self.advance();
             self.expect_one(':')?;
-            let ty = self.parse_ty()?;
-            args.push(ast::Argument {
-                name: t.as_string(),
-                r#type: ty,
-            });
+            self.parse_ty()?;
+            self.builder.finish_node();
             if !self.match_one(',') {
                 break;
             }
         }
-        self.expect_one(')')?;
-
-        let ty = if self.match_many(['-', '>']) {
-            self.parse_ty()?
-        } else {
-            ast::Type::Unit
-        };
-
-        let body = if is_extern {
-            self.expect_one(';')?;
-            vec![]
-        } else {
-            self.expect_one('{')?;
-            let body = self.parse_stmts()?;
-            self.expect_one('}')?;
-            body
-        };
+        self.expect_one(close)?;
+        self.builder.finish_node();
+        Ok(())
+    }
 
-        Ok(ast::Item::Function {
-            name: identifier,
-            is_extern,
-            params: args,
-            body,
-            ty,
-        })
+    fn parse_block(&mut self) -> ParseResult<()> {
+        self.builder.start_node(NodeKind::Block);
+        self.expect_one('{')?;
+        self.parse_stmts()?;
+        self.expect_one('}')?;
+        self.builder.finish_node();
+        Ok(())
     }
 
-    fn parse_let(&mut self) -> ParseResult<ast::Item> {
+    fn parse_let(&mut self) -> ParseResult<()> {
+        self.builder.start_node(NodeKind::Let);
         self.expect_keyword(Keyword::Let)?;
-        let identifier = self.expect_identifier()?.as_string();
-        let ty = if self.match_one(':') {
-            Some(self.parse_ty()?)
-        } else {
-            None
-        };
-
-        let expr = if self.match_one('=') {
-            Some(self.parse_expr_ref(0)?)
+        if self.parse_binding()? {
+            if self.match_one(':') {
+                self.parse_ty()?;
+            }
+            // Value taken apart by the pattern must be given
+            self.expect_one('=')?;
+            self.parse_expr(0)?;
         } else {
-            None
-        };
-
+            if self.match_one(':') {
+                self.parse_ty()?;
+            }
+            if self.match_one('=') {
+                self.parse_expr(0)?;
+            }
+        }
         self.expect_one(';')?;
-
-        Ok(ast::Item::Let {
-            name: identifier,
-            r#type: ty,
-            expr,
-        })
+        self.builder.finish_node();
+        Ok(())
     }
 
-    fn parse_ty(&mut self) -> ParseResult<ast::Type> {
-        let token = self.advance();
-        let ty = match token.get_type() {
-            TokenType::Punct('[') => {
-                let token = self.peek(0);
-                let length = match token.get_type() {
-                    TokenType::IntegralNumber => {
-                        let length = token.as_integer().unwrap() as usize;
-                        self.advance();
-                        Some(length)
-                    }
-                    _ => None,
-                };
-                self.expect_one(']')?;
-                let ty = self.parse_ty()?;
-                match length {
-                    Some(length) => Ok(ast::Type::Array(length, Box::new(ty))),
-                    None => Ok(ast::Type::Slice(Box::new(ty))),
+    fn parse_ty(&mut self) -> ParseResult<()> {
+        let token = self.peek(0);
+        let kind = match token.get_type() {
+            TokenType::Punct('[') => NodeKind::ArrayType,
+            TokenType::Punct('*') => NodeKind::PointerType,
+            TokenType::Identifier => NodeKind::NameType,
+            TokenType::Keyword(Keyword::Fn) => NodeKind::FnType,
+            TokenType::Punct('(') => NodeKind::TupleType,
+            TokenType::Keyword(Keyword::Range) => NodeKind::RangeType,
+            _ => return Err(self.unexpected()),
+        };
+        self.builder.start_node(kind);
+        self.advance();
+        match kind {
+            NodeKind::ArrayType => {
+                // Length is given by a number or by the name of a constant
+                if matches!(self.peek(0).get_type(), TokenType::IntegralNumber | TokenType::Identifier) {
+                    self.advance();
                 }
+                self.expect_one(']')?;
+                self.parse_ty()?;
             }
-            TokenType::Punct('*') => Ok(ast::Type::Pointer(Box::new(self.parse_ty()?))),
-            TokenType::Identifier => Ok(ast::Type::Name(token.as_string())),
-            TokenType::Keyword(Keyword::Fn) => {
+            NodeKind::PointerType => self.parse_ty()?,
+            NodeKind::FnType => {
+                self.builder.start_node(NodeKind::TupleType);
                 self.expect_one('(')?;
-                let args = self.parse_ty_tuple()?;
-
-                let ret = if self.match_many(['-', '>']) {
-                    self.parse_ty()?
-                } else {
-                    ast::Type::Unit
-                };
+                self.parse_ty_tuple()?;
+                self.builder.finish_node();
 
-                Ok(ast::Type::Function(args, Box::new(ret)))
+                if self.match_op(OpKind::Arrow) {
+                    self.parse_ty()?;
+                }
             }
-            TokenType::Punct('(') => {
-                let types = self.parse_ty_tuple()?;
-                Ok(ast::Type::Tuple(types))
+            NodeKind::TupleType => self.parse_ty_tuple()?,
+            NodeKind::RangeType => {
+                self.expect_one('<')?;
+                self.parse_ty()?;
+                self.expect_type_args_end()?;
             }
-            _ => unimplemented!(),
-        };
-        ty
+            NodeKind::NameType if self.type_args && self.match_one('<') => loop {
+                self.parse_ty()?;
+                if self.split_shr || !self.match_one(',') {
+                    self.expect_type_args_end()?;
+                    break;
+                }
+            },
+            _ => {}
+        }
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    /// Consumes `>` closing type arguments, `>>` closes both the inner and the outer ones
+    fn expect_type_args_end(&mut self) -> ParseResult<()> {
+        if self.split_shr {
+            self.split_shr = false;
+        } else if self.match_op(OpKind::Shr) {
+            self.split_shr = true;
+        } else {
+            self.expect_one('>')?;
+        }
+        Ok(())
+    }
+
+    /// Parses names of type parameters, if there are any
+    fn parse_type_params(&mut self) -> ParseResult<()> {
+        if self.peek(0).as_punct() != Some('<') {
+            return Ok(());
+        }
+        self.builder.start_node(NodeKind::TypeParams);
+        self.advance();
+        loop {
+            self.expect_identifier()?;
+            if !self.match_one(',') {
+                break;
+            }
+        }
+        self.expect_one('>')?;
+        self.builder.finish_node();
+        Ok(())
     }
 
-    fn parse_ty_tuple(&mut self) -> ParseResult<Vec<ast::Type>> {
-        let mut args = vec![];
+    fn parse_ty_tuple(&mut self) -> ParseResult<()> {
         loop {
             if self.match_one(')') {
                 break;
             }
-            args.push(self.parse_ty()?);
+            self.parse_ty()?;
             if !self.match_one(',') {
                 self.expect_one(')')?;
                 break;
             }
         }
-        Ok(args)
+        Ok(())
     }
 
-    fn parse_for(&mut self) -> ParseResult<ast::Item> {
+    fn parse_for(&mut self) -> ParseResult<()> {
+        self.builder.start_node(NodeKind::ForIn);
+        self.parse_label()?;
         self.expect_keyword(Keyword::For)?;
-        let identifier = self.expect_identifier()?.as_string();
+        self.parse_binding()?;
         self.expect_keyword(Keyword::In)?;
-        let expr = self.parse_expr_ref(0)?;
-        self.expect_one('{')?;
-        let items = self.parse_stmts()?;
-        self.expect_one('}')?;
-        Ok(ast::Item::ForIn {
-            name: identifier,
-            expr,
-            body: items,
-        })
+        self.parse_condition()?;
+        self.parse_block()?;
+        self.builder.finish_node();
+        Ok(())
     }
 
-    fn parse_loop(&mut self) -> ParseResult<ast::Item> {
+    fn parse_loop(&mut self) -> ParseResult<()> {
+        self.builder.start_node(NodeKind::Loop);
+        self.parse_label()?;
         self.expect_keyword(Keyword::Loop)?;
-        self.expect_one('{')?;
-        let items = self.parse_stmts()?;
-        self.expect_one('}')?;
-        Ok(ast::Item::Loop { body: items })
+        self.parse_block()?;
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    /// Parses label naming a loop, if there is one
+    fn parse_label(&mut self) -> ParseResult<()> {
+        if self.match_label() {
+            self.expect_one(':')?;
+        }
+        Ok(())
+    }
+
+    /// Consumes label if it is the next token
+    fn match_label(&mut self) -> bool {
+        if self.peek(0).get_type() == TokenType::Label {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_break(&mut self) -> ParseResult<()> {
+        self.builder.start_node(NodeKind::Break);
+        self.expect_keyword(Keyword::Break)?;
+        self.match_label();
+        if !self.match_one(';') {
+            self.parse_expr(0)?;
+            self.expect_one(';')?;
+        }
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    fn parse_while(&mut self) -> ParseResult<()> {
+        self.builder.start_node(NodeKind::While);
+        self.parse_label()?;
+        self.expect_keyword(Keyword::While)?;
+        self.parse_condition()?;
+        self.parse_block()?;
+        self.builder.finish_node();
+        Ok(())
     }
 
-    fn parse_if(&mut self) -> ParseResult<ast::Item> {
+    /// Parses expression followed by a block, so `{` after it is never a struct literal
+    fn parse_condition(&mut self) -> ParseResult<()> {
+        let struct_literals = std::mem::replace(&mut self.struct_literals, false);
+        let result = self.parse_expr(0);
+        self.struct_literals = struct_literals;
+        result
+    }
+
+    fn parse_if(&mut self) -> ParseResult<()> {
+        self.builder.start_node(NodeKind::If);
         self.expect_keyword(Keyword::If)?;
-        let condition = self.parse_expr_ref(0)?;
-        self.expect_one('{')?;
-        let arm_true = self.parse_stmts()?;
-        self.expect_one('}')?;
-        let arm_false = if self.match_keyword(Keyword::Else).is_some() {
+        self.parse_condition()?;
+        self.parse_block()?;
+        if self.match_keyword(Keyword::Else).is_some() {
             if self.peek(0).get_type() == TokenType::Keyword(Keyword::If) {
-                let item = self.parse_if()?;
-                Some(vec![item])
+                self.parse_if()?;
             } else {
-                self.expect_one('{')?;
-                let false_arm = self.parse_stmts()?;
+                self.parse_block()?;
+            }
+        }
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    fn parse_match(&mut self) -> ParseResult<()> {
+        self.builder.start_node(NodeKind::Match);
+        self.expect_keyword(Keyword::Match)?;
+        self.parse_condition()?;
+        self.expect_one('{')?;
+        while self.peek(0).as_punct() != Some('}') {
+            self.builder.start_node(NodeKind::MatchArm);
+            self.parse_pattern()?;
+            self.expect_op(OpKind::FatArrow)?;
+            // Arm with a block body doesn't need to be followed by a comma
+            if self.peek(0).as_punct() == Some('{') {
+                self.parse_block()?;
+                self.builder.finish_node();
+                self.match_one(',');
+            } else {
+                self.parse_expr(0)?;
+                self.builder.finish_node();
+                if !self.match_one(',') {
+                    break;
+                }
+            }
+        }
+        self.expect_one('}')?;
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    /// Parses `_` or a variant of an enum, along with names bound to its payload
+    fn parse_pattern(&mut self) -> ParseResult<()> {
+        let token = self.peek(0);
+        if token.get_type() == TokenType::Identifier && token.as_string() == "_" {
+            self.builder.start_node(NodeKind::WildcardPattern);
+            self.advance();
+            self.builder.finish_node();
+            return Ok(());
+        }
+
+        self.builder.start_node(NodeKind::VariantPattern);
+        self.expect_identifier()?;
+        self.expect_op(OpKind::ColonColon)?;
+        self.expect_identifier()?;
+        if self.match_one('(') {
+            loop {
+                if self.match_one(')') {
+                    break;
+                }
+                self.expect_identifier()?;
+                if !self.match_one(',') {
+                    self.expect_one(')')?;
+                    break;
+                }
+            }
+        }
+        self.builder.finish_node();
+        Ok(())
+    }
+
+    /// Parses a name or a pattern taking apart a tuple or a struct, returns whether it was a pattern
+    fn parse_binding(&mut self) -> ParseResult<bool> {
+        if self.peek(0).get_type() == TokenType::Punct('(') {
+            self.builder.start_node(NodeKind::TuplePattern);
+            self.advance();
+            loop {
+                if self.match_one(')') {
+                    break;
+                }
+                self.expect_identifier()?;
+                if !self.match_one(',') {
+                    self.expect_one(')')?;
+                    break;
+                }
+            }
+            self.builder.finish_node();
+            return Ok(true);
+        }
+
+        if self.peek(1).get_type() != TokenType::Punct('{') {
+            self.expect_identifier()?;
+            return Ok(false);
+        }
+
+        self.builder.start_node(NodeKind::StructPattern);
+        self.expect_identifier()?;
+        self.expect_one('{')?;
+        loop {
+            if self.match_one('}') {
+                break;
+            }
+            self.builder.start_node(NodeKind::FieldPattern);
+            self.expect_identifier()?;
+            if self.match_one(':') {
+                self.expect_identifier()?;
+            }
+            self.builder.finish_node();
+            if !self.match_one(',') {
                 self.expect_one('}')?;
-                Some(false_arm)
+                break;
             }
-        } else {
-            None
-        };
-        Ok(ast::Item::If {
-            condition,
-            arm_true,
-            arm_false,
-        })
+        }
+        self.builder.finish_node();
+        Ok(true)
     }
 
-    fn parse_yield(&mut self) -> ParseResult<ast::Item> {
+    fn parse_yield(&mut self) -> ParseResult<()> {
+        self.builder.start_node(NodeKind::Yield);
         self.expect_keyword(Keyword::Yield)?;
-        let value = self.parse_expr_ref(0)?;
-        Ok(ast::Item::Yield(Box::new(value)))
+        self.parse_expr(0)?;
+        self.expect_one(';')?;
+        self.builder.finish_node();
+        Ok(())
     }
 
-    fn parse_return(&mut self) -> ParseResult<ast::Item> {
+    fn parse_return(&mut self) -> ParseResult<()> {
+        self.builder.start_node(NodeKind::Return);
         self.expect_keyword(Keyword::Return)?;
-        let value = self.parse_expr_ref(0)?;
-        self.expect_one(';')?;
-        Ok(ast::Item::Return(Box::new(value)))
+        if !self.match_one(';') {
+            self.parse_expr(0)?;
+            self.expect_one(';')?;
+        }
+        self.builder.finish_node();
+        Ok(())
     }
 
     fn expect_keyword(&mut self, keyword: Keyword) -> ParseResult<Token<'lex>> {
@@ -576,6 +1046,15 @@ impl<'lex, 'arena> Parser<'lex, 'arena> where 'lex: 'arena {
         }
     }
 
+    /// Returns error for the next token, when no particular token was expected
+    fn unexpected(&mut self) -> ParseError {
+        if let Some(err) = self.lexer_error() {
+            return err;
+        }
+        let token = self.peek(0);
+        ParseError::UnexpectedToken(token.get_type(), token.line(), token.column(), None)
+    }
+
     fn expected(&mut self, token_type: TokenType) -> ParseError {
         let token = self.peek(0);
         ParseError::UnexpectedToken(
@@ -594,85 +1073,42 @@ impl<'lex, 'arena> Parser<'lex, 'arena> where 'lex: 'arena {
     }
 
     fn expect_identifier(&mut self) -> ParseResult<Token<'lex>> {
-        match self.match_identifier() {
-            Some(token) => Ok(token),
-            None => Err(self.expected(TokenType::Identifier)),
-        }
-    }
-
-    fn match_identifier(&mut self) -> Option<Token<'lex>> {
         match self.peek(0).get_type() {
-            TokenType::Identifier => Some(self.advance()),
-            _ => None,
+            TokenType::Identifier => Ok(self.advance()),
+            _ => Err(self.expected(TokenType::Identifier)),
         }
     }
 
-    /// Consumes and returns next token, otherwise returns an error
+    /// Consumes next token, otherwise returns an error
     fn expect_one(&mut self, ch: char) -> ParseResult<()> {
-        match self.expect_punct(PunctKind::Single, ch) {
-            Ok(_) => Ok(()),
-            Err(_) => self.expect_punct(PunctKind::Joint, ch),
+        if self.match_one(ch) {
+            Ok(())
+        } else {
+            Err(self.expected(TokenType::Punct(ch)))
         }
     }
 
     /// Consumes next token and returns true only if it is a char given by argument
     fn match_one(&mut self, ch: char) -> bool {
-        self.match_punct(PunctKind::Single, ch) || self.match_punct(PunctKind::Joint, ch)
-    }
-
-    #[allow(dead_code)]
-    #[inline]
-    fn expect_many(&mut self, chars: &[char]) -> ParseResult<()> {
-        for (idx, &expected) in chars.iter().enumerate() {
-            match self.peek(0).as_punct() {
-                Some((actual, kind)) if actual == expected => {
-                    if idx == chars.len() - 1 || kind == PunctKind::Joint {
-                        self.advance();
-                    }
-                }
-                _ => {
-                    return Err(self.expected(TokenType::Punct(expected)));
-                }
-            }
-        }
-        Ok(())
-    }
-
-    #[inline]
-    fn match_many<const N: usize>(&mut self, chars: [char; N]) -> bool {
-        for (index, &expected) in chars.iter().enumerate() {
-            if let Some((actual, kind)) = self.peek(index).as_punct() {
-                if actual != expected {
-                    return false;
-                }
-
-                let is_last = index == chars.len() - 1;
-                match kind {
-                    PunctKind::Single if !is_last => return false,
-                    PunctKind::Single | PunctKind::Joint => continue,
-                }
-            }
-        }
-
-        for _ in chars {
+        if self.peek(0).as_punct() == Some(ch) {
             self.advance();
+            return true;
         }
-
-        true
+        false
     }
 
-    /// Consumes and returns next token, otherwise returns an error
-    fn expect_punct(&mut self, kind: PunctKind, ch: char) -> ParseResult<()> {
-        if self.match_punct(kind, ch) {
+    /// Consumes next token if it is the operator, otherwise returns an error
+    fn expect_op(&mut self, op: OpKind) -> ParseResult<()> {
+        if self.match_op(op) {
             Ok(())
         } else {
-            Err(self.expected(TokenType::Punct(ch)))
+            Err(self.expected(TokenType::Op(op)))
         }
     }
 
-    /// Consumes and returns next token only if it is a char given by argument
-    fn match_punct(&mut self, kind: PunctKind, ch: char) -> bool {
-        if self.peek(0).as_punct() == Some((ch, kind)) {
+    /// Consumes next token and returns true only if it is an operator given by argument
+    fn match_op(&mut self, op: OpKind) -> bool {
+        if self.peek(0).as_op() == Some(op) {
             self.advance();
             return true;
         }
@@ -684,8 +1120,610 @@ impl<'lex, 'arena> Parser<'lex, 'arena> where 'lex: 'arena {
         self.peek.peek(offset).clone()
     }
 
-    /// Returns next token and consumes it
+    /// Returns next token and consumes it into the tree, unless it is a lexical error
     fn advance(&mut self) -> Token<'lex> {
-        self.peek.advance()
+        if self.peek(0).get_type() == TokenType::Error {
+            return self.peek(0);
+        }
+        self.bump()
+    }
+
+    /// Returns next token and consumes it into the tree, even if it is a lexical error
+    fn bump(&mut self) -> Token<'lex> {
+        let token = self.peek.advance();
+        self.builder.token(token.clone());
+        self.consumed += 1;
+        token
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast;
+    use crate::cst::{self, NodeKind};
+    use crate::index_arena::{Handle, IndexArena};
+    use crate::lexer::{Lexer, LexerError, NumberSuffix, TokenType};
+
+    use super::{ParseError, Parser};
+
+    fn parse(source: &str) -> (usize, Vec<ParseError>) {
+        let mut arena = IndexArena::default();
+        let mut parser = Parser::new(Lexer::from_source(source), &mut arena);
+        let (items, errors) = parser.parse();
+        (items.len(), errors)
+    }
+
+    /// Parses the source, which has to have no errors, into items and the arena of their expressions
+    fn parse_items(source: &str) -> (Vec<ast::Item>, IndexArena<ast::Expr>) {
+        let mut arena = IndexArena::default();
+        let mut parser = Parser::new(Lexer::from_source(source), &mut arena);
+        let (items, errors) = parser.parse();
+        assert!(errors.is_empty(), "{:?}", errors);
+        (items, arena)
+    }
+
+    /// Returns the body of the function
+    fn body(item: &ast::Item) -> &[ast::Item] {
+        let ast::ItemKind::Function { body, .. } = &item.kind else {
+            panic!("not a function");
+        };
+        body
+    }
+
+    /// Returns the expression bound by the let binding or evaluated by the statement
+    fn expr(item: &ast::Item) -> Handle<ast::Expr> {
+        match &item.kind {
+            ast::ItemKind::Let { expr: Some(expr), .. }
+            | ast::ItemKind::Expr { expr }
+            | ast::ItemKind::Tail { expr } => *expr,
+            ast::ItemKind::Return(Some(expr)) => **expr,
+            kind => panic!("no expression in {:?}", kind),
+        }
+    }
+
+    fn round_trip(source: &str) -> String {
+        let mut arena = IndexArena::default();
+        let mut parser = Parser::new(Lexer::from_source(source), &mut arena);
+        parser.parse_tree().0.to_string()
+    }
+
+    #[test]
+    fn tree_keeps_whitespace_and_comments() {
+        let source = "// leading\nfn  main ( a :u32,b: *[4]i32 )->u32 {\n\tlet x = /* inner /* nested */ */ ( a+ 1 , b );\n    return x . 0;\n}\n\n// trailing\n";
+        assert_eq!(round_trip(source), source);
+    }
+
+    #[test]
+    fn tree_nodes() {
+        let mut arena = IndexArena::default();
+        let mut parser = Parser::new(Lexer::from_source("fn f() { a = b + c * 2; }"), &mut arena);
+        let (tree, _) = parser.parse_tree();
+        let function = tree.node(NodeKind::Function).unwrap();
+        let assignment = function.node(NodeKind::Block).unwrap().node(NodeKind::Assignment).unwrap();
+        let rhs = assignment.exprs().nth(1).unwrap();
+        assert_eq!(rhs.kind(), NodeKind::BinaryExpr);
+        assert_eq!(rhs.span().unwrap().as_slice(), "b + c * 2");
+        assert_eq!(rhs.exprs().nth(1).unwrap().to_string(), " c * 2");
+    }
+
+    /// Returns expression with every operation in parentheses
+    fn parenthesize(source: &str) -> String {
+        fn visit(node: &cst::Node) -> String {
+            let operator = || node.tokens().next().unwrap().get_span().as_slice();
+            let operands: Vec<_> = node.exprs().map(visit).collect();
+            match node.kind() {
+                NodeKind::BinaryExpr => format!("({} {} {})", operands[0], operator(), operands[1]),
+                NodeKind::PrefixExpr => format!("({} {})", operator(), operands[0]),
+                NodeKind::CastExpr => format!("({} as {})", operands[0], node.ty().unwrap().span().unwrap().as_slice()),
+                NodeKind::FieldExpr => format!("({}.{})", operands[0], operands[1]),
+                NodeKind::CallExpr => format!("{}(..)", operands[0]),
+                NodeKind::ParenExpr => operands[0].clone(),
+                _ => node.span().unwrap().as_slice().to_string(),
+            }
+        }
+
+        let source = format!("assert {};", source);
+        let mut arena = IndexArena::default();
+        let mut parser = Parser::new(Lexer::from_source(&source), &mut arena);
+        let (tree, errors) = parser.parse_tree();
+        assert!(errors.is_empty(), "{:?}", errors);
+        let assert = tree.node(NodeKind::Assert).unwrap();
+        let expr = assert.exprs().next().unwrap();
+        visit(expr)
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(parenthesize("a + b == c and d"), "(((a + b) == c) and d)");
+        assert_eq!(parenthesize("a or b and c"), "(a or (b and c))");
+        assert_eq!(parenthesize("a * b / c % d"), "(((a * b) / c) % d)");
+        assert_eq!(parenthesize("a - b - c"), "((a - b) - c)");
+        assert_eq!(parenthesize("a | b ^ c & d"), "(a | (b ^ (c & d)))");
+        assert_eq!(parenthesize("a & b << c + d"), "(a & (b << (c + d)))");
+        assert_eq!(parenthesize("a < b | c"), "(a < (b | c))");
+        assert_eq!(parenthesize("not a == b or c"), "(((not a) == b) or c)");
+        assert_eq!(parenthesize("-a as u32 * b"), "(((- a) as u32) * b)");
+        assert_eq!(parenthesize("-a.b(c) + d"), "((- (a.b)(..)) + d)");
+        assert_eq!(parenthesize("*a + b"), "((* a) + b)");
+    }
+
+    #[test]
+    fn ast_spans() {
+        let mut arena = IndexArena::default();
+        let mut parser = Parser::new(Lexer::from_source("fn f() {\n    let a = 1 + 2;\n}"), &mut arena);
+        let (items, _) = parser.parse();
+        let ast::ItemKind::Function { body, .. } = &items[0].kind else {
+            panic!("not a function");
+        };
+        let ast::ItemKind::Let { expr: Some(expr), .. } = &body[0].kind else {
+            panic!("not a let binding");
+        };
+        let span = arena.span(*expr).unwrap();
+
+        assert_eq!((items[0].span.line, items[0].span.column, items[0].span.length), (1, 1, 29));
+        assert_eq!((body[0].span.line, body[0].span.column), (2, 5));
+        assert_eq!((span.line, span.column, span.length), (2, 13, 5));
+    }
+
+    #[test]
+    fn tuple_fields() {
+        let mut arena = IndexArena::default();
+        let mut parser = Parser::new(Lexer::from_source("fn f() { let a = t.0.1; }"), &mut arena);
+        let (items, _) = parser.parse();
+        let ast::ItemKind::Function { body, .. } = &items[0].kind else {
+            panic!("not a function");
+        };
+        let ast::ItemKind::Let { expr: Some(expr), .. } = &body[0].kind else {
+            panic!("not a let binding");
+        };
+        let ast::Expr::Place(inner, field) = arena.resolve(*expr) else {
+            panic!("not a field access");
+        };
+        assert!(matches!(arena.resolve(*field), ast::Expr::Integer(1, None)));
+        let ast::Expr::Place(tuple, field) = arena.resolve(*inner) else {
+            panic!("not a field access");
+        };
+        assert!(matches!(arena.resolve(*field), ast::Expr::Integer(0, None)));
+        assert_eq!(arena.resolve(*tuple).as_str(), Some("t"));
+    }
+
+    #[test]
+    fn ranges() {
+        let mut arena = IndexArena::default();
+        let source = "fn f(r: range<i32>) { let a = range n; let b = range 1 through 9 step 2; }";
+        let mut parser = Parser::new(Lexer::from_source(source), &mut arena);
+        let (items, _) = parser.parse();
+        let ast::ItemKind::Function { params, body, .. } = &items[0].kind else {
+            panic!("not a function");
+        };
+        let ast::Type::Range(item_ty) = &params[0].r#type else {
+            panic!("not a range type");
+        };
+        assert!(matches!(&**item_ty, ast::Type::Name(name) if name == "i32"));
+        let ast::ItemKind::Let { expr: Some(open), .. } = &body[0].kind else {
+            panic!("not a let binding");
+        };
+        assert!(matches!(
+            arena.resolve(*open),
+            ast::Expr::Range { start: None, step: None, inclusive: false, .. }
+        ));
+        let ast::ItemKind::Let { expr: Some(stepped), .. } = &body[1].kind else {
+            panic!("not a let binding");
+        };
+        let ast::Expr::Range { start: Some(start), end, step: Some(step), inclusive: true } = arena.resolve(*stepped) else {
+            panic!("not an inclusive range with a step");
+        };
+        assert!(matches!(arena.resolve(*start), ast::Expr::Integer(1, None)));
+        assert!(matches!(arena.resolve(*end), ast::Expr::Integer(9, None)));
+        assert!(matches!(arena.resolve(*step), ast::Expr::Integer(2, None)));
+    }
+
+    #[test]
+    fn slices() {
+        let mut arena = IndexArena::default();
+        let source = "fn f() { let a = t[1..n]; let b = t[..n]; let c = t[1..]; let d = t[..]; let e = t[i]; }";
+        let mut parser = Parser::new(Lexer::from_source(source), &mut arena);
+        let (items, errors) = parser.parse();
+        assert!(errors.is_empty());
+        let ast::ItemKind::Function { body, .. } = &items[0].kind else {
+            panic!("not a function");
+        };
+        let bounds: Vec<_> = body
+            .iter()
+            .map(|item| {
+                let ast::ItemKind::Let { expr: Some(expr), .. } = &item.kind else {
+                    panic!("not a let binding");
+                };
+                match arena.resolve(*expr) {
+                    ast::Expr::Slice { start, end, .. } => Some((start.is_some(), end.is_some())),
+                    _ => None,
+                }
+            })
+            .collect();
+        assert_eq!(
+            bounds,
+            [Some((true, true)), Some((false, true)), Some((true, false)), Some((false, false)), None]
+        );
+    }
+
+    #[test]
+    fn char_literals() {
+        let mut arena = IndexArena::default();
+        let source = "#[deprecated('x')]\nfn f() { let c = 'a'; g('\\n'); }";
+        let mut parser = Parser::new(Lexer::from_source(source), &mut arena);
+        let (items, errors) = parser.parse();
+        assert!(errors.is_empty(), "{:?}", errors);
+        let ast::ItemKind::Function { attributes, body, .. } = &items[0].kind else {
+            panic!("not a function");
+        };
+        assert_eq!(attributes[0].args, ["x"]);
+        let ast::ItemKind::Let { expr: Some(expr), .. } = &body[0].kind else {
+            panic!("not a let binding");
+        };
+        assert!(matches!(arena.resolve(*expr), ast::Expr::Integer(97, Some(NumberSuffix::U32))));
+        let ast::ItemKind::Expr { expr } = &body[1].kind else {
+            panic!("not an expression");
+        };
+        let ast::Expr::Call(_, args) = arena.resolve(*expr) else {
+            panic!("not a call");
+        };
+        let arg = args.into_iter().next().unwrap();
+        assert!(matches!(arena.resolve(arg), ast::Expr::Integer(10, Some(NumberSuffix::U32))));
+    }
+
+    #[test]
+    fn tree_round_trips_test_programs() {
+        for entry in std::fs::read_dir("tests").unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|it| it != "ft") {
+                continue;
+            }
+            let source = std::fs::read_to_string(&path).unwrap();
+            assert_eq!(round_trip(&source), source, "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn unterminated_string() {
+        let (_, errors) = parse("fn main() {\n    let a = \"abc;\n}");
+        assert!(matches!(
+            errors[0],
+            ParseError::Lexer(LexerError::UnterminatedString(2, 13))
+        ));
+    }
+
+    #[test]
+    fn unexpected_character_in_type() {
+        let (_, errors) = parse("fn main(a: §) {}");
+        assert!(matches!(
+            errors[..],
+            [ParseError::Lexer(LexerError::UnexpectedCharacter('§', 1, 12))]
+        ));
+    }
+
+    #[test]
+    fn lexical_error_at_top_level() {
+        let (items, errors) = parse("fn main() {}\n0x");
+        assert_eq!(items, 1);
+        assert!(matches!(errors[..], [ParseError::Lexer(LexerError::InvalidNumber(2, 1))]));
+    }
+
+    #[test]
+    fn syntax_error_before_lexical_error() {
+        let (_, errors) = parse("fn main() { let = 1; }\n\"abc");
+        assert!(matches!(
+            errors[..],
+            [
+                ParseError::UnexpectedToken(_, 1, 17, _),
+                ParseError::Lexer(LexerError::UnterminatedString(2, 1))
+            ]
+        ));
+    }
+
+    #[test]
+    fn reports_every_error() {
+        let source = "fn f() -> u32 {\n    let a = ;\n    a = 1 2;\n    return 1;\n}\n\nfn g(a: ) {\n    let b = 1;\n}\n\nfn h() {}\n";
+        let (items, errors) = parse(source);
+        assert_eq!(items, 2);
+        assert!(matches!(
+            errors[..],
+            [
+                ParseError::UnexpectedToken(_, 2, 13, None),
+                ParseError::UnexpectedToken(_, 3, 11, Some(_)),
+                ParseError::UnexpectedToken(_, 7, 9, None),
+            ]
+        ));
+        assert_eq!(round_trip(source), source);
+    }
+
+    #[test]
+    fn missing_index() {
+        let (_, errors) = parse("fn f() {\n    let a = b[];\n}\n");
+        assert!(matches!(errors[..], [ParseError::UnexpectedToken(TokenType::Punct(']'), 2, 15, None)]));
+    }
+
+    #[test]
+    fn partial_function_body() {
+        let mut arena = IndexArena::default();
+        let mut parser = Parser::new(Lexer::from_source("fn f() { a = ; b = 2; ) }"), &mut arena);
+        let (items, errors) = parser.parse();
+        let ast::ItemKind::Function { body, .. } = &items[0].kind else {
+            panic!("not a function");
+        };
+        assert_eq!(body.len(), 1);
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn let_at_top_level() {
+        let (items, errors) = parse("let a = 1;\nfn f() {}");
+        assert_eq!(items, 1);
+        assert!(matches!(errors[..], [ParseError::UnexpectedToken(_, 1, 1, None)]));
+    }
+    #[test]
+    fn block_after_condition() {
+        let (items, errors) = parse("fn f(x: bool) { while x {} if x {} else {} for i in x {} }");
+        assert_eq!(items, 1);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn block_values() {
+        let (items, arena) = parse_items("fn f(c: bool) -> i32 { let x = if c { 1 } else { { 2 } }; x + 1 }");
+        let body = body(&items[0]);
+        let ast::Expr::If { arm_true, arm_false: Some(arm_false), .. } = arena.resolve(expr(&body[0])) else {
+            panic!("not an if expression with both arms");
+        };
+        assert!(matches!(arena.resolve(expr(&arm_true[0])), ast::Expr::Integer(1, None)));
+        let ast::Expr::Block(inner) = arena.resolve(expr(&arm_false[0])) else {
+            panic!("not a block");
+        };
+        assert!(matches!(arena.resolve(expr(&inner[0])), ast::Expr::Integer(2, None)));
+        // Expression left without `;` is returned from the function
+        assert!(matches!(body[1].kind, ast::ItemKind::Return(Some(_))));
+        assert!(matches!(arena.resolve(expr(&body[1])), ast::Expr::Infix(ast::Operator::Add, ..)));
+
+        let (_, errors) = parse("fn f() -> i32 { 1 2 }");
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn loop_labels() {
+        let source = "fn f() { 'outer: loop { 'inner: while true { continue 'outer; } let x = loop { break 1; }; break 'outer; } }";
+        let (items, arena) = parse_items(source);
+        let ast::ItemKind::Loop { label: Some(outer), body } = &body(&items[0])[0].kind else {
+            panic!("not a labeled loop");
+        };
+        assert_eq!(outer, "'outer");
+        let ast::ItemKind::While { label: Some(inner), body: inner_body, .. } = &body[0].kind else {
+            panic!("not a labeled while loop");
+        };
+        assert_eq!(inner, "'inner");
+        assert!(matches!(&inner_body[0].kind, ast::ItemKind::Continue { label: Some(label) } if label == "'outer"));
+        let ast::Expr::Loop { label: None, body: value_body } = arena.resolve(expr(&body[1])) else {
+            panic!("not a loop expression");
+        };
+        let ast::ItemKind::Break { label: None, value: Some(value) } = &value_body[0].kind else {
+            panic!("not a break with a value");
+        };
+        assert!(matches!(arena.resolve(*value), ast::Expr::Integer(1, None)));
+        assert!(matches!(&body[2].kind, ast::ItemKind::Break { label: Some(label), value: None } if label == "'outer"));
+
+        let (_, errors) = parse("fn f() { 'a: let x = 1; }");
+        assert!(!errors.is_empty());
+    }
+
+    #[test]
+    fn impl_block() {
+        let source = "struct A { x: u32 }\nimpl A {\n    fn x(self: *A) -> u32 { self.x }\n    fn y(self: A) {}\n}\nfn f(a: A) -> u32 { a.x() }\n";
+        let (items, arena) = parse_items(source);
+        let ast::ItemKind::Impl { name, functions } = &items[1].kind else {
+            panic!("not an impl block");
+        };
+        assert_eq!(name, "A");
+        let ast::ItemKind::Function { name, params, .. } = &functions[0].kind else {
+            panic!("not a function");
+        };
+        assert_eq!(name, "x");
+        assert_eq!(params[0].name, "self");
+        assert!(matches!(&params[0].r#type, ast::Type::Pointer(ty) if matches!(&**ty, ast::Type::Name(name) if name == "A")));
+        // Method is looked up on the value like a field
+        let ast::Expr::Call(callee, args) = arena.resolve(expr(&body(&items[2])[0])) else {
+            panic!("not a call");
+        };
+        assert!(args.is_empty());
+        let ast::Expr::Place(receiver, method) = arena.resolve(*callee) else {
+            panic!("not a method");
+        };
+        assert_eq!(arena.resolve(*receiver).as_str(), Some("a"));
+        assert_eq!(arena.resolve(*method).as_str(), Some("x"));
+
+        let (_, errors) = parse("impl A { let a = 1; }");
+        assert!(matches!(errors[0], ParseError::UnexpectedToken(_, 1, 10, _)));
+    }
+
+    #[test]
+    fn enums_and_match() {
+        let source = "enum E { A, B(u32, *E), }\nfn f(e: E) -> u32 {\n    match e {\n        E::A => 0,\n        E::B(x, _) => { x }\n        _ => E::A,\n    }\n}\n";
+        let (items, arena) = parse_items(source);
+        let ast::ItemKind::Enum { name, variants } = &items[0].kind else {
+            panic!("not an enum");
+        };
+        assert_eq!(name, "E");
+        let variants: Vec<_> = variants.iter().map(|it| (it.name.as_str(), it.types.len())).collect();
+        assert_eq!(variants, [("A", 0), ("B", 2)]);
+        let ast::Expr::Match { scrutinee, arms } = arena.resolve(expr(&body(&items[1])[0])) else {
+            panic!("not a match");
+        };
+        assert_eq!(arena.resolve(*scrutinee).as_str(), Some("e"));
+        assert!(matches!(&arms[0].pattern, ast::Pattern::Variant { name, bindings, .. } if name == "A" && bindings.is_empty()));
+        assert!(matches!(&arms[1].pattern, ast::Pattern::Variant { r#type, name, bindings } if r#type == "E" && name == "B" && bindings == &["x", "_"]));
+        assert!(matches!(arms[2].pattern, ast::Pattern::Wildcard));
+        assert!(matches!(arena.resolve(expr(&arms[2].body[0])), ast::Expr::Path(ty, name) if ty == "E" && name == "A"));
+
+        let (_, errors) = parse("fn f(e: E) { match e { E::A 1 } }");
+        assert!(matches!(errors[0], ParseError::UnexpectedToken(_, 1, 29, Some(_))));
+    }
+
+    #[test]
+    fn generics() {
+        let source = "struct P<A, B> { a: A, b: B }\nfn f<T>(x: T, p: P<P<T, u32>, T>) -> P<T, P<T, T>> {\n    let b = x as u32 < 1;\n    p.b\n}\n";
+        let (items, arena) = parse_items(source);
+        let ast::ItemKind::Struct { type_params, .. } = &items[0].kind else {
+            panic!("not a struct");
+        };
+        assert_eq!(type_params, &["A", "B"]);
+        let ast::ItemKind::Function { type_params, params, ty, body, .. } = &items[1].kind else {
+            panic!("not a function");
+        };
+        assert_eq!(type_params, &["T"]);
+        // Closing `>>` ends both of the nested type argument lists
+        assert_eq!(
+            format!("{:?}", params[1].r#type),
+            r#"Generic("P", [Generic("P", [Name("T"), Name("u32")]), Name("T")])"#
+        );
+        assert_eq!(format!("{:?}", ty), r#"Generic("P", [Name("T"), Generic("P", [Name("T"), Name("T")])])"#);
+        // `<` after a cast is a comparison
+        let ast::Expr::Infix(ast::Operator::Less, lhs, _) = arena.resolve(expr(&body[0])) else {
+            panic!("not a comparison");
+        };
+        assert!(matches!(arena.resolve(*lhs), ast::Expr::Cast(_, ast::Type::Name(name)) if name == "u32"));
+
+        let (_, errors) = parse("fn f(p: P<u32) {}");
+        assert!(matches!(errors[0], ParseError::UnexpectedToken(_, 1, 14, _)));
+    }
+
+    #[test]
+    fn closures() {
+        let source = "fn f(k: i32) -> i32 {\n    let g = |x: i32, y: i32| x * k + y;\n    let h = || -> i32 { k };\n    g(1, 2) | h()\n}\n";
+        let (items, arena) = parse_items(source);
+        let body = body(&items[0]);
+        let ast::Expr::Closure { params, ty: None, body: value } = arena.resolve(expr(&body[0])) else {
+            panic!("not a closure without a return type");
+        };
+        let names: Vec<_> = params.iter().map(|it| it.name.as_str()).collect();
+        assert_eq!(names, ["x", "y"]);
+        // Body takes the rest of the expression
+        assert!(matches!(arena.resolve(*value), ast::Expr::Infix(ast::Operator::Add, ..)));
+        let ast::Expr::Closure { params, ty: Some(ast::Type::Name(ty)), body: value } = arena.resolve(expr(&body[1])) else {
+            panic!("not a closure with a return type");
+        };
+        assert!(params.is_empty());
+        assert_eq!(ty, "i32");
+        assert!(matches!(arena.resolve(*value), ast::Expr::Block(_)));
+        // `|` after an operand is the bitwise or
+        let ast::Expr::Infix(ast::Operator::BitOr, lhs, rhs) = arena.resolve(expr(&body[2])) else {
+            panic!("not a bitwise or");
+        };
+        assert!(matches!(arena.resolve(*lhs), ast::Expr::Call(..)));
+        assert!(matches!(arena.resolve(*rhs), ast::Expr::Call(..)));
+
+        let (_, errors) = parse("fn f() { let g = |x| x; }");
+        assert!(matches!(errors[0], ParseError::UnexpectedToken(_, 1, 20, Some(_))));
+    }
+
+    #[test]
+    fn imports() {
+        let source = "import \"lib/geometry.ft\";\nfn f() -> i32 { geometry::area(1, 2) + E::A }\n";
+        let (items, arena) = parse_items(source);
+        assert!(matches!(&items[0].kind, ast::ItemKind::Import { path } if path == "lib/geometry.ft"));
+        let ast::Expr::Infix(_, call, variant) = arena.resolve(expr(&body(&items[1])[0])) else {
+            panic!("not an addition");
+        };
+        // Path starting with an imported module names its function, other paths are left as they are
+        let ast::Expr::Call(callee, _) = arena.resolve(*call) else {
+            panic!("not a call");
+        };
+        assert_eq!(arena.resolve(*callee).as_str(), Some("geometry::area"));
+        assert!(matches!(arena.resolve(*variant), ast::Expr::Path(ty, name) if ty == "E" && name == "A"));
+
+        // Functions of an imported module are named by it
+        let mut arena = IndexArena::default();
+        let (items, _) = Parser::new(Lexer::from_source("fn area() {}"), &mut arena).parse_module("geometry");
+        assert!(matches!(&items[0].kind, ast::ItemKind::Function { name, .. } if name == "geometry::area"));
+
+        let (_, errors) = parse("import geometry;");
+        assert!(matches!(errors[0], ParseError::UnexpectedToken(_, 1, 8, Some(TokenType::String))));
+    }
+
+    #[test]
+    fn globals() {
+        let source = "const N: u32 = 4;\nstatic mut COUNT: i32 = 0;\nstatic LIMIT: i32 = 9;\nfn f(a: [N]i32) { COUNT += a[0]; }\n";
+        let (items, arena) = parse_items(source);
+        let ast::ItemKind::Const { name, r#type: ast::Type::Name(ty), expr: value } = &items[0].kind else {
+            panic!("not a constant");
+        };
+        assert_eq!((name.as_str(), ty.as_str()), ("N", "u32"));
+        assert!(matches!(arena.resolve(*value), ast::Expr::Integer(4, None)));
+        assert!(matches!(&items[1].kind, ast::ItemKind::Static { name, mutable: true, .. } if name == "COUNT"));
+        assert!(matches!(&items[2].kind, ast::ItemKind::Static { name, mutable: false, .. } if name == "LIMIT"));
+        let ast::ItemKind::Function { params, body, .. } = &items[3].kind else {
+            panic!("not a function");
+        };
+        // Length of the array is left for the type checker to look up
+        assert!(matches!(&params[0].r#type, ast::Type::ConstArray(len, _) if len == "N"));
+        let ast::ItemKind::Assignment { lhs, operator: Some(ast::Operator::Add), .. } = &body[0].kind else {
+            panic!("not a compound assignment");
+        };
+        assert_eq!(arena.resolve(*lhs).as_str(), Some("COUNT"));
+
+        let (_, errors) = parse("const mut N: u32 = 4;");
+        assert!(matches!(errors[0], ParseError::UnexpectedToken(_, 1, 7, Some(TokenType::Identifier))));
+    }
+
+    #[test]
+    fn destructuring() {
+        let source = "fn f(p: Point) {\n    let (a, _) = g();\n    let Point { x, y: b } = p;\n    for (i, v) in pairs {}\n}\n";
+        let (items, arena) = parse_items(source);
+        let body = body(&items[0]);
+        let ast::ItemKind::LetPattern { pattern: ast::Destructuring::Tuple(names), .. } = &body[0].kind else {
+            panic!("not a tuple pattern");
+        };
+        assert_eq!(names, &["a", "_"]);
+        let ast::ItemKind::LetPattern { pattern: ast::Destructuring::Struct { r#type, fields }, .. } = &body[1].kind else {
+            panic!("not a struct pattern");
+        };
+        assert_eq!(r#type, "Point");
+        // Field without a name is bound to its own name
+        assert_eq!(fields, &[("x".to_string(), "x".to_string()), ("y".to_string(), "b".to_string())]);
+        // Item of the loop is bound to a hidden name and taken apart at the start of the body
+        let ast::ItemKind::ForIn { name, body, .. } = &body[2].kind else {
+            panic!("not a for loop");
+        };
+        let ast::ItemKind::LetPattern { pattern: ast::Destructuring::Tuple(names), expr, .. } = &body[0].kind else {
+            panic!("not a tuple pattern");
+        };
+        assert_eq!(names, &["i", "v"]);
+        assert_eq!(arena.resolve(*expr).as_str(), Some(name.as_str()));
+
+        let (_, errors) = parse("fn f() { let (a, b); }");
+        assert!(matches!(errors[0], ParseError::UnexpectedToken(_, 1, 20, Some(TokenType::Punct('=')))));
+    }
+
+    #[test]
+    fn attributes() {
+        let source = "#[test]\n#[deprecated(\"use g\", 2)]\nfn f() {}\n#[inline] struct S { a: i32 }\nimpl S {\n    #[inline]\n    fn g(self: S) {}\n}\n";
+        let (items, _) = parse_items(source);
+        let ast::ItemKind::Function { attributes, .. } = &items[0].kind else {
+            panic!("not a function");
+        };
+        assert_eq!(attributes[0].name, "test");
+        assert!(attributes[0].args.is_empty());
+        assert_eq!(attributes[1].name, "deprecated");
+        assert_eq!(attributes[1].args, ["use g", "2"]);
+        let ast::ItemKind::Struct { attributes, .. } = &items[1].kind else {
+            panic!("not a struct");
+        };
+        assert_eq!(attributes[0].name, "inline");
+        let ast::ItemKind::Impl { functions, .. } = &items[2].kind else {
+            panic!("not an impl block");
+        };
+        let ast::ItemKind::Function { attributes, .. } = &functions[0].kind else {
+            panic!("not a method");
+        };
+        assert_eq!(attributes[0].name, "inline");
+
+        let (_, errors) = parse("#[inline] enum E {}");
+        assert!(matches!(errors[0], ParseError::UnexpectedToken(_, 1, 11, None)));
     }
 }
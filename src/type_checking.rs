@@ -1,11 +1,14 @@
 use std::borrow::Borrow;
 use std::collections::HashMap;
+use std::fmt;
 use std::ptr::addr_of;
 
 use crate::arena::Arena;
 use crate::ast;
-use crate::index_arena::{Handle, IndexArena};
-use crate::ir::{Bits, Var};
+use crate::host::HostFunctions;
+use crate::index_arena::{Handle, IndexArena, Many};
+use crate::ir::{Bits, Const, Var};
+use crate::lexer::{NumberSuffix, Span};
 use crate::types::{Type, TypeRef};
 
 fn is_coercible_to(ty: TypeRef<'_>, target: TypeRef<'_>) -> bool {
@@ -14,6 +17,10 @@ fn is_coercible_to(ty: TypeRef<'_>, target: TypeRef<'_>) -> bool {
         (Type::Float, Type::F32) => true,
         (Type::Array(a, x), Type::Array(b, y)) => a == b && is_coercible_to(x, y),
         (Type::Pointer(x), Type::Pointer(y)) => is_coercible_to(x, y),
+        (Type::Tuple(xs), Type::Tuple(ys)) => {
+            xs.len() == ys.len()
+                && std::iter::zip(xs, ys).all(|(x, y)| is_compatible_to(y, x) || is_coercible_to(x, y))
+        }
         _ => false,
     }
 }
@@ -52,7 +59,18 @@ fn is_compatible_to(ty: TypeRef<'_>, subty: TypeRef<'_>) -> bool {
                 .all(|(ty1, ty2)| is_compatible_to(ty1, ty2))
         }
         (Type::Pointer(ty1), Type::Pointer(ty2)) => is_compatible_to(ty1, ty2),
+        (Type::Generator(ty1), Type::Generator(ty2)) => is_compatible_to(ty1, ty2),
+        (Type::Range(ty1), Type::Range(ty2)) => is_compatible_to(ty1, ty2),
         (Type::Any, _) | (_, Type::Any) => true,
+        (Type::Placeholder(lhs), Type::Placeholder(rhs)) => lhs == rhs,
+        (Type::Enum { variants: lhs }, Type::Enum { variants: rhs }) => {
+            lhs.len() == rhs.len()
+                && std::iter::zip(lhs, rhs).all(|((lhs_name, lhs_tys), (rhs_name, rhs_tys))| {
+                    lhs_name == rhs_name
+                        && lhs_tys.len() == rhs_tys.len()
+                        && std::iter::zip(lhs_tys, rhs_tys).all(|(lhs, rhs)| is_compatible_to(lhs, rhs))
+                })
+        }
         (Type::Struct { fields: lhs_fields }, Type::Struct { fields: rhs_fields }) => {
             std::iter::zip(lhs_fields, rhs_fields).all(
                 |((lhs_name, lhs_ty), (rhs_name, rhs_ty))| {
@@ -64,6 +82,141 @@ fn is_compatible_to(ty: TypeRef<'_>, subty: TypeRef<'_>) -> bool {
     }
 }
 
+/// Returns number of type parameters the type refers to, zero when it is not generic
+pub(crate) fn type_params_count(ty: TypeRef<'_>) -> usize {
+    match ty {
+        Type::Placeholder(idx) => idx + 1,
+        Type::Array(_, ty) | Type::Slice(ty) | Type::Pointer(ty) | Type::Generator(ty) | Type::Range(ty) => {
+            type_params_count(ty)
+        }
+        Type::Tuple(types) => types.iter().map(|ty| type_params_count(ty)).max().unwrap_or(0),
+        Type::Function(args, ret) => args
+            .iter()
+            .map(|ty| type_params_count(ty))
+            .fold(type_params_count(ret), usize::max),
+        Type::Struct { fields } => fields.iter().map(|(_, ty)| type_params_count(ty)).max().unwrap_or(0),
+        Type::Enum { variants } => variants
+            .iter()
+            .flat_map(|(_, types)| types)
+            .map(|ty| type_params_count(ty))
+            .max()
+            .unwrap_or(0),
+        _ => 0,
+    }
+}
+
+/// Replaces placeholders in the type with type arguments of the same index
+pub(crate) fn substitute<'tcx>(
+    arena: &'tcx Arena<Type<'tcx>>,
+    ty: TypeRef<'tcx>,
+    args: &[TypeRef<'tcx>],
+) -> TypeRef<'tcx> {
+    if type_params_count(ty) == 0 {
+        return ty;
+    }
+    let each = |types: &[TypeRef<'tcx>]| types.iter().map(|ty| substitute(arena, ty, args)).collect();
+    arena.alloc(match ty {
+        Type::Placeholder(idx) => return args[*idx],
+        Type::Array(len, ty) => Type::Array(*len, substitute(arena, ty, args)),
+        Type::Slice(ty) => Type::Slice(substitute(arena, ty, args)),
+        Type::Pointer(ty) => Type::Pointer(substitute(arena, ty, args)),
+        Type::Generator(ty) => Type::Generator(substitute(arena, ty, args)),
+        Type::Range(ty) => Type::Range(substitute(arena, ty, args)),
+        Type::Tuple(types) => Type::Tuple(each(types)),
+        Type::Function(params, ret) => Type::Function(each(params), substitute(arena, ret, args)),
+        Type::Struct { fields } => Type::Struct {
+            fields: fields
+                .iter()
+                .map(|(name, ty)| (name.clone(), substitute(arena, ty, args)))
+                .collect(),
+        },
+        Type::Enum { variants } => Type::Enum {
+            variants: variants
+                .iter()
+                .map(|(name, types)| (name.clone(), each(types)))
+                .collect(),
+        },
+        ty => unreachable!("{:?} has no placeholders", ty),
+    })
+}
+
+/// Binds placeholders in the pattern to the parts of the type at the same position
+///
+/// Abstract types bind only when `defaults` is set, as `i32` and `f32`, so that specific types
+/// found elsewhere take precedence.
+pub(crate) fn bind_placeholders<'tcx>(
+    pattern: TypeRef<'tcx>,
+    ty: TypeRef<'tcx>,
+    args: &mut [Option<TypeRef<'tcx>>],
+    defaults: bool,
+) {
+    match (pattern, ty) {
+        (Type::Placeholder(idx), ty) if args[*idx].is_none() => {
+            args[*idx] = match ty {
+                Type::Integer if defaults => Some(&Type::I32),
+                Type::Float if defaults => Some(&Type::F32),
+                Type::Integer | Type::Float => None,
+                ty => Some(ty),
+            };
+        }
+        (Type::Array(_, pattern), Type::Array(_, ty))
+        | (Type::Slice(pattern), Type::Slice(ty) | Type::Array(_, ty))
+        | (Type::Pointer(pattern), Type::Pointer(ty))
+        | (Type::Generator(pattern), Type::Generator(ty))
+        | (Type::Range(pattern), Type::Range(ty)) => bind_placeholders(pattern, ty, args, defaults),
+        (Type::Tuple(patterns), Type::Tuple(types)) => {
+            for (pattern, ty) in std::iter::zip(patterns, types) {
+                bind_placeholders(pattern, ty, args, defaults);
+            }
+        }
+        (Type::Function(patterns, pattern), Type::Function(types, ty)) => {
+            for (pattern, ty) in std::iter::zip(patterns, types) {
+                bind_placeholders(pattern, ty, args, defaults);
+            }
+            bind_placeholders(pattern, ty, args, defaults);
+        }
+        (Type::Struct { fields: patterns }, Type::Struct { fields: types }) => {
+            for ((_, pattern), (_, ty)) in std::iter::zip(patterns, types) {
+                bind_placeholders(pattern, ty, args, defaults);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Checks whether items yield, without looking into nested functions
+fn yields(items: &[ast::Item]) -> bool {
+    items.iter().any(|item| match &item.kind {
+        ast::ItemKind::Yield(_) => true,
+        ast::ItemKind::If {
+            arm_true,
+            arm_false,
+            ..
+        } => yields(arm_true) || arm_false.as_deref().is_some_and(yields),
+        ast::ItemKind::ForIn { body, .. }
+        | ast::ItemKind::Loop { body, .. }
+        | ast::ItemKind::While { body, .. }
+        | ast::ItemKind::Block(body) => yields(body),
+        _ => false,
+    })
+}
+
+/// Checks whether the items never run to their end, as the last of them leaves the function or the loop
+fn diverges(items: &[Item<'_, '_>]) -> bool {
+    matches!(
+        items.last().map(|it| &it.kind),
+        Some(ItemKind::Return(_) | ItemKind::Break { .. } | ItemKind::Continue { .. })
+    )
+}
+
+/// Describes the `break` or `continue` with no loop to leave, naming its label if it has one
+fn outside_of_loop(statement: &str, label: &Option<String>) -> String {
+    match label {
+        Some(label) => format!("{} outside of a loop labeled {}", statement, label),
+        None => format!("{} outside of a loop", statement),
+    }
+}
+
 macro_rules! intrinsics {
     ($($name:ident),*) => {
         #[derive(Copy, Clone, PartialEq, Debug)]
@@ -71,7 +224,7 @@ macro_rules! intrinsics {
         pub enum Intrinsic { $($name,)* }
 
         impl Intrinsic {
-            pub fn to_str(&self) -> &'static str {
+            pub fn to_str(self) -> &'static str {
                  match self {
                     $(Intrinsic::$name => stringify!($name)),*
                 }
@@ -81,9 +234,13 @@ macro_rules! intrinsics {
 }
 
 intrinsics! {
-    debug
+    debug,
+    len
 }
 
+/// Attributes which have a meaning, others are reported
+const KNOWN_ATTRIBUTES: [&str; 3] = ["test", "inline", "deprecated"];
+
 pub(crate) type ExprRef<'expr> = &'expr Expression<'expr>;
 
 #[derive(Debug, Clone)]
@@ -95,13 +252,32 @@ pub(crate) enum Expression<'expr> {
     Infix(ast::Operator, ExprRef<'expr>, ExprRef<'expr>),
     Prefix(ast::Operator, ExprRef<'expr>),
     Index(ExprRef<'expr>, ExprRef<'expr>),
+    /// Slice of the elements of an array or a slice, up to the end of the base when there is no end.
+    /// Slicing an array copies its elements, slicing a slice shares them
+    Slice {
+        base: ExprRef<'expr>,
+        start: ExprRef<'expr>,
+        end: Option<ExprRef<'expr>>,
+    },
     Array(Vec<ExprRef<'expr>>),
     Call(ExprRef<'expr>, Vec<ExprRef<'expr>>),
     Tuple(Vec<ExprRef<'expr>>),
     StructLiteral(Vec<ExprRef<'expr>>),
-    Range(ExprRef<'expr>, Option<ExprRef<'expr>>),
+    /// Range with its bounds and the step coerced to the type of its items
+    Range {
+        start: ExprRef<'expr>,
+        end: ExprRef<'expr>,
+        step: ExprRef<'expr>,
+        inclusive: bool,
+    },
     Cast(ExprRef<'expr>),
     Field(ExprRef<'expr>, usize),
+    /// Value of an enum, index of its variant along with the payload
+    Variant(usize, Vec<ExprRef<'expr>>),
+    /// Function value, name of the function along with the values it captured
+    Closure(String, Vec<ExprRef<'expr>>),
+    /// Value of the named static
+    Static(String),
     Error,
     Var(Var),
     Intrinsic(Intrinsic),
@@ -114,12 +290,32 @@ pub(crate) struct Argument<'tcx> {
 }
 
 #[derive(Debug, Clone)]
-pub(crate) enum Item<'expr, 'tcx> {
+pub(crate) struct Item<'expr, 'tcx> {
+    pub(crate) kind: ItemKind<'expr, 'tcx>,
+    pub(crate) span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct MatchArm<'expr, 'tcx> {
+    /// Index of the matched variant, `None` matches any
+    pub(crate) variant: Option<usize>,
+    /// Locals the payload is bound to, along with the index of the bound field
+    pub(crate) bindings: Vec<(usize, String, TypeRef<'tcx>)>,
+    pub(crate) body: Vec<Item<'expr, 'tcx>>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum ItemKind<'expr, 'tcx> {
     Let {
         name: String,
         ty: TypeRef<'tcx>,
         expr: Option<ExprRef<'expr>>,
     },
+    /// Locals bound to the fields of the value, along with the index of the bound field
+    LetPattern {
+        bindings: Vec<(usize, String, TypeRef<'tcx>)>,
+        expr: ExprRef<'expr>,
+    },
     Assignment {
         lhs: ExprRef<'expr>,
         operator: Option<ast::Operator>,
@@ -128,29 +324,58 @@ pub(crate) enum Item<'expr, 'tcx> {
     Expression {
         expr: ExprRef<'expr>,
     },
+    /// Static along with its initial value
+    Static {
+        name: String,
+        value: ExprRef<'expr>,
+    },
     Function {
         name: String,
+        attributes: Vec<ast::Attribute>,
+        /// Names of type parameters, the function is instantiated for each set of type arguments
+        type_params: Vec<String>,
         is_extern: bool,
         args: Vec<Argument<'tcx>>,
         ty: TypeRef<'tcx>,
         body: Vec<Item<'expr, 'tcx>>,
     },
+    /// Struct definition, its type is known by its name from then on
+    Struct {
+        name: String,
+        attributes: Vec<ast::Attribute>,
+    },
     If {
         condition: ExprRef<'expr>,
         arm_true: Vec<Item<'expr, 'tcx>>,
         arm_false: Option<Vec<Item<'expr, 'tcx>>>,
     },
     ForIn {
+        label: Option<String>,
         name: String,
         expr: ExprRef<'expr>,
         body: Vec<Item<'expr, 'tcx>>,
     },
     Loop {
+        label: Option<String>,
         body: Vec<Item<'expr, 'tcx>>,
     },
-    Break,
+    Match {
+        scrutinee: ExprRef<'expr>,
+        arms: Vec<MatchArm<'expr, 'tcx>>,
+    },
+    While {
+        label: Option<String>,
+        condition: ExprRef<'expr>,
+        body: Vec<Item<'expr, 'tcx>>,
+    },
+    Break {
+        label: Option<String>,
+    },
+    Continue {
+        label: Option<String>,
+    },
     Yield(ExprRef<'expr>),
-    Return(ExprRef<'expr>),
+    Return(Option<ExprRef<'expr>>),
     Block(Vec<Item<'expr, 'tcx>>),
     Assert(ExprRef<'expr>),
 }
@@ -186,6 +411,12 @@ impl<'tcx> ExprToType<'tcx> {
         match self.of(expr) {
             Type::Unknown => panic!("coercion failed??"),
             other if is_coercible_to(other, ty) => {
+                // Values of a tuple literal are coerced one by one
+                if let (Expression::Tuple(values), Type::Tuple(types)) = (expr, ty) {
+                    for (value, ty) in std::iter::zip(values, types) {
+                        self.try_coerce(value, ty);
+                    }
+                }
                 self.try_insert(expr, ty);
                 log::debug!("coerced to {:?}", ty);
                 true
@@ -217,13 +448,113 @@ impl<'tcx> ExprToType<'tcx> {
     }
 }
 
+/// Side table with the location in the source of each typed expression
+///
+/// Expressions made up by the later stages, eg. when desugaring loops, have no location.
+pub(crate) struct ExprToSpan {
+    map: HashMap<*const (), Span>,
+}
+
+impl ExprToSpan {
+    pub(crate) fn new() -> Self {
+        Self {
+            map: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn insert(&mut self, expr: ExprRef<'_>, span: Span) {
+        self.map.insert(addr_of!(*expr).cast(), span);
+    }
+
+    pub(crate) fn of(&self, expr: ExprRef<'_>) -> Option<Span> {
+        self.map.get(&addr_of!(*expr).cast()).copied()
+    }
+}
+
+/// Problem found in the program, reported to the user
+pub(crate) struct Diagnostic {
+    pub(crate) severity: Severity,
+    pub(crate) span: Span,
+    pub(crate) message: String,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum Severity {
+    /// Program is rejected
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}: {}", self.span, severity, self.message)
+    }
+}
+
 pub(crate) struct TypeCheckerContext<'tcx, 'expr, 'ast> {
     pub(crate) arena: &'tcx Arena<Type<'tcx>>,
     pub(crate) locals: HashMap<&'ast str, TypeRef<'tcx>>,
     pub(crate) defined_types: HashMap<&'expr str, TypeRef<'tcx>>,
+    /// Names of type parameters in scope, each stands for the placeholder with its index
+    pub(crate) type_params: Vec<&'ast str>,
+    /// Types of methods by the name of their type and their own name
+    pub(crate) methods: HashMap<(&'expr str, &'expr str), TypeRef<'tcx>>,
     pub(crate) ast_expr_arena: &'ast IndexArena<ast::Expr>,
     pub(crate) exprs: &'expr Arena<Expression<'expr>>,
     pub(crate) type_by_expr: ExprToType<'tcx>,
+    pub(crate) span_by_expr: ExprToSpan,
+    /// Return type of the function being checked
+    pub(crate) ret_ty: Option<TypeRef<'tcx>>,
+    /// Items computing values of `if` and block expressions, placed before the item using them
+    pub(crate) hoisted: Vec<Item<'expr, 'tcx>>,
+    /// Loops enclosing the items being checked, innermost last
+    pub(crate) loops: Vec<EnclosingLoop<'ast, 'tcx>>,
+    /// Names bound so far within the function being checked
+    pub(crate) bindings: Vec<&'ast str>,
+    /// Number of bindings made outside of the closure being checked, along with the ones it used
+    pub(crate) captures: Option<(usize, Vec<&'ast str>)>,
+    /// Functions made of closures, each takes values it captured before its own arguments
+    pub(crate) closures: Vec<Item<'expr, 'tcx>>,
+    /// Constants and statics defined so far
+    pub(crate) globals: HashMap<&'ast str, Global<'expr, 'tcx>>,
+    /// Attributes of functions, methods and structs defined so far
+    pub(crate) attributes: HashMap<String, &'ast [ast::Attribute]>,
+    /// Functions implemented by the host, which `extern fn` declarations refer to
+    pub(crate) host: &'ast HostFunctions,
+    /// Problems found so far, which have not been reported yet
+    pub(crate) diagnostics: Vec<Diagnostic>,
+    /// Whether the call being checked gives the items of a `for` loop, which is the only way to use a generator
+    pub(crate) iterated: bool,
+    /// Type parameters of each generic function which it applies arithmetic to, they can stand only for numbers
+    pub(crate) arithmetic: HashMap<String, Vec<usize>>,
+    /// Type parameters the generic function being checked applies arithmetic to
+    pub(crate) arithmetic_params: Vec<usize>,
+    /// Integer literals along with their values, checked to fit their types once these are settled
+    pub(crate) literals: Vec<(ExprRef<'expr>, i64)>,
+    /// Bindings of the function being checked to integer literals without a suffix, the literal
+    /// takes the type which the binding settles on
+    pub(crate) untyped_lets: Vec<(&'ast str, ExprRef<'expr>)>,
+}
+
+/// Name defined at the top level, other than a function or a type
+pub(crate) enum Global<'expr, 'tcx> {
+    /// Constant with its value
+    Const(ExprRef<'expr>),
+    Static { ty: TypeRef<'tcx>, mutable: bool },
+}
+
+/// Loop enclosing the items being checked
+pub(crate) struct EnclosingLoop<'ast, 'tcx> {
+    label: Option<&'ast str>,
+    /// Whether `break` can carry a value, which is true only for `loop`
+    breaks_with_value: bool,
+    /// Local storing the value when the loop is used as an expression, along with its type
+    /// once the first `break` with a value is seen
+    value: Option<(String, Option<TypeRef<'tcx>>)>,
 }
 
 impl<'ast, 'tcx, 'expr> TypeCheckerContext<'tcx, 'expr, 'ast>
@@ -236,21 +567,54 @@ where
         expr
     }
 
-    fn deduce_expr_ty(&mut self, expr: &Handle<ast::Expr>) -> ExprRef<'expr> {
+    fn deduce_expr_ty(&mut self, handle: &Handle<ast::Expr>) -> ExprRef<'expr> {
+        let expr = self.deduce_expr_ty_inner(handle);
+        if let Some(span) = self.ast_expr_arena.span(*handle) {
+            self.span_by_expr.insert(expr, span);
+        }
+        match self.ast_expr_arena.resolve(*handle) {
+            ast::Expr::Integer(val, _) => self.literals.push((expr, *val)),
+            // Negative literal is checked as a whole, eg. `-2147483648` fits in `i32`
+            ast::Expr::Prefix(ast::Operator::Negate, inner) => {
+                if let ast::Expr::Integer(val, _) = self.ast_expr_arena.resolve(*inner) {
+                    self.literals.pop();
+                    self.literals.push((expr, -val));
+                }
+            }
+            _ => (),
+        }
+        expr
+    }
+
+    fn deduce_expr_ty_inner(&mut self, expr: &Handle<ast::Expr>) -> ExprRef<'expr> {
         let (expr, ty) = match self.ast_expr_arena.resolve(*expr) {
             ast::Expr::Bool(val) => (Expression::Bool(*val), self.arena.alloc(Type::Bool)),
-            ast::Expr::Integer(val) => (
-                // FIXME: type
+            ast::Expr::Integer(val, Some(NumberSuffix::U32)) => (
+                Expression::Integer((*val as u32).into()),
+                self.arena.alloc(Type::U32),
+            ),
+            ast::Expr::Integer(val, Some(NumberSuffix::I32)) => (
+                Expression::Integer((*val as i32).into()),
+                self.arena.alloc(Type::I32),
+            ),
+            // Type of the literal is settled later, values which do not fit in it are reported then
+            ast::Expr::Integer(val, _) => (
                 Expression::Integer((*val as i32).into()),
                 self.arena.alloc(Type::Integer),
             ),
-            ast::Expr::Float(val) => (Expression::Float(*val), self.arena.alloc(Type::Float)),
+            ast::Expr::Float(val, Some(NumberSuffix::F32)) => {
+                (Expression::Float(*val), self.arena.alloc(Type::F32))
+            }
+            ast::Expr::Float(val, _) => (Expression::Float(*val), self.arena.alloc(Type::Float)),
             ast::Expr::Infix(op, lhs, rhs) => {
                 let lhs = self.deduce_expr_ty(lhs);
                 let rhs = self.deduce_expr_ty(rhs);
                 let ty = if is_compatible_to(self.type_by_expr.of(lhs), self.type_by_expr.of(rhs))
                     || self.type_by_expr.try_coerce_any(lhs, rhs)
                 {
+                    if !matches!(op, ast::Operator::Equal | ast::Operator::NotEqual) {
+                        self.requires_number(self.type_by_expr.of(lhs));
+                    }
                     match op {
                         ast::Operator::Less
                         | ast::Operator::LessEqual
@@ -263,7 +627,14 @@ where
                         ast::Operator::Add
                         | ast::Operator::Sub
                         | ast::Operator::Mul
-                        | ast::Operator::Div => self.type_by_expr.of(lhs),
+                        | ast::Operator::Div
+                        | ast::Operator::Rem
+                        | ast::Operator::BitAnd
+                        | ast::Operator::BitOr
+                        | ast::Operator::BitXor
+                        | ast::Operator::Shl
+                        | ast::Operator::Shr => self.type_by_expr.of(lhs),
+                        ast::Operator::Not => unimplemented!(),
                         ast::Operator::Negate => unimplemented!(),
                         ast::Operator::Ref => unimplemented!(),
                         ast::Operator::Deref => unimplemented!(),
@@ -290,10 +661,21 @@ where
                         Type::Pointer(inner) => inner,
                         other => unimplemented!("deref of {:?}", other),
                     },
+                    ast::Operator::Negate => {
+                        self.requires_number(self.type_by_expr.of(inner));
+                        self.type_by_expr.of(inner)
+                    }
                     _ => self.type_by_expr.of(inner),
                 };
                 (Expression::Prefix(*op, inner), ty)
             }
+            // Globals are shadowed by bindings of the function
+            ast::Expr::Identifier(ident) if !self.bindings.contains(&ident.as_str()) && self.globals.contains_key(ident.as_str()) => {
+                match self.globals[ident.as_str()] {
+                    Global::Const(value) => ((*value).clone(), self.type_by_expr.of(value)),
+                    Global::Static { ty, .. } => (Expression::Static(ident.clone()), ty),
+                }
+            }
             ast::Expr::Identifier(ident) => {
                 let ty = match self.locals.get(ident.as_str()) {
                     Some(ty) => ty,
@@ -302,26 +684,45 @@ where
                         self.arena.alloc(Type::Error)
                     }
                 };
+                self.capture(ident);
                 (Expression::Identifier(ident.to_string()), ty)
             }
+            ast::Expr::Path(r#type, name) => {
+                let span = self.ast_expr_arena.span(*expr).unwrap_or_default();
+                let Some((ty, variant, payload)) = self.variant(r#type, name, span) else {
+                    return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                };
+                if !payload.is_empty() {
+                    log::debug!("{}::{} takes {} values", r#type, name, payload.len());
+                    return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                }
+                (Expression::Variant(variant, vec![]), ty)
+            }
             ast::Expr::Place(expr, field) => {
+                let span = self.ast_expr_arena.span(*expr).unwrap_or_default();
                 let field = self.ast_expr_arena.resolve(*field);
                 let lhs = self.deduce_expr_ty(expr);
 
-                let Some(name) = field.as_str() else {
-                    unimplemented!()
-                };
-
-                let (idx, (_, ty)) = match &self.type_by_expr.of(lhs) {
-                    Type::Struct { fields } => fields
+                // Fields are reached through pointers as well, fields of tuples are named by their index
+                let found = match (field, self.type_by_expr.of(lhs)) {
+                    (
+                        ast::Expr::Identifier(name),
+                        Type::Struct { fields } | Type::Pointer(Type::Struct { fields }),
+                    ) => fields
                         .iter()
-                        .enumerate()
-                        .find(|(idx, (n, _))| n == name)
-                        .unwrap(),
-                    _ => unimplemented!(),
+                        .position(|(it, _)| it == name)
+                        .map(|idx| (idx, fields[idx].1)),
+                    (ast::Expr::Integer(idx, None), Type::Tuple(types) | Type::Pointer(Type::Tuple(types))) => {
+                        types.get(*idx as usize).map(|ty| (*idx as usize, *ty))
+                    }
+                    _ => None,
+                };
+                let Some((idx, ty)) = found else {
+                    log::debug!("{}: no field {:?} in {:?}", span, field, self.type_by_expr.of(lhs));
+                    return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
                 };
 
-                (Expression::Field(lhs, idx), *ty)
+                (Expression::Field(lhs, idx), ty)
             }
             ast::Expr::Array(items) => {
                 if items.is_empty() {
@@ -339,7 +740,7 @@ where
                     }
                 }
 
-                let item_ty = first_specific_type.unwrap_or(&Type::I32);
+                let item_ty = first_specific_type.unwrap_or_else(|| self.default_of(self.type_by_expr.of(values[0])));
                 for expr in &values {
                     if is_compatible_to(self.type_by_expr.of(expr), item_ty)
                         || self.type_by_expr.try_coerce(expr, item_ty)
@@ -362,6 +763,7 @@ where
             }
             ast::Expr::Call(callee, args) => match self.ast_expr_arena.resolve(*callee) {
                 ast::Expr::Identifier(ident) => {
+                    let iterated = std::mem::take(&mut self.iterated);
                     let callee = match ident.as_str() {
                         "debug" => {
                             let ty = self
@@ -369,11 +771,30 @@ where
                                 .alloc(Type::Function(vec![&Type::Any], &Type::Any));
                             self.make_expr(ty, Expression::Intrinsic(Intrinsic::debug))
                         }
+                        "len" => {
+                            let mut args = args.into_iter();
+                            let (Some(arg), None) = (args.next(), args.next()) else {
+                                log::debug!("len takes one argument");
+                                return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                            };
+                            let arg = self.deduce_expr_ty(&arg);
+                            if !matches!(self.type_by_expr.of(arg), Type::Array(..) | Type::Slice(_)) {
+                                log::debug!("{:?} has no length", self.type_by_expr.of(arg));
+                                return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                            }
+                            let ty = self.arena.alloc(Type::Function(vec![&Type::Any], &Type::U32));
+                            let callee = self.make_expr(ty, Expression::Intrinsic(Intrinsic::len));
+                            return self.make_expr(&Type::U32, Expression::Call(callee, vec![arg]));
+                        }
                         other => {
-                            let ty = self
-                                .locals
-                                .get(other)
-                                .unwrap_or_else(|| panic!("a type for {}", other));
+                            let span = self.ast_expr_arena.span(*expr).unwrap_or_default();
+                            if !self.locals.contains_key(other) {
+                                self.error(span, format!("unknown identifier {}", other));
+                                return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                            }
+                            if !self.bindings.contains(&other) {
+                                self.check_deprecated(other, span);
+                            }
                             self.deduce_expr_ty(callee)
                         }
                     };
@@ -386,51 +807,453 @@ where
                                 .make_expr(self.arena.alloc(Type::Error), Expression::Error);
                         }
                     };
+                    if matches!(ret_ty, Type::Generator(_)) && !iterated {
+                        let span = self.ast_expr_arena.span(*expr).unwrap_or_default();
+                        self.error(span, format!("generator {} can only be iterated by a for loop", ident));
+                        return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                    }
 
                     let mut values = Vec::new();
+                    let type_params = type_params_count(self.type_by_expr.of(callee));
+                    if type_params > 0 {
+                        // Type arguments of a generic function are inferred from its arguments
+                        let values: Vec<_> = args.into_iter().map(|arg| self.deduce_expr_ty(&arg)).collect();
+                        let mut type_args = vec![None; type_params];
+                        for defaults in [false, true] {
+                            for (value, expected_ty) in values.iter().zip(args_ty) {
+                                bind_placeholders(expected_ty, self.type_by_expr.of(value), &mut type_args, defaults);
+                            }
+                        }
+                        let Some(type_args) = type_args.into_iter().collect::<Option<Vec<_>>>() else {
+                            log::debug!("cannot infer type arguments of {}", ident);
+                            return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                        };
+                        // Arithmetic of the function is checked for each set of type arguments it is called with
+                        for &idx in self.arithmetic.get(ident.as_str()).cloned().iter().flatten() {
+                            let ty = type_args[idx];
+                            if !self.requires_number(ty) {
+                                let span = self.ast_expr_arena.span(*expr).unwrap_or_default();
+                                self.error(
+                                    span,
+                                    format!("{} applies arithmetic to its type parameter, which cannot be {:?}", ident, ty),
+                                );
+                                return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                            }
+                        }
+                        for (value, expected_ty) in values.iter().zip(args_ty) {
+                            let expected_ty = substitute(self.arena, expected_ty, &type_args);
+                            if !is_compatible_to(self.type_by_expr.of(value), expected_ty)
+                                && !self.type_by_expr.try_coerce(value, expected_ty)
+                            {
+                                log::debug!(
+                                    "incompatible types {:?} and {:?}",
+                                    self.type_by_expr.of(value),
+                                    expected_ty
+                                );
+                                return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                            }
+                        }
+                        return self.make_expr(
+                            substitute(self.arena, ret_ty, &type_args),
+                            Expression::Call(callee, values),
+                        );
+                    }
+                    if !self.deduce_args(args, args_ty, &mut values) {
+                        return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                    }
 
-                    for (arg, expected_ty) in args.into_iter().zip(args_ty) {
-                        let arg = self.deduce_expr_ty(&arg);
+                    (Expression::Call(callee, values), *ret_ty)
+                }
+                ast::Expr::Place(receiver, method) => {
+                    let receiver = self.deduce_expr_ty(receiver);
+                    let Some(method) = self.ast_expr_arena.resolve(*method).as_str() else {
+                        unimplemented!()
+                    };
 
-                        if is_compatible_to(self.type_by_expr.of(arg), expected_ty)
-                            || self.type_by_expr.try_coerce(arg, expected_ty)
-                        {
-                            //
-                        } else {
-                            log::debug!(
-                                "incompatible types {:?} and {:?}",
-                                self.type_by_expr.of(arg),
-                                expected_ty
-                            );
-                            return self
-                                .make_expr(self.arena.alloc(Type::Error), Expression::Error);
-                        }
+                    let receiver_ty = self.type_by_expr.of(receiver);
+                    let self_ty = match receiver_ty {
+                        Type::Pointer(ty) => *ty,
+                        ty => ty,
+                    };
+                    // Methods are looked up by the name the type was defined with
+                    let Some((&type_name, _)) = self
+                        .defined_types
+                        .iter()
+                        .find(|(_, ty)| std::ptr::eq(**ty, self_ty))
+                    else {
+                        log::debug!("{:?} has no methods", receiver_ty);
+                        return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                    };
+                    let Some(&func_ty) = self.methods.get(&(type_name, method)) else {
+                        log::debug!("no method {} for {}", method, type_name);
+                        return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                    };
+                    self.check_deprecated(
+                        &format!("{}::{}", type_name, method),
+                        self.ast_expr_arena.span(*expr).unwrap_or_default(),
+                    );
+                    let Type::Function(args_ty, ret_ty) = func_ty else {
+                        unreachable!();
+                    };
 
-                        values.push(arg);
+                    // Receiver is passed by reference or by value, as the method takes it
+                    let receiver = match (args_ty[0], receiver_ty) {
+                        (Type::Pointer(_), Type::Pointer(_)) => receiver,
+                        (Type::Pointer(_), _) => self.make_expr(
+                            self.arena.alloc(Type::Pointer(receiver_ty)),
+                            Expression::Prefix(ast::Operator::Ref, receiver),
+                        ),
+                        (_, Type::Pointer(_)) => self.make_expr(
+                            self_ty,
+                            Expression::Prefix(ast::Operator::Deref, receiver),
+                        ),
+                        (_, _) => receiver,
+                    };
+
+                    let mut values = vec![receiver];
+                    if !self.deduce_args(args, &args_ty[1..], &mut values) {
+                        return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
                     }
 
+                    let callee = self.make_expr(
+                        func_ty,
+                        Expression::Identifier(format!("{}::{}", type_name, method)),
+                    );
+                    (Expression::Call(callee, values), *ret_ty)
+                }
+                ast::Expr::Path(r#type, name) => {
+                    let span = self.ast_expr_arena.span(*expr).unwrap_or_default();
+                    let Some((ty, variant, payload)) = self.variant(r#type, name, span) else {
+                        return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                    };
+                    let mut values = Vec::new();
+                    if args.len() != payload.len() || !self.deduce_args(args, payload, &mut values) {
+                        log::debug!("{}::{} takes {} values", r#type, name, payload.len());
+                        return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                    }
+                    (Expression::Variant(variant, values), ty)
+                }
+                _ => {
+                    // Function value given by any other expression, eg. a closure
+                    let callee = self.deduce_expr_ty(callee);
+                    let Type::Function(args_ty, ret_ty) = self.type_by_expr.of(callee) else {
+                        log::debug!("{:?} is not callable", self.type_by_expr.of(callee));
+                        return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                    };
+                    let mut values = Vec::new();
+                    if !self.deduce_args(args, args_ty, &mut values) {
+                        return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                    }
                     (Expression::Call(callee, values), *ret_ty)
                 }
-                expr => unimplemented!("{:?}", expr),
             },
-            ast::Expr::Range(from, Some(to)) => {
-                let from = self.deduce_expr_ty(from);
-                let to = self.deduce_expr_ty(to);
-                if is_compatible_to(self.type_by_expr.of(from), self.type_by_expr.of(to))
-                    || self.type_by_expr.try_coerce_any(from, to)
+            ast::Expr::Range {
+                start,
+                end,
+                step,
+                inclusive,
+            } => {
+                let span = self.ast_expr_arena.span(*expr).unwrap_or_default();
+                let end = self.deduce_expr_ty(end);
+                let start = start.as_ref().map(|start| self.deduce_expr_ty(start));
+                if let Some(start) = start {
+                    if !is_compatible_to(self.type_by_expr.of(start), self.type_by_expr.of(end))
+                        && !self.type_by_expr.try_coerce_any(start, end)
+                    {
+                        log::debug!("{}: incompatible range bounds", span);
+                        return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                    }
+                }
+                let item_ty = match self.type_by_expr.of(end) {
+                    Type::Integer => &Type::I32,
+                    ty @ (Type::I32 | Type::U32) => ty,
+                    other => {
+                        log::debug!("{}: range bounds should be integers, got {:?}", span, other);
+                        return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                    }
+                };
+                // Step known up front has to move the range forward, others are checked while iterating
+                if let Some(step) = step {
+                    let literal = match self.ast_expr_arena.resolve(*step) {
+                        ast::Expr::Integer(value, _) => Some(*value),
+                        ast::Expr::Prefix(ast::Operator::Negate, value) => match self.ast_expr_arena.resolve(*value) {
+                            ast::Expr::Integer(value, _) => Some(-*value),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    if let Some(value) = literal.filter(|it| *it <= 0) {
+                        let span = self.ast_expr_arena.span(*step).unwrap_or_default();
+                        self.error(span, format!("step of a range has to be positive, got {}", value));
+                        return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                    }
+                }
+                // Range without the start begins at zero, without the step it moves by one
+                let mut default = |value: i32| {
+                    let bits = match item_ty {
+                        Type::U32 => (value as u32).into(),
+                        _ => value.into(),
+                    };
+                    self.make_expr(item_ty, Expression::Integer(bits))
+                };
+                let start = start.unwrap_or_else(|| default(0));
+                let step = match step {
+                    Some(step) => self.deduce_expr_ty(step),
+                    None => default(1),
+                };
+                self.type_by_expr.try_coerce(start, item_ty);
+                self.type_by_expr.try_coerce(end, item_ty);
+                if !is_compatible_to(self.type_by_expr.of(step), item_ty)
+                    && !self.type_by_expr.try_coerce(step, item_ty)
                 {
-                    //
-                } else {
-                    log::debug!("incompatible range bounds");
+                    log::debug!("{}: step of {:?} range is {:?}", span, item_ty, self.type_by_expr.of(step));
                     return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
                 }
                 (
-                    Expression::Range(from, Some(to)),
-                    self.arena.alloc(Type::Range),
+                    Expression::Range {
+                        start,
+                        end,
+                        step,
+                        inclusive: *inclusive,
+                    },
+                    self.arena.alloc(Type::Range(item_ty)),
                 )
             }
-            ast::Expr::Range(to, None) => {
-                unimplemented!()
+            ast::Expr::If {
+                condition,
+                arm_true,
+                arm_false,
+            } => {
+                let span = self.ast_expr_arena.span(*expr).unwrap_or_default();
+                let condition = self.deduce_expr_ty(condition);
+                if !is_compatible_to(self.type_by_expr.of(condition), &Type::Bool) {
+                    log::debug!(
+                        "{}: only boolean expressions are allowed in if conditions, got {:?}",
+                        span,
+                        self.type_by_expr.of(condition)
+                    );
+                    return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                }
+
+                let (mut items_true, tail_true) = self.infer_block(arm_true);
+                let (mut items_false, tail_false) = match arm_false {
+                    Some(arm_false) => self.infer_block(arm_false),
+                    None => (vec![], None),
+                };
+                let ty = match (tail_true, tail_false) {
+                    (Some(lhs), Some(rhs))
+                        if is_compatible_to(self.type_by_expr.of(lhs), self.type_by_expr.of(rhs))
+                            || self.type_by_expr.try_coerce_any(lhs, rhs) =>
+                    {
+                        self.type_by_expr.of(lhs)
+                    }
+                    // Arm which diverges gives no value, so the `if` has the type of the other one
+                    (Some(tail), None) if diverges(&items_false) => self.type_by_expr.of(tail),
+                    (None, Some(tail)) if diverges(&items_true) => self.type_by_expr.of(tail),
+                    (None, None) => self.arena.alloc(Type::Unit),
+                    (lhs, rhs) => {
+                        log::debug!(
+                            "{}: incompatible types of if arms, got {:?} and {:?}",
+                            span,
+                            lhs.map(|it| self.type_by_expr.of(it)),
+                            rhs.map(|it| self.type_by_expr.of(it))
+                        );
+                        return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                    }
+                };
+
+                // Both arms store their value in a local read after the `if`
+                let name = format!("_if{}", span.start);
+                for (items, tail) in [(&mut items_true, tail_true), (&mut items_false, tail_false)] {
+                    if let Some(tail) = tail {
+                        let lhs = self.make_expr(ty, Expression::Identifier(name.clone()));
+                        items.push(Item {
+                            kind: ItemKind::Assignment {
+                                lhs,
+                                operator: None,
+                                expr: tail,
+                            },
+                            span,
+                        });
+                    }
+                }
+                self.hoisted.push(Item {
+                    kind: ItemKind::Let {
+                        name: name.clone(),
+                        ty,
+                        expr: None,
+                    },
+                    span,
+                });
+                self.hoisted.push(Item {
+                    kind: ItemKind::If {
+                        condition,
+                        arm_true: items_true,
+                        arm_false: arm_false.as_ref().map(|_| items_false),
+                    },
+                    span,
+                });
+                (Expression::Identifier(name), ty)
+            }
+            ast::Expr::Match { scrutinee, arms } => {
+                let span = self.ast_expr_arena.span(*expr).unwrap_or_default();
+                let scrutinee = self.deduce_expr_ty(scrutinee);
+                let scrutinee_ty = self.type_by_expr.of(scrutinee);
+                let Type::Enum { variants } = scrutinee_ty else {
+                    if !matches!(scrutinee_ty, Type::Error) {
+                        self.error(span, format!("only enums can be matched, got {:?}", scrutinee_ty));
+                    }
+                    return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                };
+
+                let mut covered = vec![false; variants.len()];
+                let mut typed_arms = Vec::new();
+                let mut tails = Vec::new();
+                for arm in arms {
+                    let (variant, bindings) = match &arm.pattern {
+                        ast::Pattern::Wildcard => {
+                            covered.fill(true);
+                            (None, vec![])
+                        }
+                        ast::Pattern::Variant {
+                            r#type,
+                            name,
+                            bindings,
+                        } => {
+                            let Some((ty, variant, payload)) = self.variant(r#type, name, span) else {
+                                return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                            };
+                            if !std::ptr::eq(ty, scrutinee_ty) {
+                                self.error(span, format!("pattern {}::{} is not a variant of the matched enum", r#type, name));
+                                return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                            }
+                            if bindings.len() != payload.len() {
+                                self.error(
+                                    span,
+                                    format!(
+                                        "pattern {}::{} binds {} values, but the variant holds {}",
+                                        r#type,
+                                        name,
+                                        bindings.len(),
+                                        payload.len()
+                                    ),
+                                );
+                                return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                            }
+                            covered[variant] = true;
+                            let bindings: Vec<_> = bindings
+                                .iter()
+                                .zip(payload)
+                                .enumerate()
+                                .filter(|(_, (name, _))| name.as_str() != "_")
+                                .map(|(idx, (name, ty))| {
+                                    self.locals.insert(name.as_str(), ty);
+                                    self.bindings.push(name.as_str());
+                                    (idx, name.clone(), *ty)
+                                })
+                                .collect();
+                            (Some(variant), bindings)
+                        }
+                    };
+                    let (body, tail) = self.infer_block(&arm.body);
+                    typed_arms.push(MatchArm {
+                        variant,
+                        bindings,
+                        body,
+                    });
+                    tails.push(tail);
+                }
+
+                let missing: Vec<_> = std::iter::zip(variants, &covered)
+                    .filter(|(_, covered)| !**covered)
+                    .map(|((name, _), _)| name.as_str())
+                    .collect();
+                if !missing.is_empty() {
+                    self.error(span, format!("match is not exhaustive, missing {}", missing.join(", ")));
+                    return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                }
+
+                // Arms either all give a value of the same type or none of them does
+                let ty = match tails.split_first() {
+                    Some((Some(first), rest)) if rest.iter().all(|tail| {
+                        tail.is_some_and(|tail| {
+                            is_compatible_to(self.type_by_expr.of(first), self.type_by_expr.of(tail))
+                                || self.type_by_expr.try_coerce_any(first, tail)
+                        })
+                    }) => self.type_by_expr.of(first),
+                    _ if tails.iter().all(Option::is_none) => self.arena.alloc(Type::Unit),
+                    _ => {
+                        let types: Vec<_> = tails.iter().map(|tail| tail.map(|it| self.type_by_expr.of(it))).collect();
+                        self.error(span, format!("incompatible types of match arms, got {:?}", types));
+                        return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                    }
+                };
+
+                // Every arm stores its value in a local read after the `match`
+                let name = format!("_match{}", span.start);
+                for (arm, tail) in typed_arms.iter_mut().zip(tails) {
+                    if let Some(tail) = tail {
+                        let lhs = self.make_expr(ty, Expression::Identifier(name.clone()));
+                        arm.body.push(Item {
+                            kind: ItemKind::Assignment {
+                                lhs,
+                                operator: None,
+                                expr: tail,
+                            },
+                            span,
+                        });
+                    }
+                }
+                self.hoisted.push(Item {
+                    kind: ItemKind::Let {
+                        name: name.clone(),
+                        ty,
+                        expr: None,
+                    },
+                    span,
+                });
+                self.hoisted.push(Item {
+                    kind: ItemKind::Match {
+                        scrutinee,
+                        arms: typed_arms,
+                    },
+                    span,
+                });
+                (Expression::Identifier(name), ty)
+            }
+            ast::Expr::Closure { params, ty, body } => {
+                let span = self.ast_expr_arena.span(*expr).unwrap_or_default();
+                self.infer_closure(format!("_closure{}", span.start), params, ty, body, span)
+            }
+            ast::Expr::Loop { label, body } => {
+                let span = self.ast_expr_arena.span(*expr).unwrap_or_default();
+                let name = format!("_loop{}", span.start);
+                let (body, ty) = self.infer_loop(label, true, Some(name.clone()), body);
+                let ty = ty.unwrap_or(self.arena.alloc(Type::Unit));
+                self.hoisted.push(Item {
+                    kind: ItemKind::Let {
+                        name: name.clone(),
+                        ty,
+                        expr: None,
+                    },
+                    span,
+                });
+                self.hoisted.push(Item {
+                    kind: ItemKind::Loop {
+                        label: label.clone(),
+                        body,
+                    },
+                    span,
+                });
+                (Expression::Identifier(name), ty)
+            }
+            ast::Expr::Block(items) => {
+                let (mut items, tail) = self.infer_block(items);
+                self.hoisted.append(&mut items);
+                match tail {
+                    Some(tail) => return tail,
+                    None => (Expression::Tuple(vec![]), self.arena.alloc(Type::Unit)),
+                }
             }
             ast::Expr::Tuple(items) => {
                 let mut values = Vec::new();
@@ -451,168 +1274,451 @@ where
                 let array = self.deduce_expr_ty(array_expr);
                 let index = self.deduce_expr_ty(index_expr);
 
-                let ty = match (self.type_by_expr.of(array), self.type_by_expr.of(index)) {
-                    (Type::Array(_, item_ty), idx) | (Type::Slice(item_ty), idx) => {
-                        self.type_by_expr.try_coerce(array, &Type::I32);
-                        idx
-                    }
+                let ty = match self.type_by_expr.of(array) {
+                    Type::Array(_, item_ty) | Type::Slice(item_ty) if self.is_index(index) => *item_ty,
                     _ => self.arena.alloc(Type::Error),
                 };
 
                 (Expression::Index(array, index), ty)
             }
+            ast::Expr::Slice { base, start, end } => {
+                let span = self.ast_expr_arena.span(*expr).unwrap_or_default();
+                let base = self.deduce_expr_ty(base);
+                let item_ty = match self.type_by_expr.of(base) {
+                    Type::Array(_, item_ty) | Type::Slice(item_ty) => *item_ty,
+                    other => {
+                        log::debug!("{}: {:?} cannot be sliced", span, other);
+                        return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                    }
+                };
+                // Elements are kept in memory one after another, without fields of their own
+                if matches!(item_ty, Type::Tuple(_) | Type::Struct { .. } | Type::Enum { .. } | Type::Array(..)) {
+                    log::debug!("{}: slices of {:?} are not supported", span, item_ty);
+                    return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                }
+                let start = match start {
+                    Some(start) => self.deduce_expr_ty(start),
+                    None => self.make_expr(&Type::U32, Expression::Integer(0u32.into())),
+                };
+                let end = end.as_ref().map(|end| self.deduce_expr_ty(end));
+                if !self.is_index(start) || !end.is_none_or(|end| self.is_index(end)) {
+                    log::debug!("{}: bounds of a slice should be integers", span);
+                    return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                }
+                (
+                    Expression::Slice { base, start, end },
+                    self.arena.alloc(Type::Slice(item_ty)),
+                )
+            }
             ast::Expr::Var(_) => unreachable!(),
             ast::Expr::Cast(expr, ty) => {
                 let expr = self.deduce_expr_ty(expr);
                 let target_ty = self.unify(ty);
                 (Expression::Cast(expr), target_ty)
             }
-            ast::Expr::StructLiteral(expr, fields) => {
-                let ty = match expr {
-                    Some(name) => self.defined_types[name.as_str()],
+            ast::Expr::StructLiteral(name, fields) => {
+                let ty = match name {
+                    Some(name) => {
+                        self.check_deprecated(name, self.ast_expr_arena.span(*expr).unwrap_or_default());
+                        self.defined_types[name.as_str()]
+                    }
                     None => &Type::Unknown,
                 };
 
-                let fields = fields
+                let fields: Vec<_> = fields
                     .iter()
-                    .map(|(name, expr)| self.deduce_expr_ty(expr))
+                    .map(|(name, expr)| (name, self.deduce_expr_ty(expr)))
                     .collect();
+                let ty = match ty {
+                    // Type arguments of a generic struct are inferred from its fields
+                    Type::Struct { fields: templates } if type_params_count(ty) > 0 => {
+                        let mut type_args = vec![None; type_params_count(ty)];
+                        for defaults in [false, true] {
+                            for (name, value) in &fields {
+                                if let Some((_, template)) = templates.iter().find(|(it, _)| it == *name) {
+                                    bind_placeholders(template, self.type_by_expr.of(value), &mut type_args, defaults);
+                                }
+                            }
+                        }
+                        let Some(type_args) = type_args.into_iter().collect::<Option<Vec<_>>>() else {
+                            log::debug!("cannot infer type arguments of {:?}", name);
+                            return self.make_expr(self.arena.alloc(Type::Error), Expression::Error);
+                        };
+                        let ty = substitute(self.arena, ty, &type_args);
+                        if let Type::Struct { fields: types } = ty {
+                            for (name, value) in &fields {
+                                if let Some((_, field_ty)) = types.iter().find(|(it, _)| it == *name) {
+                                    self.type_by_expr.try_coerce(value, field_ty);
+                                }
+                            }
+                        }
+                        ty
+                    }
+                    ty => ty,
+                };
+                let fields = fields.into_iter().map(|(_, value)| value).collect();
                 (Expression::StructLiteral(fields), ty)
             }
         };
         self.make_expr(ty, expr)
     }
 
+    /// Returns type of the value bound by `let`, which must fit the type given to the binding
+    /// Checks whether the expression can index an array, literals become `u32`
+    fn is_index(&mut self, expr: ExprRef<'expr>) -> bool {
+        match self.type_by_expr.of(expr) {
+            Type::I32 | Type::U32 => true,
+            _ => self.type_by_expr.try_coerce(expr, &Type::U32),
+        }
+    }
+
+    /// Makes slice of all elements of the array, when the array is used where a slice is expected
+    fn slice_of_array(&mut self, expr: ExprRef<'expr>, ty: TypeRef<'tcx>) -> ExprRef<'expr> {
+        match (self.type_by_expr.of(expr), ty) {
+            (Type::Array(_, item_ty), Type::Slice(_)) => {
+                let start = self.make_expr(&Type::U32, Expression::Integer(0u32.into()));
+                let slice = Expression::Slice {
+                    base: expr,
+                    start,
+                    end: None,
+                };
+                self.make_expr(self.arena.alloc(Type::Slice(item_ty)), slice)
+            }
+            _ => expr,
+        }
+    }
+
+    fn binding_ty(
+        &mut self,
+        expected_ty: &Option<ast::Type>,
+        expr: ExprRef<'expr>,
+        span: Span,
+    ) -> Option<TypeRef<'tcx>> {
+        let Some(expected) = expected_ty else {
+            return Some(self.type_by_expr.of(expr));
+        };
+        let target_ty = self.unify(expected);
+        if let (ast::Type::ConstArray(name, _), Type::Error) = (expected, target_ty) {
+            self.error(span, format!("length of array {} is not a constant", name));
+            return None;
+        }
+        let source_ty = self.type_by_expr.of(expr);
+        if is_compatible_to(target_ty, source_ty) || self.type_by_expr.try_coerce(expr, target_ty) {
+            Some(target_ty)
+        } else {
+            log::debug!(
+                "{}: mismatched types. expected {:?}, got {:?}",
+                span,
+                target_ty,
+                source_ty
+            );
+            None
+        }
+    }
+
+    /// Type which values of the abstract type take when nothing else tells it
+    fn default_of(&self, ty: TypeRef<'tcx>) -> TypeRef<'tcx> {
+        match ty {
+            Type::Integer => &Type::I32,
+            Type::Float => &Type::F32,
+            Type::Tuple(types) => self.arena.alloc(Type::Tuple(types.iter().map(|ty| self.default_of(ty)).collect())),
+            ty => ty,
+        }
+    }
+
+    /// Returns names bound by the pattern along with the index and the type of the field bound to each of them
+    fn destructure(
+        &self,
+        pattern: &'ast ast::Destructuring,
+        ty: TypeRef<'tcx>,
+    ) -> Option<Vec<(usize, &'ast str, TypeRef<'tcx>)>> {
+        match (pattern, ty) {
+            (ast::Destructuring::Tuple(names), Type::Tuple(types)) if names.len() == types.len() => Some(
+                std::iter::zip(names, types)
+                    .enumerate()
+                    .map(|(idx, (name, ty))| (idx, name.as_str(), *ty))
+                    .collect(),
+            ),
+            (ast::Destructuring::Struct { r#type, fields }, Type::Struct { fields: types }) => {
+                // Generic structs are instantiated, so only names of the fields are compared
+                let Some(Type::Struct { fields: defined }) = self.defined_types.get(r#type.as_str()) else {
+                    return None;
+                };
+                if !defined.iter().map(|(name, _)| name).eq(types.iter().map(|(name, _)| name)) {
+                    return None;
+                }
+                fields
+                    .iter()
+                    .map(|(field, name)| {
+                        let idx = types.iter().position(|(it, _)| it == field)?;
+                        Some((idx, name.as_str(), types[idx].1))
+                    })
+                    .collect()
+            }
+            _ => None,
+        }
+    }
+
     pub(crate) fn infer_types(
         &mut self,
         items: &'ast [ast::Item],
         expected_ret_ty: Option<TypeRef<'tcx>>,
     ) -> Vec<Item<'expr, 'tcx>> {
+        let ret_ty = std::mem::replace(&mut self.ret_ty, expected_ret_ty);
+        let hoisted = std::mem::take(&mut self.hoisted);
         let mut lowered_items = Vec::new();
         for item in items.iter() {
-            let item = match item {
-                ast::Item::Let {
+            // Drop whatever was left by an item that failed to type check
+            self.hoisted.clear();
+            let kind = match &item.kind {
+                ast::ItemKind::Let {
                     name,
                     r#type: expected_ty,
                     expr,
                 } => {
                     let Some(expr) = expr else {
-                        log::debug!("no expression on the right hand side of the let binding");
+                        log::debug!("{}: no expression on the right hand side of the let binding", item.span);
                         continue;
                     };
 
                     let expr = self.deduce_expr_ty(expr);
+                    let expr = match expected_ty {
+                        Some(expected_ty) => self.slice_of_array(expr, self.unify(expected_ty)),
+                        None => expr,
+                    };
                     log::debug!("deduced type {:?} for binding {}", self.type_by_expr.of(expr), name);
-                    let ty = match expected_ty {
-                        Some(expected) => {
-                            let target_ty = self.unify(expected);
-                            let source_ty = self.type_by_expr.of(expr);
-                            if is_compatible_to(target_ty, source_ty)
-                                || self.type_by_expr.try_coerce(expr, target_ty)
-                            {
-                                target_ty
-                            } else {
-                                log::debug!(
-                                    "mismatched types. expected {:?}, got {:?}",
-                                    target_ty,
-                                    source_ty
-                                );
-                                continue;
-                            }
-                        }
-                        None => self.type_by_expr.of(expr),
+                    let Some(ty) = self.binding_ty(expected_ty, expr, item.span) else {
+                        continue;
                     };
                     self.locals.insert(name, ty);
+                    self.bindings.push(name);
+                    self.untyped_lets.retain(|(it, _)| it != name);
+                    if let Type::Integer = ty {
+                        self.untyped_lets.push((name, expr));
+                    }
 
-                    Item::Let {
+                    ItemKind::Let {
                         name: name.clone(),
                         ty,
                         expr: Some(expr),
                     }
                 }
-                ast::Item::Assignment {
+                ast::ItemKind::LetPattern {
+                    pattern,
+                    r#type: expected_ty,
+                    expr,
+                } => {
+                    let expr = self.deduce_expr_ty(expr);
+                    let Some(ty) = self.binding_ty(expected_ty, expr, item.span) else {
+                        continue;
+                    };
+                    let Some(fields) = self.destructure(pattern, ty) else {
+                        let pattern = match pattern {
+                            ast::Destructuring::Tuple(names) => format!("({})", names.join(", ")),
+                            ast::Destructuring::Struct { r#type, .. } => format!("{} {{ .. }}", r#type),
+                        };
+                        self.error(item.span, format!("pattern {} does not match {:?}", pattern, ty));
+                        continue;
+                    };
+                    let bindings = fields
+                        .into_iter()
+                        .filter(|(_, name, _)| *name != "_")
+                        .map(|(idx, name, ty)| {
+                            self.locals.insert(name, ty);
+                            self.bindings.push(name);
+                            (idx, name.to_string(), ty)
+                        })
+                        .collect();
+
+                    ItemKind::LetPattern { bindings, expr }
+                }
+                ast::ItemKind::Assignment {
                     lhs: lhs_expr,
                     operator,
                     expr: rhs_expr,
                 } => {
+                    if let ast::Expr::Identifier(name) = self.ast_expr_arena.resolve(*lhs_expr) {
+                        if !self.bindings.contains(&name.as_str())
+                            && matches!(
+                                self.globals.get(name.as_str()),
+                                Some(Global::Const(_) | Global::Static { mutable: false, .. })
+                            )
+                        {
+                            self.error(item.span, format!("cannot assign to {}, it is not a mutable static", name));
+                            continue;
+                        }
+                    }
                     let lhs = self.deduce_expr_ty(lhs_expr);
                     let rhs = self.deduce_expr_ty(rhs_expr);
 
                     if is_compatible_to(self.type_by_expr.of(lhs), self.type_by_expr.of(rhs))
                         || self.type_by_expr.try_coerce_any(lhs, rhs)
                     {
-                        match self.ast_expr_arena.resolve(*lhs_expr) {
-                            ast::Expr::Identifier(name) => {
-                                self.locals.insert(name, self.type_by_expr.of(rhs));
-                            }
-                            _ => (),
+                        if let ast::Expr::Identifier(name) = self.ast_expr_arena.resolve(*lhs_expr) {
+                            self.locals.insert(name, self.type_by_expr.of(rhs));
                         }
 
                         //
                     } else {
                         log::debug!(
-                            "incompatible types in assignment, got {:?} and {:?}",
+                            "{}: incompatible types in assignment, got {:?} and {:?}",
+                            item.span,
                             self.type_by_expr.of(lhs),
                             self.type_by_expr.of(rhs)
                         );
                         continue;
                     }
 
-                    Item::Assignment {
+                    ItemKind::Assignment {
                         lhs,
                         operator: *operator,
                         expr: rhs,
                     }
                 }
-                ast::Item::Expr { expr } => {
+                ast::ItemKind::Expr { expr } | ast::ItemKind::Tail { expr } => {
                     let expr = self.deduce_expr_ty(expr);
-                    Item::Expression { expr }
+                    ItemKind::Expression { expr }
                 }
-                ast::Item::Function {
+                ast::ItemKind::Function {
                     name,
+                    attributes,
+                    type_params,
+                    is_extern,
                     params,
                     ty,
                     body,
-                    ..
                 } => {
-                    let mut args = Vec::new();
-                    for param in params {
-                        let ty = self.unify(&param.r#type);
-                        log::debug!("Found arg {} of type {:?}", &param.name, ty);
-                        self.locals
-                            .insert(param.name.as_str(), self.unify(&param.r#type));
-                        args.push(ty);
+                    // Generic function is checked once, with placeholders standing for type arguments
+                    let outer = std::mem::replace(
+                        &mut self.type_params,
+                        type_params.iter().map(String::as_str).collect(),
+                    );
+                    let func_ty = self.function_ty(params, ty, body);
+                    // Tests are run without arguments and pass when they give true
+                    if ast::attribute(attributes, "test").is_some()
+                        && !matches!(func_ty, Type::Function(args, Type::Bool) if args.is_empty() && type_params.is_empty())
+                    {
+                        log::debug!("{}: test {} must take no arguments and return bool", item.span, name);
+                        self.type_params = outer;
+                        continue;
+                    }
+                    // Mismatched declaration is still bound, so that its calls are checked against it
+                    if *is_extern {
+                        self.check_host_function(name, func_ty, item.span);
                     }
-
-                    let func_ty = Type::Function(args, self.unify(ty));
-                    let func_ty = self.arena.alloc(func_ty);
                     self.locals.insert(name.as_str(), func_ty);
-
-                    let body = self.infer_types(body, Some(self.unify(ty)));
-                    Item::Function {
-                        name: name.clone(),
-                        is_extern: false,
-                        args: params
-                            .iter()
-                            .map(|it| Argument {
-                                name: it.name.clone(),
-                                ty: self.unify(&it.r#type),
-                            })
-                            .collect(),
-                        ty: self.unify(ty),
-                        body,
+                    self.check_attributes(attributes, item.span);
+                    self.attributes.insert(name.clone(), attributes);
+                    let arithmetic = std::mem::take(&mut self.arithmetic_params);
+                    let kind =
+                        self.infer_function(name.clone(), attributes, type_params, *is_extern, params, func_ty, body);
+                    let arithmetic = std::mem::replace(&mut self.arithmetic_params, arithmetic);
+                    if !arithmetic.is_empty() {
+                        self.arithmetic.insert(name.clone(), arithmetic);
                     }
+                    self.type_params = outer;
+                    kind
                 }
-                ast::Item::Struct { name, fields } => {
+                ast::ItemKind::Struct {
+                    name,
+                    attributes,
+                    type_params,
+                    fields,
+                } => {
+                    let outer = std::mem::replace(
+                        &mut self.type_params,
+                        type_params.iter().map(String::as_str).collect(),
+                    );
                     let fields: Vec<_> = fields
                         .iter()
                         .map(|field| (field.name.clone(), self.unify(&field.r#type)))
                         .collect();
+                    self.type_params = outer;
                     self.defined_types
                         .insert(name, self.arena.alloc(Type::Struct { fields }));
+                    self.check_attributes(attributes, item.span);
+                    self.attributes.insert(name.clone(), attributes);
+                    ItemKind::Struct {
+                        name: name.clone(),
+                        attributes: attributes.clone(),
+                    }
+                }
+                ast::ItemKind::Enum { name, variants } => {
+                    let variants = variants
+                        .iter()
+                        .map(|variant| {
+                            let types = variant.types.iter().map(|ty| self.unify(ty)).collect();
+                            (variant.name.clone(), types)
+                        })
+                        .collect();
+                    self.defined_types
+                        .insert(name, self.arena.alloc(Type::Enum { variants }));
+                    continue;
+                }
+                ast::ItemKind::Const { name, r#type, expr } => {
+                    let Some(value) = self.global_value(name, r#type, expr, item.span) else {
+                        continue;
+                    };
+                    self.globals.insert(name, Global::Const(value));
                     continue;
                 }
-                ast::Item::If {
+                ast::ItemKind::Static {
+                    name,
+                    mutable,
+                    r#type,
+                    expr,
+                } => {
+                    let Some(value) = self.global_value(name, r#type, expr, item.span) else {
+                        continue;
+                    };
+                    let ty = self.type_by_expr.of(value);
+                    self.globals.insert(name, Global::Static { ty, mutable: *mutable });
+                    ItemKind::Static {
+                        name: name.clone(),
+                        value,
+                    }
+                }
+                // Imported modules are checked before the importing file
+                ast::ItemKind::Import { .. } => continue,
+                ast::ItemKind::Impl { name, functions } => {
+                    let Some(&self_ty) = self.defined_types.get(name.as_str()) else {
+                        log::debug!("{}: impl of unknown type {}", item.span, name);
+                        continue;
+                    };
+                    for function in functions {
+                        let ast::ItemKind::Function {
+                            name: method,
+                            attributes,
+                            params,
+                            ty,
+                            body,
+                            ..
+                        } = &function.kind
+                        else {
+                            unreachable!("impl contains only functions");
+                        };
+                        let func_ty = self.function_ty(params, ty, body);
+                        match func_ty {
+                            Type::Function(args, _)
+                                if args.first().is_some_and(|it| Self::receives(it, self_ty)) => {}
+                            _ => {
+                                log::debug!("{}: method {} takes no self of type {}", function.span, method, name);
+                                continue;
+                            }
+                        }
+                        self.methods.insert((name.as_str(), method.as_str()), func_ty);
+                        let qualified = format!("{}::{}", name, method);
+                        self.check_attributes(attributes, function.span);
+                        self.attributes.insert(qualified.clone(), attributes);
+                        let kind = self.infer_function(qualified, attributes, &[], false, params, func_ty, body);
+                        lowered_items.push(Item {
+                            kind,
+                            span: function.span,
+                        });
+                    }
+                    continue;
+                }
+                ast::ItemKind::If {
                     condition,
                     arm_true,
                     arm_false,
@@ -620,83 +1726,619 @@ where
                     let cond = self.deduce_expr_ty(condition);
                     if !is_compatible_to(self.type_by_expr.of(cond), self.arena.alloc(Type::Bool)) {
                         log::debug!(
-                            "only boolean expressions are allowed in if conditions, got {:?}",
+                            "{}: only boolean expressions are allowed in if conditions, got {:?}",
+                            item.span,
                             self.type_by_expr.of(cond)
                         );
                         continue;
                     }
-                    Item::If {
+                    ItemKind::If {
                         condition: cond,
                         arm_true: self.infer_types(arm_true, expected_ret_ty),
-                        arm_false: if let Some(arm_false) = arm_false {
-                            Some(self.infer_types(arm_false, expected_ret_ty))
-                        } else {
-                            None
-                        },
+                        arm_false: arm_false.as_ref().map(|arm_false| self.infer_types(arm_false, expected_ret_ty)),
                     }
                 }
-                ast::Item::ForIn { name, expr, body } => {
+                ast::ItemKind::ForIn {
+                    label,
+                    name,
+                    expr,
+                    body,
+                } => {
+                    self.iterated = matches!(self.ast_expr_arena.resolve(*expr), ast::Expr::Call(..));
                     let expr = self.deduce_expr_ty(expr);
-                    let is_iterable = match self.type_by_expr.of(expr) {
-                        Type::Array(_, _) | Type::Slice(_) => true,
-                        Type::Range => true,
-                        _ => false,
+                    self.iterated = false;
+                    let item_ty = match self.type_by_expr.of(expr) {
+                        Type::Array(_, item_ty) => *item_ty,
+                        Type::Slice(item_ty) => *item_ty,
+                        Type::Range(item_ty) => *item_ty,
+                        Type::Generator(item_ty) => *item_ty,
+                        Type::Error => continue,
+                        ty => {
+                            self.error(item.span, format!("{:?} is not iterable", ty));
+                            continue;
+                        }
                     };
-                    if !is_iterable {
-                        log::debug!("{:?} is not iterable", self.type_by_expr.of(expr));
-                        continue;
-                    }
-                    self.locals
-                        .insert(name.as_str(), self.arena.alloc(Type::U32));
-                    let body = self.infer_types(body, expected_ret_ty);
-                    Item::ForIn {
+                    self.locals.insert(name.as_str(), item_ty);
+                    self.bindings.push(name);
+                    let (body, _) = self.infer_loop(label, false, None, body);
+                    ItemKind::ForIn {
+                        label: label.clone(),
                         name: name.clone(),
                         expr,
                         body,
                     }
                 }
-                ast::Item::Loop { body } => Item::Loop {
-                    body: self.infer_types(body, expected_ret_ty),
+                ast::ItemKind::Loop { label, body } => ItemKind::Loop {
+                    label: label.clone(),
+                    body: self.infer_loop(label, true, None, body).0,
                 },
-                ast::Item::Return(expr) => {
+                ast::ItemKind::While {
+                    label,
+                    condition,
+                    body,
+                } => {
+                    let cond = self.deduce_expr_ty(condition);
+                    if !is_compatible_to(self.type_by_expr.of(cond), self.arena.alloc(Type::Bool)) {
+                        let span = self.span_by_expr.of(cond).unwrap_or(item.span);
+                        let message = format!(
+                            "only boolean expressions are allowed in while conditions, got {:?}",
+                            self.type_by_expr.of(cond)
+                        );
+                        self.error(span, message);
+                        continue;
+                    }
+                    ItemKind::While {
+                        label: label.clone(),
+                        condition: cond,
+                        body: self.infer_loop(label, false, None, body).0,
+                    }
+                }
+                ast::ItemKind::Return(expr) => {
                     if expected_ret_ty.is_none() {
                         panic!("return outside of a function");
                     }
+                    // Generator gives its items only by `yield`, returning finishes it
+                    let expr = match (expr, expected_ret_ty.unwrap()) {
+                        (None, Type::Generator(_) | Type::Unit) => None,
+                        (Some(_), Type::Generator(_)) => {
+                            self.error(item.span, "generator cannot return a value, it gives items by yield".to_string());
+                            continue;
+                        }
+                        (None, ty) => {
+                            log::debug!("{}: function marked as returning {:?} but returned nothing", item.span, ty);
+                            continue;
+                        }
+                        (Some(expr), _) => Some(self.deduce_expr_ty(expr)),
+                    };
+                    if let Some(expr) = expr {
+                        if !is_compatible_to(self.type_by_expr.of(expr), expected_ret_ty.unwrap())
+                            && !self.type_by_expr.try_coerce(expr, expected_ret_ty.unwrap())
+                        {
+                            log::debug!(
+                                "{}: function marked as returning {:?} but returned {:?}",
+                                item.span,
+                                expected_ret_ty.unwrap(),
+                                expr
+                            );
+                            continue;
+                        }
+                    }
+                    ItemKind::Return(expr)
+                }
+                ast::ItemKind::Break { label, value } => {
+                    let Some(target) = self.enclosing_loop(label) else {
+                        self.error(item.span, outside_of_loop("break", label));
+                        continue;
+                    };
+                    if let Some(value) = value {
+                        if !self.loops[target].breaks_with_value {
+                            log::debug!("{}: only `loop` can be broken out of with a value", item.span);
+                            continue;
+                        }
+                        let value = self.deduce_expr_ty(value);
+                        match self.loops[target].value.clone() {
+                            Some((name, ty)) => {
+                                // The first value decides the type of the loop
+                                let ty = ty.unwrap_or_else(|| match self.type_by_expr.of(value) {
+                                    Type::Integer => &Type::I32,
+                                    Type::Float => &Type::F32,
+                                    ty => ty,
+                                });
+                                if !is_compatible_to(self.type_by_expr.of(value), ty)
+                                    && !self.type_by_expr.try_coerce(value, ty)
+                                {
+                                    log::debug!(
+                                        "{}: loop is broken out of with {:?}, but got {:?}",
+                                        item.span,
+                                        ty,
+                                        self.type_by_expr.of(value)
+                                    );
+                                    continue;
+                                }
+                                self.loops[target].value = Some((name.clone(), Some(ty)));
+                                let lhs = self.make_expr(ty, Expression::Identifier(name));
+                                self.hoisted.push(Item {
+                                    kind: ItemKind::Assignment {
+                                        lhs,
+                                        operator: None,
+                                        expr: value,
+                                    },
+                                    span: item.span,
+                                });
+                            }
+                            // Value of a loop used as a statement is discarded
+                            None => self.hoisted.push(Item {
+                                kind: ItemKind::Expression { expr: value },
+                                span: item.span,
+                            }),
+                        }
+                    }
+                    ItemKind::Break {
+                        label: label.clone(),
+                    }
+                }
+                ast::ItemKind::Continue { label } => {
+                    if self.enclosing_loop(label).is_none() {
+                        self.error(item.span, outside_of_loop("continue", label));
+                        continue;
+                    }
+                    ItemKind::Continue {
+                        label: label.clone(),
+                    }
+                }
+                ast::ItemKind::Yield(expr) => {
+                    let Some(Type::Generator(item_ty)) = expected_ret_ty else {
+                        log::debug!("{}: yield outside of a function", item.span);
+                        continue;
+                    };
                     let expr = self.deduce_expr_ty(expr);
-                    if is_compatible_to(self.type_by_expr.of(expr), expected_ret_ty.unwrap())
-                        || self.type_by_expr.try_coerce(expr, expected_ret_ty.unwrap())
+                    if is_compatible_to(self.type_by_expr.of(expr), item_ty)
+                        || self.type_by_expr.try_coerce(expr, item_ty)
                     {
                         //
                     } else {
                         log::debug!(
-                            "function marked as returning {:?} but returned {:?}",
-                            expected_ret_ty.unwrap(),
-                            expr
+                            "{}: function marked as yielding {:?} but yielded {:?}",
+                            item.span,
+                            item_ty,
+                            self.type_by_expr.of(expr)
                         );
                         continue;
                     }
-                    Item::Return(expr)
+                    ItemKind::Yield(expr)
                 }
-                ast::Item::Break => Item::Break,
-                ast::Item::Yield(_) => unimplemented!(),
-                ast::Item::Block(body) => {
+                ast::ItemKind::Block(body) => {
                     self.infer_types(body, expected_ret_ty);
                     todo!()
                 }
-                ast::Item::Assert(expr) => {
+                ast::ItemKind::Assert(expr) => {
                     let expr = self.deduce_expr_ty(expr);
-                    Item::Assert(expr)
+                    ItemKind::Assert(expr)
                 }
             };
 
-            lowered_items.push(item);
+            lowered_items.append(&mut self.hoisted);
+            lowered_items.push(Item {
+                kind,
+                span: item.span,
+            });
         }
 
+        // Types of the literals are settled once the top level items are checked
+        if ret_ty.is_none() && expected_ret_ty.is_none() {
+            self.check_literals();
+        }
+        self.ret_ty = ret_ty;
+        self.hoisted = hoisted;
         lowered_items
     }
 
+    fn check_literals(&mut self) {
+        for (expr, value) in std::mem::take(&mut self.literals) {
+            // Literals whose use does not tell their type are `i32`
+            let ty = match self.type_by_expr.of(expr) {
+                Type::Integer => &Type::I32,
+                ty => ty,
+            };
+            let fits = match ty {
+                Type::U32 => u32::try_from(value).is_ok(),
+                Type::I32 => i32::try_from(value).is_ok(),
+                _ => true,
+            };
+            if !fits {
+                let span = self.span_by_expr.of(expr).unwrap_or_default();
+                self.error(span, format!("{} does not fit in {:?}", value, ty));
+            }
+        }
+    }
+
+    /// Type checks body of a loop, returns it along with the type of the value it is broken out with
+    ///
+    /// When the loop is used as an expression, `value` names the local its value is stored in.
+    fn infer_loop(
+        &mut self,
+        label: &'ast Option<String>,
+        breaks_with_value: bool,
+        value: Option<String>,
+        body: &'ast [ast::Item],
+    ) -> (Vec<Item<'expr, 'tcx>>, Option<TypeRef<'tcx>>) {
+        self.loops.push(EnclosingLoop {
+            label: label.as_deref(),
+            breaks_with_value,
+            value: value.map(|name| (name, None)),
+        });
+        let body = self.infer_types(body, self.ret_ty);
+        let enclosing = self.loops.pop().unwrap();
+        (body, enclosing.value.and_then(|(_, ty)| ty))
+    }
+
+    /// Returns type of the enum, index of its variant with given name and types of its payload
+    fn variant(&mut self, r#type: &str, name: &str, span: Span) -> Option<(TypeRef<'tcx>, usize, &'tcx [TypeRef<'tcx>])> {
+        let ty = match self.defined_types.get(r#type) {
+            Some(ty @ Type::Enum { .. }) => *ty,
+            _ => {
+                self.error(span, format!("{} is not an enum", r#type));
+                return None;
+            }
+        };
+        let Type::Enum { variants } = ty else {
+            unreachable!();
+        };
+        match variants.iter().position(|(it, _)| it == name) {
+            Some(idx) => Some((ty, idx, &variants[idx].1)),
+            None => {
+                self.error(span, format!("{} has no variant {}", r#type, name));
+                None
+            }
+        }
+    }
+
+    /// Type checks arguments of a call against the types the callee takes, adding them to `values`
+    fn deduce_args(
+        &mut self,
+        args: &Handle<ast::Expr, Many>,
+        args_ty: &[TypeRef<'tcx>],
+        values: &mut Vec<ExprRef<'expr>>,
+    ) -> bool {
+        for (arg, expected_ty) in args.into_iter().zip(args_ty) {
+            let arg = self.deduce_expr_ty(&arg);
+            let arg = self.slice_of_array(arg, expected_ty);
+
+            if is_compatible_to(self.type_by_expr.of(arg), expected_ty)
+                || self.type_by_expr.try_coerce(arg, expected_ty)
+            {
+                //
+            } else {
+                log::debug!(
+                    "incompatible types {:?} and {:?}",
+                    self.type_by_expr.of(arg),
+                    expected_ty
+                );
+                return false;
+            }
+
+            values.push(arg);
+        }
+        true
+    }
+
+    /// Returns type of the function, calling a function which yields gives a generator
+    fn function_ty(&self, params: &[ast::Argument], ty: &ast::Type, body: &[ast::Item]) -> TypeRef<'tcx> {
+        let args = params.iter().map(|param| self.unify(&param.r#type)).collect();
+        let ret_ty = if yields(body) {
+            self.arena.alloc(Type::Generator(self.unify(ty)))
+        } else {
+            self.unify(ty)
+        };
+        self.arena.alloc(Type::Function(args, ret_ty))
+    }
+
+    /// Type checks body of the function of given type
+    #[allow(clippy::too_many_arguments)]
+    fn infer_function(
+        &mut self,
+        name: String,
+        attributes: &[ast::Attribute],
+        type_params: &[String],
+        is_extern: bool,
+        params: &'ast [ast::Argument],
+        func_ty: TypeRef<'tcx>,
+        body: &'ast [ast::Item],
+    ) -> ItemKind<'expr, 'tcx> {
+        let Type::Function(args_ty, ret_ty) = func_ty else {
+            unreachable!("{:?} is not a function", func_ty);
+        };
+        // Parameters and locals go out of scope with the function, uncovering what they shadowed
+        let locals = self.locals.clone();
+        for (param, ty) in params.iter().zip(args_ty) {
+            log::debug!("Found arg {} of type {:?}", &param.name, ty);
+            self.locals.insert(param.name.as_str(), ty);
+        }
+
+        let bindings = params.iter().map(|param| param.name.as_str()).collect();
+        let bindings = std::mem::replace(&mut self.bindings, bindings);
+        let untyped_lets = std::mem::take(&mut self.untyped_lets);
+        let body = self.infer_types(body, Some(ret_ty));
+        for (name, expr) in std::mem::replace(&mut self.untyped_lets, untyped_lets) {
+            if let ty @ (Type::I32 | Type::U32) = self.locals[name] {
+                self.type_by_expr.try_coerce(expr, ty);
+            }
+        }
+        self.bindings = bindings;
+        self.locals = locals;
+        ItemKind::Function {
+            name,
+            attributes: attributes.to_vec(),
+            type_params: type_params.to_vec(),
+            is_extern,
+            args: params
+                .iter()
+                .zip(args_ty)
+                .map(|(param, ty)| Argument {
+                    name: param.name.clone(),
+                    ty,
+                })
+                .collect(),
+            ty: ret_ty,
+            body,
+        }
+    }
+
+    /// Checks whether the `extern fn` declaration matches the signature of the host function
+    fn check_host_function(&mut self, name: &str, func_ty: TypeRef<'tcx>, span: Span) {
+        let Some(function) = self.host.get(name) else {
+            self.error(span, format!("no host function {}", name));
+            return;
+        };
+        let (args, ret) = function.signature();
+        let matches = match func_ty {
+            Type::Function(declared, declared_ret) => {
+                declared.len() == args.len()
+                    && std::iter::zip(declared, args).all(|(declared, arg)| is_compatible_to(declared, arg))
+                    && is_compatible_to(declared_ret, ret)
+            }
+            _ => false,
+        };
+        if !matches {
+            self.error(
+                span,
+                format!(
+                    "{} is declared as {:?}, but the host function takes {:?} and returns {:?}",
+                    name, func_ty, args, ret
+                ),
+            );
+        }
+    }
+
+    /// Reports a problem which makes the program rejected
+    fn error(&mut self, span: Span, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            span,
+            message,
+        });
+    }
+
+    /// Reports a problem which does not prevent running the program
+    fn warning(&mut self, span: Span, message: String) {
+        self.diagnostics.push(Diagnostic {
+            severity: Severity::Warning,
+            span,
+            message,
+        });
+    }
+
+    /// Warns about a use of the function, the method or the struct marked as deprecated
+    fn check_deprecated(&mut self, name: &str, span: Span) {
+        let Some(attribute) = self.attributes.get(name).and_then(|it| ast::attribute(it, "deprecated")) else {
+            return;
+        };
+        let message = match attribute.args.first() {
+            Some(note) => format!("{} is deprecated, {}", name, note),
+            None => format!("{} is deprecated", name),
+        };
+        self.warning(span, message);
+    }
+
+    /// Checks whether values of the type can be operands of arithmetic. Type parameter of the
+    /// generic function being checked is recorded as having to be a number
+    fn requires_number(&mut self, ty: TypeRef<'tcx>) -> bool {
+        match ty {
+            Type::Placeholder(idx) => {
+                if !self.arithmetic_params.contains(idx) {
+                    self.arithmetic_params.push(*idx);
+                }
+                true
+            }
+            Type::Integer | Type::Float | Type::I32 | Type::U32 | Type::F32 => true,
+            _ => false,
+        }
+    }
+
+    /// Warns about attributes of the item which have no meaning
+    fn check_attributes(&mut self, attributes: &[ast::Attribute], span: Span) {
+        for attribute in attributes {
+            if !KNOWN_ATTRIBUTES.contains(&attribute.name.as_str()) {
+                self.warning(span, format!("unknown attribute {}", attribute.name));
+            }
+        }
+    }
+
+    /// Computes value of the constant or the initial value of the static while type checking
+    fn global_value(
+        &mut self,
+        name: &str,
+        ty: &ast::Type,
+        expr: &Handle<ast::Expr>,
+        span: Span,
+    ) -> Option<ExprRef<'expr>> {
+        let expr = self.deduce_expr_ty(expr);
+        let ty = self.unify(ty);
+        if !is_compatible_to(self.type_by_expr.of(expr), ty) && !self.type_by_expr.try_coerce(expr, ty) {
+            log::debug!("{}: mismatched types. expected {:?}, got {:?}", span, ty, self.type_by_expr.of(expr));
+            return None;
+        }
+        let value = match expr.as_const(&self.type_by_expr) {
+            Some(Const::Integer(bits)) => Expression::Integer(bits),
+            Some(Const::F32(value)) => Expression::Float(value.into()),
+            Some(Const::Bool(value)) => Expression::Bool(value),
+            _ => {
+                log::debug!("{}: value of {} is not known at compile time", span, name);
+                return None;
+            }
+        };
+        Some(self.make_expr(ty, value))
+    }
+
+    /// Records use of the binding, the closure being checked captures it when it was bound outside
+    fn capture(&mut self, name: &'ast str) {
+        if let Some((outer, captured)) = &mut self.captures {
+            if !self.bindings[*outer..].contains(&name)
+                && self.bindings[..*outer].contains(&name)
+                && !captured.contains(&name)
+            {
+                captured.push(name);
+            }
+        }
+    }
+
+    /// Type checks body of the closure as a function of its own, returns its value
+    ///
+    /// Function takes values of the captured bindings first, followed by parameters of the closure.
+    fn infer_closure(
+        &mut self,
+        name: String,
+        params: &'ast [ast::Argument],
+        ty: &'ast Option<ast::Type>,
+        body: &'ast Handle<ast::Expr>,
+        span: Span,
+    ) -> (Expression<'expr>, TypeRef<'tcx>) {
+        let args_ty: Vec<_> = params.iter().map(|param| self.unify(&param.r#type)).collect();
+        let expected_ty = ty.as_ref().map(|ty| self.unify(ty));
+        for (param, ty) in params.iter().zip(&args_ty) {
+            self.locals.insert(param.name.as_str(), ty);
+        }
+
+        let outer = self.bindings.len();
+        let captures = self.captures.replace((outer, Vec::new()));
+        self.bindings.extend(params.iter().map(|param| param.name.as_str()));
+        let hoisted = std::mem::take(&mut self.hoisted);
+        let loops = std::mem::take(&mut self.loops);
+        let ret_ty = self.ret_ty.replace(expected_ty.unwrap_or(&Type::Any));
+
+        let value = self.deduce_expr_ty(body);
+        let value_ty = match expected_ty {
+            Some(ty) if is_compatible_to(self.type_by_expr.of(value), ty) || self.type_by_expr.try_coerce(value, ty) => ty,
+            Some(ty) => {
+                log::debug!("{}: closure marked as returning {:?} but returned {:?}", span, ty, self.type_by_expr.of(value));
+                self.arena.alloc(Type::Error)
+            }
+            None => match self.type_by_expr.of(value) {
+                Type::Integer if self.type_by_expr.try_coerce(value, &Type::I32) => &Type::I32,
+                Type::Float if self.type_by_expr.try_coerce(value, &Type::F32) => &Type::F32,
+                ty => ty,
+            },
+        };
+        let mut items = std::mem::replace(&mut self.hoisted, hoisted);
+        items.push(Item {
+            kind: ItemKind::Return(Some(value)),
+            span,
+        });
+
+        self.ret_ty = ret_ty;
+        self.loops = loops;
+        self.bindings.truncate(outer);
+        let (_, captured) = std::mem::replace(&mut self.captures, captures).unwrap();
+        // Enclosing closure has to capture the bindings it does not own as well
+        for name in &captured {
+            self.capture(name);
+        }
+
+        let args = captured
+            .iter()
+            .map(|name| Argument {
+                name: name.to_string(),
+                ty: self.locals[name],
+            })
+            .chain(params.iter().zip(&args_ty).map(|(param, ty)| Argument {
+                name: param.name.clone(),
+                ty,
+            }))
+            .collect();
+        self.closures.push(Item {
+            kind: ItemKind::Function {
+                name: name.clone(),
+                attributes: Vec::new(),
+                type_params: self.type_params.iter().map(|it| it.to_string()).collect(),
+                is_extern: false,
+                args,
+                ty: value_ty,
+                body: items,
+            },
+            span,
+        });
+
+        let values = captured
+            .iter()
+            .map(|name| self.make_expr(self.locals[name], Expression::Identifier(name.to_string())))
+            .collect();
+        (
+            Expression::Closure(name, values),
+            self.arena.alloc(Type::Function(args_ty, value_ty)),
+        )
+    }
+
+    /// Checks whether the first argument of a method can take a value of the type, or its pointer
+    fn receives(ty: TypeRef<'tcx>, self_ty: TypeRef<'tcx>) -> bool {
+        match ty {
+            Type::Pointer(ty) => std::ptr::eq(*ty, self_ty),
+            ty => std::ptr::eq(ty, self_ty),
+        }
+    }
+
+    /// Returns index of the loop `break` or `continue` with the given label refers to
+    fn enclosing_loop(&self, label: &Option<String>) -> Option<usize> {
+        match label {
+            Some(label) => self.loops.iter().rposition(|it| it.label == Some(label.as_str())),
+            None => self.loops.len().checked_sub(1),
+        }
+    }
+
+    /// Type checks block used as a value, returns its items and the expression giving the value
+    fn infer_block(
+        &mut self,
+        items: &'ast [ast::Item],
+    ) -> (Vec<Item<'expr, 'tcx>>, Option<ExprRef<'expr>>) {
+        let (tail, items) = match items.split_last() {
+            Some((ast::Item { kind: ast::ItemKind::Tail { expr }, .. }, items)) => (Some(expr), items),
+            _ => (None, items),
+        };
+        let mut items = self.infer_types(items, self.ret_ty);
+        let tail = tail.map(|expr| {
+            let hoisted = std::mem::take(&mut self.hoisted);
+            let expr = self.deduce_expr_ty(expr);
+            items.append(&mut std::mem::replace(&mut self.hoisted, hoisted));
+            expr
+        });
+        (items, tail)
+    }
+
     fn unify(&self, ty: &ast::Type) -> TypeRef<'tcx> {
         match ty {
+            ast::Type::Name(name) if self.type_params.contains(&name.as_str()) => {
+                let idx = self.type_params.iter().position(|it| it == name).unwrap();
+                self.arena.alloc(Type::Placeholder(idx))
+            }
+            ast::Type::Generic(name, args) => {
+                let args: Vec<_> = args.iter().map(|it| self.unify(it)).collect();
+                match self.defined_types.get(name.as_str()) {
+                    Some(ty) if type_params_count(ty) == args.len() => substitute(self.arena, ty, &args),
+                    _ => {
+                        log::warn!("no generic type {:?} taking {} type arguments", name, args.len());
+                        &Type::Unknown
+                    }
+                }
+            }
             ast::Type::Name(name) => {
                 match name.as_str() {
                     "i32" => self.arena.alloc(Type::I32),
@@ -706,7 +2348,7 @@ where
                     custom if let Some(ty) = self.defined_types.get(custom) => ty,
                     custom => {
                         log::warn!("missing type for custom {:?}", custom);
-                        return &Type::Unknown;
+                        &Type::Unknown
                     }
                 }
             }
@@ -718,7 +2360,17 @@ where
             }
             ast::Type::Pointer(ty) => self.arena.alloc(Type::Pointer(self.unify(ty))),
             ast::Type::Array(len, ty) => self.arena.alloc(Type::Array(*len, self.unify(ty))),
+            ast::Type::ConstArray(name, ty) => match self.globals.get(name.as_str()) {
+                Some(Global::Const(Expression::Integer(len))) => {
+                    self.arena.alloc(Type::Array(len.as_usize(), self.unify(ty)))
+                }
+                _ => {
+                    log::warn!("length of array {} is not a constant", name);
+                    &Type::Error
+                }
+            },
             ast::Type::Slice(item_ty) => self.arena.alloc(Type::Slice(self.unify(item_ty))),
+            ast::Type::Range(item_ty) => self.arena.alloc(Type::Range(self.unify(item_ty))),
             ast::Type::Unit => self.arena.alloc(Type::Unit),
             ast::Type::Function(args_ty, ret_ty) => {
                 let args = args_ty.iter().map(|it| self.unify(it)).collect();
@@ -13,9 +13,16 @@ pub(crate) enum Type<'tcx> {
     Struct {
         fields: Vec<(String, TypeRef<'tcx>)>,
     },
+    /// Tagged union, each variant has a name and types of its payload
+    Enum {
+        variants: Vec<(String, Vec<TypeRef<'tcx>>)>,
+    },
     Function(Vec<TypeRef<'tcx>>, TypeRef<'tcx>),
     Pointer(TypeRef<'tcx>),
-    Range,
+    /// Value returned by calling a function which yields items of the given type
+    Generator(TypeRef<'tcx>),
+    /// Range with items of the given integer type
+    Range(TypeRef<'tcx>),
     #[default]
     Unknown,
     Error,
@@ -27,6 +34,7 @@ impl<'tcx> Type<'tcx> {
     pub(crate) fn is_abstract(&self) -> bool {
         match self {
             Type::Integer | Type::Float => true,
+            Type::Tuple(types) => types.iter().any(|ty| ty.is_abstract()),
             _ => false,
         }
     }
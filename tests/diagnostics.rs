@@ -0,0 +1,194 @@
+//! Checks the problems reported while compiling programs
+
+use std::process::Command;
+
+/// Compiles the program from the `tests` directory, returning what was printed, errors logged
+/// while running included
+fn compile(program: &str) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_untitled"))
+        .arg(format!("tests/{}", program))
+        .output()
+        .unwrap();
+    String::from_utf8(output.stdout).unwrap() + &String::from_utf8(output.stderr).unwrap()
+}
+
+#[test]
+fn unknown_host_function() {
+    let output = compile("unknown_host_function.ft");
+    assert!(output.contains("tests/unknown_host_function.ft:1:1: error: no host function nosuch"), "{}", output);
+    assert!(!output.contains("panicked"), "{}", output);
+}
+
+#[test]
+fn mismatched_host_function() {
+    let output = compile("mismatched_host_function.ft");
+    assert!(
+        output.contains("tests/mismatched_host_function.ft:1:1: error: abs is declared as Function([F32], F32)"),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn unknown_identifier() {
+    let output = compile("unknown_identifier.ft");
+    assert!(output.contains("tests/unknown_identifier.ft:2:5: error: unknown identifier nosuch"), "{}", output);
+}
+
+#[test]
+fn generator_misuse() {
+    let output = compile("generator_misuse.ft");
+    assert!(
+        output.contains("tests/generator_misuse.ft:7:5: error: generator cannot return a value"),
+        "{}",
+        output
+    );
+    assert!(
+        output.contains("tests/generator_misuse.ft:11:17: error: generator count_to can only be iterated by a for loop"),
+        "{}",
+        output
+    );
+    assert!(
+        output.contains("tests/generator_misuse.ft:16:8: error: generator count_to can only be iterated by a for loop"),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn attribute_warnings() {
+    let output = compile("attributes.ft");
+    assert!(output.contains("tests/attributes.ft:16:1: warning: unknown attribute packed"), "{}", output);
+    assert!(output.contains("tests/attributes.ft:29:15: warning: Old is deprecated\n"), "{}", output);
+    assert!(
+        output.contains("tests/attributes.ft:45:8: warning: plus is deprecated, use add instead"),
+        "{}",
+        output
+    );
+    // Warnings do not prevent running the program
+    assert!(output.contains("OK"), "{}", output);
+}
+
+#[test]
+fn arithmetic_on_type_parameter() {
+    let output = compile("generic_arithmetic.ft");
+    assert!(
+        output.contains("tests/generic_arithmetic.ft:14:5: error: sum applies arithmetic to its type parameter, which cannot be Bool"),
+        "{}",
+        output
+    );
+    // Constraint holds for generic functions passing their own type parameter on
+    assert!(
+        output.contains("tests/generic_arithmetic.ft:18:5: error: twice applies arithmetic to its type parameter, which cannot be Bool"),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn range_step() {
+    let output = compile("range_step.ft");
+    assert!(output.contains("tests/range_step.ft:3:32: error: step of a range has to be positive, got -1"), "{}", output);
+    assert!(output.contains("tests/range_step.ft:11:32: error: step of a range has to be positive, got 0"), "{}", output);
+}
+
+#[test]
+fn range_dynamic_step() {
+    let output = compile("range_dynamic_step.ft");
+    assert!(output.contains("step of a range has to be positive, got 0"), "{}", output);
+    assert!(output.contains("tests/range_dynamic_step.ft:15:1: Assertion stopped by a runtime error"), "{}", output);
+    // Caller does not carry on with the value of the stopped function
+    assert!(output.contains("tests/range_dynamic_step.ft:16:1: Assertion stopped by a runtime error"), "{}", output);
+}
+
+#[test]
+fn out_of_bounds() {
+    let output = compile("out_of_bounds.ft");
+    assert!(output.contains("index 3 out of bounds of a slice of 3 elements"), "{}", output);
+    assert!(output.contains("tests/out_of_bounds.ft:34:1: Assertion stopped by a runtime error"), "{}", output);
+    assert!(output.contains("slice 1..4 out of bounds of a slice of 3 elements"), "{}", output);
+    assert!(output.contains("tests/out_of_bounds.ft:35:1: Assertion stopped by a runtime error"), "{}", output);
+    assert!(output.contains("slice 1..0 out of bounds of a slice of 3 elements"), "{}", output);
+    assert!(output.contains("tests/out_of_bounds.ft:36:1: Assertion stopped by a runtime error"), "{}", output);
+    assert!(output.contains("index 5 out of bounds of an array"), "{}", output);
+    assert!(output.contains("tests/out_of_bounds.ft:37:1: Assertion stopped by a runtime error"), "{}", output);
+    assert!(output.contains("tests/out_of_bounds.ft:40:1: Assertion stopped by a runtime error"), "{}", output);
+    // Callers do not carry on with the values of the stopped functions
+    assert!(output.contains("tests/out_of_bounds.ft:38:1: Assertion stopped by a runtime error"), "{}", output);
+    assert!(output.contains("tests/out_of_bounds.ft:39:1: Assertion stopped by a runtime error"), "{}", output);
+}
+
+#[test]
+fn loop_control_outside_of_loop() {
+    let output = compile("loop_outside.ft");
+    assert!(output.contains("tests/loop_outside.ft:2:5: error: break outside of a loop\n"), "{}", output);
+    assert!(
+        output.contains("tests/loop_outside.ft:9:9: error: continue outside of a loop labeled 'outer"),
+        "{}",
+        output
+    );
+    assert!(output.contains("tests/loop_outside.ft:16:9: error: break outside of a loop labeled 'inner"), "{}", output);
+}
+
+#[test]
+fn match_misuse() {
+    let output = compile("match_misuse.ft");
+    assert!(output.contains("tests/match_misuse.ft:12:5: error: match is not exhaustive, missing Empty"), "{}", output);
+    assert!(
+        output.contains("tests/match_misuse.ft:19:5: error: pattern Op::Negate is not a variant of the matched enum"),
+        "{}",
+        output
+    );
+    assert!(
+        output.contains("tests/match_misuse.ft:26:5: error: pattern Shape::Rect binds 1 values, but the variant holds 2"),
+        "{}",
+        output
+    );
+    assert!(output.contains("tests/match_misuse.ft:33:5: error: only enums can be matched, got I32"), "{}", output);
+}
+
+#[test]
+fn literal_out_of_range() {
+    let output = compile("literal_range.ft");
+    assert!(output.contains("tests/literal_range.ft:2:18: error: 5000000000 does not fit in U32"), "{}", output);
+    assert!(output.contains("tests/literal_range.ft:7:12: error: 4294967295 does not fit in I32"), "{}", output);
+    assert!(output.contains("tests/literal_range.ft:11:18: error: -1 does not fit in U32"), "{}", output);
+}
+
+#[test]
+fn destructuring_misuse() {
+    let output = compile("destructuring_misuse.ft");
+    assert!(
+        output.contains("tests/destructuring_misuse.ft:6:5: error: pattern (a, b, c) does not match Tuple([I32, I32])"),
+        "{}",
+        output
+    );
+    assert!(output.contains("tests/destructuring_misuse.ft:11:5: error: pattern (a, b) does not match Integer"), "{}", output);
+    assert!(output.contains("tests/destructuring_misuse.ft:16:5: error: Integer is not iterable"), "{}", output);
+}
+
+#[test]
+fn non_bool_while_condition() {
+    let output = compile("non_bool_while.ft");
+    assert!(
+        output.contains("tests/non_bool_while.ft:3:11: error: only boolean expressions are allowed in while conditions, got I32"),
+        "{}",
+        output
+    );
+}
+
+#[test]
+fn globals_misuse() {
+    let output = compile("globals_misuse.ft");
+    assert!(
+        output.contains("tests/globals_misuse.ft:5:5: error: cannot assign to LIMIT, it is not a mutable static"),
+        "{}",
+        output
+    );
+    assert!(
+        output.contains("tests/globals_misuse.ft:10:5: error: cannot assign to N, it is not a mutable static"),
+        "{}",
+        output
+    );
+    assert!(output.contains("tests/globals_misuse.ft:15:5: error: length of array M is not a constant"), "{}", output);
+}